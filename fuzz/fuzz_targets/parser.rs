@@ -0,0 +1,22 @@
+//! Feeds arbitrary lines to the parser, which must never panic on them.
+//! Run with `cargo fuzz run parser` from the repository root.
+#![no_main]
+
+use a_shell::parser::{quote, CommandParser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|line: &str| {
+    let parser = CommandParser::new();
+    let parsed = parser.parse(line);
+    parser.is_incomplete(line);
+    parser.join_lines(&line.lines().map(str::to_string).collect::<Vec<_>>());
+
+    // Quoting what was parsed must give it back unchanged.
+    if !parsed.command.is_empty() || !parsed.args.is_empty() {
+        let words = std::iter::once(&parsed.command)
+            .chain(&parsed.args)
+            .map(|f| quote(f))
+            .collect::<Vec<_>>();
+        assert_eq!(parser.parse(&words.join(" ")), parsed);
+    }
+});
@@ -1,6 +1,7 @@
+use std::io::{self, Write};
 use std::{env, fs};
 
-pub fn print_about() {
+pub fn print_about(stdout: &mut dyn Write) -> io::Result<()> {
     let ascii_art = r#"⠀⠀⠀⠀⠀⣀⣠⣤⣤⣤⣤⣄⣀⠀⠀⠀⠀⠀
 ⠀⠀⢀⣴⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⣦⡀⠀⠀
 ⠀⣴⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⠟⢿⣿⣷⡀⠀
@@ -14,12 +15,7 @@ pub fn print_about() {
 
     // Fetch system information
     let username = env::var("USER").unwrap_or_else(|_| "Unknown".to_string());
-    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| {
-        fs::read_to_string("/etc/hostname")
-            .unwrap_or_else(|_| "Unknown".to_string())
-            .trim()
-            .to_string()
-    });
+    let hostname = get_hostname();
     let os = fs::read_to_string("/etc/os-release")
         .ok()
         .and_then(|content| {
@@ -102,22 +98,33 @@ pub fn print_about() {
     let max_art_width = art_lines.iter().map(|line| line.len()).max().unwrap_or(0) + 5;
 
     for (i, art_line) in art_lines.iter().enumerate() {
-        print!("{}", art_line);
+        write!(stdout, "{}", art_line)?;
         if i < info_lines.len() {
-            print!(
+            write!(
+                stdout,
                 "{:width$}{}",
                 "",
                 info_lines[i],
                 width = max_art_width - art_line.len()
-            );
+            )?;
         }
-        println!();
+        writeln!(stdout)?;
     }
 
     // Print remaining info lines if any
     if art_lines.len() < info_lines.len() {
         for line in info_lines.iter().skip(art_lines.len()) {
-            println!("{:width$}{}", "", line, width = max_art_width);
+            writeln!(stdout, "{:width$}{}", "", line, width = max_art_width)?;
         }
     }
+    Ok(())
+}
+
+pub fn get_hostname() -> String {
+    env::var("HOSTNAME").unwrap_or_else(|_| {
+        fs::read_to_string("/etc/hostname")
+            .unwrap_or_else(|_| "Unknown".to_string())
+            .trim()
+            .to_string()
+    })
 }
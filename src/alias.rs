@@ -0,0 +1,66 @@
+use std::collections::{BTreeMap, HashSet};
+
+/// Aliases defined with the `alias` builtin, expanded in the command
+/// position of every simple command before it runs.
+#[derive(Default)]
+pub struct Aliases {
+    aliases: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.aliases.clear();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.aliases.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.aliases.iter()
+    }
+
+    /// Expands the first word of every command of `line`. The result may
+    /// contain new pipes and `&&` lists, which are split later like typed
+    /// ones.
+    pub fn expand(&self, line: &str) -> String {
+        line.split(" | ")
+            .map(|group| {
+                group
+                    .split(" && ")
+                    .map(|command| self.expand_command(command))
+                    .collect::<Vec<_>>()
+                    .join(" && ")
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    fn expand_command(&self, command: &str) -> String {
+        let mut command = command.to_string();
+        // An alias is never expanded inside its own expansion, which lets
+        // `alias ls='ls --color'` work without looping forever.
+        let mut expanded = HashSet::new();
+        loop {
+            let trimmed = command.trim_start();
+            let indent = command.len() - trimmed.len();
+            let name = trimmed.split(' ').next().unwrap_or_default();
+            let Some(value) = self.aliases.get(name) else {
+                break;
+            };
+            if !expanded.insert(name.to_string()) {
+                break;
+            }
+            command = format!("{}{}{}", &command[..indent], value, &trimmed[name.len()..]);
+        }
+        command
+    }
+}
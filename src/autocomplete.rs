@@ -1,53 +1,151 @@
-use crossterm::terminal;
+use crate::{
+    bridge::get_bridged_candidates,
+    config::CompletionConfig,
+    glob,
+    parser::{self, CommandParser},
+    plugin::Plugins,
+    worker,
+};
 
-use crate::parser::CommandParser;
-
-use std::error::Error;
+use crate::error::AshError;
 use std::fs::{self};
-use std::io::{self};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
 
 pub struct Suggestion {
-    file_name: String,
-    is_dir: bool,
+    pub file_name: String,
+    pub is_dir: bool,
+    pub description: Option<String>,
 }
 
-pub struct AutoComplete {}
+pub struct Completion {
+    pub value: String,
+    /// Candidates to list below the prompt when none could be inserted.
+    pub listing: Vec<Suggestion>,
+}
+
+pub struct AutoComplete {
+    /// Matches candidates regardless of case, from the `nocasecomplete`
+    /// option.
+    pub ignore_case: bool,
+    config: CompletionConfig,
+}
 
 impl AutoComplete {
-    pub fn new() -> Self {
-        return AutoComplete {};
+    pub fn new(config: CompletionConfig) -> Self {
+        AutoComplete {
+            ignore_case: false,
+            config,
+        }
     }
 
     pub fn autocomplete(
         &self,
         command: &str,
         parser: &CommandParser,
-    ) -> Result<String, Box<dyn Error>> {
+        history: &[String],
+        executables: &[String],
+        plugins: &Plugins,
+    ) -> Result<Completion, AshError> {
+        if let Some(expanded) = self.expand_glob(command) {
+            return Ok(Completion {
+                value: expanded,
+                listing: vec![],
+            });
+        }
+
         let mut new_value = String::from(command);
+        let mut listing = vec![];
         let parsed_command = parser.parse(command);
-        let searched_file = parsed_command.paths.last().map_or("", |s| s.as_str());
-        let in_path =
-            parsed_command.paths[..parsed_command.paths.len().saturating_sub(1)].join("/");
+        let completing_new_word = command.trim().is_empty() || command.ends_with(' ');
+        // A first word without a `/` names a command rather than a file.
+        let completing_command = !completing_new_word && !command.trim_start().contains([' ', '/']);
+        // A generator's candidates are matched against the whole word, as
+        // they aren't paths: `feature/` is part of a branch name.
+        let generator = self
+            .get_spec(command, completing_new_word, parser)
+            .filter(|f| f.get("command").is_some());
+        let searched_file = if completing_new_word {
+            ""
+        } else if completing_command || generator.is_some() {
+            command.split_whitespace().next_back().unwrap_or("")
+        } else {
+            parsed_command.paths.last().map_or("", |s| s.as_str())
+        };
+        let in_path = if completing_new_word {
+            ".".to_string()
+        } else {
+            parsed_command.paths[..parsed_command.paths.len().saturating_sub(1)].join("/")
+        };
 
-        let mut entries = fs::read_dir(&in_path)?
-            .map(|res| res.map(|e| e.path()))
-            .collect::<Result<Vec<_>, io::Error>>()?;
+        // A directory that doesn't exist just has no candidates.
+        let mut entries = match fs::read_dir(&in_path) {
+            Ok(dir) if generator.is_none() => dir.flatten().map(|e| e.path()).collect(),
+            _ => vec![],
+        };
         entries.sort();
 
         if parsed_command.command == "cd" {
-            entries = entries.into_iter().filter(|f| f.is_dir()).collect();
+            entries.retain(|f| f.is_dir());
         }
 
-        let terminal_width = terminal::size()?.0 as usize;
-
-        let mut matching_file_names: Vec<Suggestion> = vec![];
+        let mut matching_file_names: Vec<Suggestion> = match generator {
+            Some(spec) => self.get_generated_candidates(spec, searched_file),
+            None => match self.get_plugin_candidates(
+                command,
+                completing_new_word,
+                searched_file,
+                plugins,
+            ) {
+                Some(candidates) => candidates,
+                None if self.config.bash_bridge => self.get_bridged_candidates(
+                    command,
+                    completing_new_word,
+                    searched_file,
+                    executables,
+                ),
+                None => vec![],
+            },
+        };
+        if completing_command {
+            let candidates = executables.iter().map(|f| (f.clone(), None));
+            for candidate in self.to_suggestions(candidates, searched_file) {
+                if !matching_file_names
+                    .iter()
+                    .any(|f| f.file_name == candidate.file_name)
+                {
+                    matching_file_names.push(candidate);
+                }
+            }
+        }
+        for argument in self.get_history_arguments(&parsed_command.command, history, searched_file)
+        {
+            if !matching_file_names
+                .iter()
+                .any(|f| f.file_name == argument.file_name)
+            {
+                matching_file_names.push(argument);
+            }
+        }
 
-        for (_i, entry) in entries.iter().enumerate() {
+        for entry in entries.iter() {
             let file_name = entry.file_name().unwrap().to_string_lossy().to_string();
-            if searched_file.len() == 0 || file_name.starts_with(&searched_file) {
+            if !self.config.show_hidden
+                && file_name.starts_with('.')
+                && !searched_file.starts_with('.')
+            {
+                continue;
+            }
+            if (searched_file.is_empty() || self.has_prefix(&file_name, searched_file))
+                && !matching_file_names.iter().any(|f| f.file_name == file_name)
+            {
                 matching_file_names.push(Suggestion {
                     file_name: file_name.clone(),
                     is_dir: entry.is_dir(),
+                    description: None,
                 });
             }
         }
@@ -56,32 +154,15 @@ impl AutoComplete {
             let longest_match = self.get_longest_match(&matching_file_names, searched_file);
 
             if longest_match.len() > searched_file.len() {
-                new_value = command.replace(&searched_file, &format!("{}", longest_match));
+                new_value = self.replace_word(command, searched_file, &longest_match);
             } else {
-                let max_width = entries
-                    .iter()
-                    .map(|entry| entry.file_name().unwrap().to_string_lossy().len())
-                    .max()
-                    .unwrap_or(0);
-                let columns = terminal_width / (max_width + 2); // Add 4 for padding
-                println!("");
-
-                for (i, suggestion) in matching_file_names.iter().enumerate() {
-                    print!("{:<width$}", suggestion.file_name, width = max_width);
-                    if (i + 1) % columns == 0 {
-                        println!();
-                    }
-                }
-
-                // Ensure we end with a new line
-                if entries.len() % columns != 0 {
-                    println!();
-                }
+                listing = matching_file_names;
             }
         } else if matching_file_names.len() == 1 {
             let matched = matching_file_names.first().unwrap();
-            new_value = command.replace(
-                &searched_file,
+            new_value = self.replace_word(
+                command,
+                searched_file,
                 &format!(
                     "{}{}",
                     matched.file_name,
@@ -89,29 +170,272 @@ impl AutoComplete {
                 ),
             );
         }
-        Ok(new_value)
+        Ok(Completion {
+            value: new_value,
+            listing,
+        })
     }
 
-    fn get_longest_match(&self, entries: &Vec<Suggestion>, search: &str) -> String {
-        let mut longest_match = String::from(search);
-        let mut len = longest_match.len();
-        let first_entry = entries.first().unwrap().to_owned();
-        loop {
-            len += 1;
-            let mut success = true;
-            let (trying_match, _) = first_entry.file_name.split_at(len);
-            for entry in entries {
-                if !entry.file_name.starts_with(trying_match) {
-                    success = false;
-                    break;
+    /// Expands a trailing word containing wildcards into the files it
+    /// matches, leaving the input untouched when nothing matches.
+    fn expand_glob(&self, command: &str) -> Option<String> {
+        let (head, word) = match command.rfind(' ') {
+            Some(i) => command.split_at(i + 1),
+            None => ("", command),
+        };
+        if !glob::is_glob(word) {
+            return None;
+        }
+        let matches = glob::expand(word);
+        if matches.is_empty() {
+            return Some(command.to_string());
+        }
+        let expanded = matches
+            .iter()
+            .map(|f| match f.strip_prefix("~/") {
+                Some(rest) => format!("~/{}", parser::quote(rest)),
+                None => parser::quote(f),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Some(format!("{}{} ", head, expanded))
+    }
+
+    /// Arguments previously passed to `command`, most recent first, so that
+    /// e.g. `ssh <Tab>` offers the hosts that were actually used before.
+    fn get_history_arguments(
+        &self,
+        command: &str,
+        history: &[String],
+        searched: &str,
+    ) -> Vec<Suggestion> {
+        let mut arguments: Vec<Suggestion> = vec![];
+        if command.is_empty() {
+            return arguments;
+        }
+        for line in history {
+            let mut words = line.split_whitespace();
+            if words.next() != Some(command) {
+                continue;
+            }
+            for word in words {
+                if word.starts_with('-')
+                    || word.contains('/')
+                    || !self.has_prefix(word, searched)
+                    || arguments.iter().any(|f| f.file_name == word)
+                {
+                    continue;
                 }
+                if command == "cd" && !Path::new(word).is_dir() {
+                    continue;
+                }
+                arguments.push(Suggestion {
+                    file_name: word.to_string(),
+                    is_dir: false,
+                    description: None,
+                });
             }
-            if success {
-                longest_match = String::from(trying_match);
-            } else {
-                break;
+        }
+        arguments
+    }
+
+    /// Looks up the completion spec for the words before the one being
+    /// completed, preferring `git_checkout` over `git`.
+    fn get_spec<'a>(
+        &self,
+        command: &str,
+        completing_new_word: bool,
+        parser: &'a CommandParser,
+    ) -> Option<&'a toml::Value> {
+        let mut words = command.split_whitespace().collect::<Vec<_>>();
+        if !completing_new_word {
+            words.pop();
+        }
+        let subcommand = words.iter().take(2).copied().collect::<Vec<_>>().join("_");
+        parser
+            .get_metadata(&subcommand)
+            .or_else(|| parser.get_metadata(words.first()?))
+    }
+
+    /// Runs the spec's `command` generator and turns each line of its output
+    /// into a candidate, with an optional tab-separated description.
+    fn get_generated_candidates(&self, spec: &toml::Value, searched: &str) -> Vec<Suggestion> {
+        let Some(generator) = spec.get("command").and_then(|f| f.as_str()) else {
+            return vec![];
+        };
+        let timeout = spec
+            .get("timeout")
+            .and_then(|f| f.as_integer())
+            .map_or(self.config.generator_timeout, |ms| ms.max(0) as u64);
+        let timeout = Duration::from_millis(timeout);
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(generator);
+        let Ok(output) = run_with_timeout(command, timeout) else {
+            return vec![];
+        };
+
+        let lines = output.lines().map(|line| match line.split_once('\t') {
+            Some((name, description)) => (
+                name.trim().to_string(),
+                Some(description.trim().to_string()),
+            ),
+            None => (line.trim().to_string(), None),
+        });
+        self.to_suggestions(lines, searched)
+    }
+
+    /// Candidates from the plugin completing the command, if there is one.
+    fn get_plugin_candidates(
+        &self,
+        command: &str,
+        completing_new_word: bool,
+        searched: &str,
+        plugins: &Plugins,
+    ) -> Option<Vec<Suggestion>> {
+        let mut words = command.split_whitespace().collect::<Vec<_>>();
+        if completing_new_word {
+            words.push("");
+        }
+        let candidates = plugins.complete(&words)?;
+        Some(self.to_suggestions(candidates.into_iter(), searched))
+    }
+
+    fn get_bridged_candidates(
+        &self,
+        command: &str,
+        completing_new_word: bool,
+        searched: &str,
+        executables: &[String],
+    ) -> Vec<Suggestion> {
+        let mut words = command.split_whitespace().collect::<Vec<_>>();
+        if completing_new_word {
+            words.push("");
+        }
+        if words.last().is_some_and(|word| word.contains('/')) {
+            return vec![];
+        }
+        self.to_suggestions(
+            get_bridged_candidates(&words, executables).into_iter(),
+            searched,
+        )
+    }
+
+    fn to_suggestions(
+        &self,
+        lines: impl Iterator<Item = (String, Option<String>)>,
+        searched: &str,
+    ) -> Vec<Suggestion> {
+        let mut candidates: Vec<Suggestion> = vec![];
+        for (name, description) in lines {
+            if name.is_empty()
+                || !self.has_prefix(&name, searched)
+                || candidates.iter().any(|f| f.file_name == name)
+            {
+                continue;
             }
+            candidates.push(Suggestion {
+                file_name: name,
+                is_dir: false,
+                description,
+            });
+        }
+        candidates
+    }
+
+    fn has_prefix(&self, name: &str, prefix: &str) -> bool {
+        if self.ignore_case {
+            name.to_lowercase().starts_with(&prefix.to_lowercase())
+        } else {
+            name.starts_with(prefix)
+        }
+    }
+
+    fn replace_word(&self, command: &str, searched: &str, replacement: &str) -> String {
+        match command.strip_suffix(searched) {
+            Some(rest) => format!("{}{}", rest, replacement),
+            None => command.replacen(searched, replacement, 1),
+        }
+    }
+
+    fn get_longest_match(&self, entries: &[Suggestion], search: &str) -> String {
+        let mut longest_match = entries.first().unwrap().file_name.clone();
+        for entry in entries {
+            while !entry.file_name.starts_with(&longest_match) {
+                longest_match.pop();
+            }
+        }
+        if longest_match.len() < search.len() {
+            return String::from(search);
         }
         longest_match
     }
 }
+
+/// Writes the paths under `root`, relative to it and one per line, with a
+/// `/` after directories, for a fuzzy finder to pick from. Dotfiles are
+/// left out unless `show_hidden` is on, and `.git` always is. Stops at the
+/// first failed write.
+pub fn walk_files(root: &Path, show_hidden: bool, out: &mut dyn Write) -> io::Result<()> {
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries = entries.filter_map(|f| f.ok()).collect::<Vec<_>>();
+        entries.sort_by_key(|f| f.file_name());
+        let mut subdirs = vec![];
+        for entry in entries {
+            let name = entry.file_name();
+            if name == ".git" || (!show_hidden && name.to_string_lossy().starts_with('.')) {
+                continue;
+            }
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy();
+            // Symlinks to directories aren't followed, so loops can't be.
+            if entry.file_type().is_ok_and(|f| f.is_dir()) {
+                writeln!(out, "{}/", relative)?;
+                subdirs.push(path);
+            } else {
+                writeln!(out, "{}", relative)?;
+            }
+        }
+        // Popped in order, so siblings come out sorted.
+        dirs.extend(subdirs.into_iter().rev());
+    }
+    Ok(())
+}
+
+/// Runs an external helper (completion source, git query), giving up and
+/// killing it once `timeout` has passed so it can't freeze the prompt.
+pub fn run_with_timeout(mut command: Command, timeout: Duration) -> io::Result<String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdout = child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+
+    // Read on a separate thread so a generator producing more than a pipe
+    // buffer of output can't deadlock against the timeout.
+    let (sender, receiver) = mpsc::channel();
+    worker::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        let _ = sender.send(output);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(output) => {
+            child.wait()?;
+            Ok(output)
+        }
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "completion source timed out",
+            ))
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::autocomplete::run_with_timeout;
+use crate::hash;
+
+const BRIDGE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+const BASH_COMPLETION_SCRIPTS: [&str; 2] = [
+    "/usr/share/bash-completion/bash_completion",
+    "/etc/bash_completion",
+];
+
+// Loads the command's bash-completion function, fakes the COMP_* state bash
+// would set up, and prints whatever ends up in COMPREPLY.
+const BASH_BRIDGE: &str = r#"
+source "$1" >/dev/null 2>&1
+shift
+COMP_WORDS=("$@")
+COMP_CWORD=$((${#COMP_WORDS[@]} - 1))
+COMP_LINE="${COMP_WORDS[*]}"
+COMP_POINT=${#COMP_LINE}
+declare -F _completion_loader >/dev/null && _completion_loader "$1" >/dev/null 2>&1
+spec=$(complete -p "$1" 2>/dev/null)
+func=$(sed -n 's/.*-F \([^ ]*\).*/\1/p' <<< "$spec")
+[ -z "$func" ] && exit 0
+"$func" "$1" "${COMP_WORDS[COMP_CWORD]}" "${COMP_WORDS[COMP_CWORD-1]}" >/dev/null 2>&1
+printf '%s\n' "${COMPREPLY[@]}"
+"#;
+
+#[derive(Deserialize)]
+struct CarapaceExport {
+    #[serde(alias = "Values", default)]
+    values: Vec<CarapaceValue>,
+}
+
+#[derive(Deserialize)]
+struct CarapaceValue {
+    #[serde(alias = "Value")]
+    value: String,
+    #[serde(alias = "Description", default)]
+    description: String,
+}
+
+/// Asks an existing completion ecosystem for candidates for `words`, where the
+/// last word is the one being completed. carapace is preferred because its
+/// JSON export carries descriptions; bash-completion is the fallback.
+pub fn get_bridged_candidates(
+    words: &[&str],
+    executables: &[String],
+) -> Vec<(String, Option<String>)> {
+    if words.len() < 2 {
+        return vec![];
+    }
+    if is_on_path("carapace", executables) {
+        return get_carapace_candidates(words);
+    }
+    if is_on_path("bash", executables) {
+        if let Some(script) = BASH_COMPLETION_SCRIPTS
+            .iter()
+            .find(|script| Path::new(script).exists())
+        {
+            return get_bash_candidates(script, words);
+        }
+    }
+    vec![]
+}
+
+fn get_carapace_candidates(words: &[&str]) -> Vec<(String, Option<String>)> {
+    let mut command = Command::new("carapace");
+    command.arg(words[0]).arg("export").args(words);
+    let Ok(output) = run_with_timeout(command, BRIDGE_TIMEOUT) else {
+        return vec![];
+    };
+    let Ok(export) = serde_json::from_str::<CarapaceExport>(&output) else {
+        return vec![];
+    };
+    export
+        .values
+        .into_iter()
+        .map(|value| {
+            let description = Some(value.description).filter(|f| !f.is_empty());
+            (value.value, description)
+        })
+        .collect()
+}
+
+fn get_bash_candidates(script: &str, words: &[&str]) -> Vec<(String, Option<String>)> {
+    let mut command = Command::new("bash");
+    command
+        .arg("-c")
+        .arg(BASH_BRIDGE)
+        .arg("ash")
+        .arg(script)
+        .args(words);
+    let Ok(output) = run_with_timeout(command, BRIDGE_TIMEOUT) else {
+        return vec![];
+    };
+    output
+        .lines()
+        .map(|line| (line.trim_end().to_string(), None))
+        .collect()
+}
+
+/// Whether `binary` is on PATH, from the names the shell indexed there,
+/// sorted; until the first scan finishes that list is empty.
+fn is_on_path(binary: &str, executables: &[String]) -> bool {
+    if executables.is_empty() {
+        return hash::find_on_path(binary).is_some();
+    }
+    executables
+        .binary_search_by(|f| f.as_str().cmp(binary))
+        .is_ok()
+}
@@ -0,0 +1,170 @@
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// The line being edited and the cursor in it. The cursor only ever sits
+/// between graphemes, so a character built from several code points, like
+/// an accented letter or a flag, is moved over and deleted as one, and
+/// positions on screen are counted in terminal columns rather than bytes.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct EditBuffer {
+    text: String,
+    /// Byte offset of the cursor in `text`.
+    cursor: usize,
+}
+
+impl EditBuffer {
+    /// A buffer holding `text`, with the cursor at its end.
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let cursor = text.len();
+        EditBuffer { text, cursor }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Byte offset of the cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.cursor == self.text.len()
+    }
+
+    /// Replaces the text, with the cursor at its end.
+    pub fn set(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    /// Takes the text out, leaving the buffer empty.
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        // A combining mark joins the grapheme before it.
+        self.cursor = self.next_boundary(self.previous_boundary(self.cursor));
+    }
+
+    pub fn insert_str(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+        self.cursor = self.next_boundary(self.previous_boundary(self.cursor));
+    }
+
+    /// Deletes the grapheme before the cursor, returning whether there was
+    /// one.
+    pub fn delete_backward(&mut self) -> bool {
+        let start = self.previous_boundary(self.cursor);
+        self.delete(start..self.cursor)
+    }
+
+    /// Deletes the grapheme after the cursor.
+    pub fn delete_forward(&mut self) -> bool {
+        let end = self.next_boundary(self.cursor);
+        self.delete(self.cursor..end)
+    }
+
+    /// Deletes from the start of the word before the cursor to the cursor.
+    pub fn delete_word_backward(&mut self) -> bool {
+        let start = self.word_start();
+        self.delete(start..self.cursor)
+    }
+
+    fn delete(&mut self, range: Range<usize>) -> bool {
+        if range.is_empty() {
+            return false;
+        }
+        self.cursor = range.start;
+        self.text.replace_range(range, "");
+        true
+    }
+
+    pub fn move_left(&mut self) -> bool {
+        self.move_to(self.previous_boundary(self.cursor))
+    }
+
+    pub fn move_right(&mut self) -> bool {
+        self.move_to(self.next_boundary(self.cursor))
+    }
+
+    /// Moves to the start of the word before the cursor. Words are what the
+    /// shell splits a line into: runs of anything but whitespace.
+    pub fn move_word_left(&mut self) -> bool {
+        self.move_to(self.word_start())
+    }
+
+    /// Moves past the end of the word after the cursor.
+    pub fn move_word_right(&mut self) -> bool {
+        let rest = &self.text[self.cursor..];
+        let word = rest.len() - rest.trim_start().len();
+        let end = rest[word..]
+            .find(char::is_whitespace)
+            .map_or(self.text.len(), |f| self.cursor + word + f);
+        self.move_to(end)
+    }
+
+    pub fn move_to_start(&mut self) -> bool {
+        self.move_to(0)
+    }
+
+    pub fn move_to_end(&mut self) -> bool {
+        self.move_to(self.text.len())
+    }
+
+    fn move_to(&mut self, cursor: usize) -> bool {
+        let moved = cursor != self.cursor;
+        self.cursor = cursor;
+        moved
+    }
+
+    /// Columns the text takes on a terminal.
+    pub fn width(&self) -> usize {
+        self.text.graphemes(true).map(width).sum()
+    }
+
+    /// Columns of the text before the cursor.
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.cursor].graphemes(true).map(width).sum()
+    }
+
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before.rfind(char::is_whitespace).map_or(0, |f| {
+            f + before[f..].chars().next().map_or(1, char::len_utf8)
+        })
+    }
+
+    /// Where the grapheme before `offset` starts.
+    fn previous_boundary(&self, offset: usize) -> usize {
+        self.text[..offset]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// Where the grapheme at `offset` ends.
+    fn next_boundary(&self, offset: usize) -> usize {
+        self.text[offset..]
+            .graphemes(true)
+            .next()
+            .map_or(offset, |f| offset + f.len())
+    }
+}
+
+/// Columns a grapheme takes on a terminal: two for wide ones like CJK and
+/// most emoji, none for those made only of combining or control marks.
+pub fn width(grapheme: &str) -> usize {
+    grapheme.width()
+}
@@ -0,0 +1,694 @@
+use crate::error::AshError;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use crate::keys::KeyBindings;
+use crate::shell::Shell;
+
+/// A command that runs inside the shell process. It gets the stdin and
+/// stdout of its place in the pipeline as handles rather than using the
+/// terminal, so it can be piped like an external command, and returns its
+/// exit status.
+pub trait Builtin {
+    fn run(
+        &self,
+        shell: &mut Shell,
+        args: &[String],
+        stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError>;
+}
+
+impl<F> Builtin for F
+where
+    F: Fn(&mut Shell, &[String], &mut dyn Read, &mut dyn Write) -> Result<i32, AshError>,
+{
+    fn run(
+        &self,
+        shell: &mut Shell,
+        args: &[String],
+        stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        self(shell, args, stdin, stdout)
+    }
+}
+
+/// Usage and a one-line description of every builtin, shown by `help`.
+pub const HELP: &[(&str, &str, &str)] = &[
+    (":", ":", "Do nothing and succeed"),
+    (".", ". FILE", "Run the commands of FILE in this shell"),
+    ("[", "[ EXPRESSION ]", "Evaluate a conditional expression"),
+    ("about", "about", "Show information about ash"),
+    ("alias", "alias [NAME[=VALUE]...]", "Define or list aliases"),
+    (
+        "bg",
+        "bg [%JOB]",
+        "Continue a stopped job in the background",
+    ),
+    (
+        "builtin",
+        "builtin NAME [ARG...]",
+        "Run a builtin, ignoring aliases",
+    ),
+    ("cd", "cd [DIR | -]", "Change the working directory"),
+    (
+        "command",
+        "command [-vV] NAME [ARG...]",
+        "Run a command, ignoring aliases",
+    ),
+    (
+        "copy",
+        "copy",
+        "Put stdin, or the last command line, on the clipboard",
+    ),
+    ("dirs", "dirs [-c | -p | -v]", "List the directory stack"),
+    (
+        "disown",
+        "disown [-ah] [%JOB...]",
+        "Remove jobs from the job table",
+    ),
+    ("echo", "echo [-neE] [ARG...]", "Print the arguments"),
+    (
+        "exit",
+        "exit [N]",
+        "Leave the shell with status N, or that of the last command",
+    ),
+    (
+        "export",
+        "export [NAME=VALUE...]",
+        "Set environment variables",
+    ),
+    ("false", "false", "Do nothing and fail"),
+    ("fg", "fg [%JOB]", "Run a job in the foreground"),
+    (
+        "hash",
+        "hash [-r] [-d NAME...] [NAME...]",
+        "Show or change the command path cache",
+    ),
+    ("help", "help [NAME]", "Show help for builtins"),
+    (
+        "j",
+        "j [QUERY...]",
+        "Jump to a frequently visited directory",
+    ),
+    ("jobs", "jobs [-l | -p]", "List background jobs"),
+    (
+        "kill",
+        "kill [-s SIGNAL | -SIGNAL] PID | %JOB...",
+        "Send a signal to processes or jobs",
+    ),
+    (
+        "popd",
+        "popd [+N | -N]",
+        "Remove a directory from the stack",
+    ),
+    (
+        "printf",
+        "printf FORMAT [ARG...]",
+        "Print formatted arguments",
+    ),
+    (
+        "pushd",
+        "pushd [DIR | +N | -N]",
+        "Add a directory to the stack",
+    ),
+    ("pwd", "pwd [-L | -P]", "Print the working directory"),
+    (
+        "rehash",
+        "rehash",
+        "Forget cached command paths and rescan PATH",
+    ),
+    ("reload", "reload", "Apply changes to the config file"),
+    (
+        "reload-shell",
+        "reload-shell",
+        "Restart ash in place, keeping the cwd",
+    ),
+    (
+        "retry-sudo",
+        "retry-sudo",
+        "Run the previous command again with sudo",
+    ),
+    ("return", "return [N]", "Stop a sourced file with status N"),
+    (
+        "set",
+        "set [-o | +o] [NAME...]",
+        "Turn shell options on or off",
+    ),
+    (
+        "shopt",
+        "shopt [-psu] [NAME...]",
+        "Set, unset, or list shell options",
+    ),
+    (
+        "source",
+        "source FILE",
+        "Run the commands of FILE in this shell",
+    ),
+    (
+        "test",
+        "test EXPRESSION",
+        "Evaluate a conditional expression",
+    ),
+    (
+        "time",
+        "time [-v] PIPELINE",
+        "Report how long a pipeline takes",
+    ),
+    (
+        "trap",
+        "trap [-lp] [COMMAND CONDITION...]",
+        "Run a command on signals or exit",
+    ),
+    ("true", "true", "Do nothing and succeed"),
+    ("unalias", "unalias [-a] NAME...", "Remove aliases"),
+    ("unset", "unset NAME...", "Remove environment variables"),
+    (
+        "z",
+        "z [QUERY...]",
+        "Jump to a frequently visited directory",
+    ),
+];
+
+/// Prefixes a command line can start with, shown by `help`.
+pub const LINE_SYNTAX: &[(&str, &str)] = &[(
+    "--timeout DURATION LINE",
+    "Kill the line's foreground pipeline once it runs too long; \
+     `--timeout=DURATION` works too",
+)];
+
+/// Output of `help`: every builtin, plugin command, and key binding, or
+/// the usage of the named builtins.
+pub fn help(
+    args: &[String],
+    plugins: &[(&str, &str)],
+    keys: &KeyBindings,
+) -> Result<String, AshError> {
+    let mut output = String::new();
+    if args.is_empty() {
+        output.push_str("Builtins:\n");
+        for (name, _, description) in HELP {
+            output.push_str(&format!("  {:<10}{}\n", name, description));
+        }
+        if !plugins.is_empty() {
+            output.push_str("\nPlugin commands:\n");
+            for (name, description) in plugins {
+                output.push_str(&format!("  {:<10}{}\n", name, description));
+            }
+        }
+        output.push_str("\nLine syntax:\n");
+        for (syntax, description) in LINE_SYNTAX {
+            output.push_str(&format!("  {}\n      {}\n", syntax, description));
+        }
+        output.push_str("\nKey bindings:\n");
+        for (key, description) in keys.describe() {
+            output.push_str(&format!("  {:<10}{}\n", key, description));
+        }
+        let unbound = keys.unbound_actions();
+        if !unbound.is_empty() {
+            output.push_str(&format!(
+                "\nActions without a key, to bind in [keys]: {}\n",
+                unbound.join(", ")
+            ));
+        }
+        output.push_str("\nRun `help NAME` for the usage of a builtin.\n");
+        return Ok(output);
+    }
+    for arg in args {
+        let (_, usage, description) = HELP
+            .iter()
+            .find(|f| f.0 == arg)
+            .ok_or_else(|| format!("help: no help topics match `{}`", arg))?;
+        output.push_str(&format!("{}\n    {}.\n", usage, description));
+    }
+    Ok(output)
+}
+
+/// Output of `echo`: `-n` drops the trailing newline, `-e` interprets
+/// backslash escapes and `-E` turns them back off.
+pub fn echo(args: &[String]) -> Vec<u8> {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut words = args;
+    while let Some(flags) = words.first().and_then(|f| f.strip_prefix('-')) {
+        if flags.is_empty() || !flags.chars().all(|c| matches!(c, 'n' | 'e' | 'E')) {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        words = &words[1..];
+    }
+
+    let text = words.join(" ");
+    let mut output = if escapes {
+        let (text, stop) = unescape(&text);
+        if stop {
+            return text;
+        }
+        text
+    } else {
+        text.into_bytes()
+    };
+    if newline {
+        output.push(b'\n');
+    }
+    output
+}
+
+/// Output of `printf FORMAT [ARGS...]`. The format is reused until every
+/// argument is consumed; missing arguments count as empty or zero. It's
+/// bytes rather than text, as escapes like `\377` make any byte.
+pub fn printf(args: &[String]) -> Result<Vec<u8>, AshError> {
+    let Some((format, mut args)) = args.split_first() else {
+        return Err("printf: usage: printf format [arguments]".into());
+    };
+    let mut output = vec![];
+    loop {
+        let (formatted, consumed, stop) = format_once(format, args)?;
+        output.extend(formatted);
+        args = &args[consumed.min(args.len())..];
+        if stop || consumed == 0 || args.is_empty() {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+fn format_once(format: &str, args: &[String]) -> Result<(Vec<u8>, usize, bool), AshError> {
+    let mut output = vec![];
+    let mut consumed = 0;
+    let mut next_arg = || {
+        let arg = args.get(consumed).cloned().unwrap_or_default();
+        consumed += 1;
+        arg
+    };
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut escape = String::from('\\');
+                if let Some(next) = chars.next() {
+                    escape.push(next);
+                    if next == 'x' || next == '0' {
+                        while escape.len() < 5
+                            && chars.peek().is_some_and(|f| f.is_ascii_hexdigit())
+                        {
+                            escape.push(chars.next().unwrap());
+                        }
+                    }
+                }
+                let (text, stop) = unescape(&escape);
+                output.extend(text);
+                if stop {
+                    return Ok((output, consumed, true));
+                }
+            }
+            '%' => {
+                let mut spec = String::new();
+                while let Some(&f) = chars.peek() {
+                    if !matches!(f, '-' | '+' | ' ' | '#' | '0'..='9' | '.') {
+                        break;
+                    }
+                    spec.push(f);
+                    chars.next();
+                }
+                let Some(conversion) = chars.next() else {
+                    return Err("printf: missing format character".into());
+                };
+                let text = match conversion {
+                    '%' => "%".to_string(),
+                    's' => next_arg(),
+                    'b' => {
+                        let (text, stop) = unescape(&next_arg());
+                        if stop {
+                            output.extend(text);
+                            return Ok((output, consumed, true));
+                        }
+                        output.extend(pad(&spec, conversion, text));
+                        continue;
+                    }
+                    'c' => next_arg()
+                        .chars()
+                        .next()
+                        .map(String::from)
+                        .unwrap_or_default(),
+                    'd' | 'i' => parse_number(&next_arg())?.to_string(),
+                    'x' => format!("{:x}", parse_number(&next_arg())?),
+                    'X' => format!("{:X}", parse_number(&next_arg())?),
+                    'o' => format!("{:o}", parse_number(&next_arg())?),
+                    'f' | 'e' | 'g' => {
+                        let arg = next_arg();
+                        let number = if arg.is_empty() {
+                            0.0
+                        } else {
+                            arg.parse::<f64>()
+                                .map_err(|_| format!("printf: {}: invalid number", arg))?
+                        };
+                        let precision = spec
+                            .split_once('.')
+                            .map_or(6, |(_, f)| f.parse().unwrap_or(0));
+                        match conversion {
+                            'e' => format!("{:.*e}", precision, number),
+                            _ => format!("{:.*}", precision, number),
+                        }
+                    }
+                    other => return Err(format!("printf: %{}: invalid directive", other).into()),
+                };
+                output.extend(pad(&spec, conversion, text.into_bytes()));
+            }
+            c => output.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Ok((output, consumed, false))
+}
+
+/// Applies the flags, width, and string precision of a `%` directive.
+/// Text is measured in characters, or in bytes when it isn't UTF-8.
+fn pad(spec: &str, conversion: char, mut text: Vec<u8>) -> Vec<u8> {
+    let left = spec.contains('-');
+    let (flags_width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, Some(precision.parse::<usize>().unwrap_or(0))),
+        None => (spec, None),
+    };
+    if let (Some(precision), 's' | 'b') = (precision, conversion) {
+        let end = match std::str::from_utf8(&text) {
+            Ok(text) => text
+                .char_indices()
+                .nth(precision)
+                .map_or(text.len(), |f| f.0),
+            Err(_) => precision.min(text.len()),
+        };
+        text.truncate(end);
+    }
+    let digits = flags_width.trim_start_matches(['-', '+', ' ', '#', '0']);
+    let flags = &flags_width[..flags_width.len() - digits.len()];
+    let zero = !left && flags.contains('0');
+    let width = digits.parse::<usize>().unwrap_or(0);
+    let length = std::str::from_utf8(&text).map_or(text.len(), |f| f.chars().count());
+    if length >= width {
+        return text;
+    }
+    let fill = width - length;
+    let mut padded = Vec::with_capacity(width);
+    if left {
+        padded.extend(text);
+        padded.resize(padded.len() + fill, b' ');
+    } else if zero && conversion != 's' {
+        let number = match text.strip_prefix(b"-") {
+            Some(number) => {
+                padded.push(b'-');
+                number
+            }
+            None => &text,
+        };
+        padded.resize(padded.len() + fill, b'0');
+        padded.extend(number);
+    } else {
+        padded.resize(fill, b' ');
+        padded.extend(text);
+    }
+    padded
+}
+
+fn parse_number(arg: &str) -> Result<i64, AshError> {
+    if arg.is_empty() {
+        return Ok(0);
+    }
+    let parsed = if let Some(hex) = arg.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(character) = arg.strip_prefix(['\'', '"']) {
+        return Ok(character.chars().next().map_or(0, |f| f as i64));
+    } else {
+        arg.parse()
+    };
+    parsed.map_err(|_| format!("printf: {}: invalid number", arg).into())
+}
+
+/// Interprets backslash escapes, which can make bytes that aren't UTF-8.
+/// The flag is set when `\c` asked for the rest of the output to be
+/// dropped.
+fn unescape(text: &str) -> (Vec<u8>, bool) {
+    let mut output = vec![];
+    let mut chars = text.chars().peekable();
+    let push = |output: &mut Vec<u8>, c: char| {
+        output.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+    };
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push(&mut output, c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            output.push(b'\\');
+            break;
+        };
+        match escape {
+            'a' => output.push(0x07),
+            'b' => output.push(0x08),
+            'c' => return (output, true),
+            'e' | 'E' => output.push(0x1b),
+            'f' => output.push(0x0c),
+            'n' => output.push(b'\n'),
+            'r' => output.push(b'\r'),
+            't' => output.push(b'\t'),
+            'v' => output.push(0x0b),
+            '\\' => output.push(b'\\'),
+            '0' | 'x' => {
+                let (radix, length) = if escape == '0' { (8, 3) } else { (16, 2) };
+                let mut digits = String::new();
+                while digits.len() < length && chars.peek().is_some_and(|f| f.is_digit(radix)) {
+                    digits.push(chars.next().unwrap());
+                }
+                // `\0777` is past a byte; like coreutils, its low byte is
+                // kept.
+                match u16::from_str_radix(&digits, radix) {
+                    Ok(value) => output.push(value as u8),
+                    Err(_) if escape == '0' => output.push(0),
+                    Err(_) => output.extend(b"\\x"),
+                }
+            }
+            other => {
+                output.push(b'\\');
+                push(&mut output, other);
+            }
+        }
+    }
+    (output, false)
+}
+
+/// Evaluates a `test` expression. Errors mean the expression couldn't be
+/// parsed, which `test` reports with status 2.
+pub fn test(args: &[String]) -> Result<bool, AshError> {
+    let args = args.iter().map(|f| f.as_str()).collect::<Vec<_>>();
+    // With few arguments the meaning depends only on their count, so e.g.
+    // `test -f` checks that the string "-f" isn't empty.
+    match args.as_slice() {
+        [] => return Ok(false),
+        [word] => return Ok(!word.is_empty()),
+        ["!", word] => return Ok(word.is_empty()),
+        _ => {}
+    }
+    let mut parser = TestParser {
+        args: &args,
+        position: 0,
+    };
+    let result = parser.or()?;
+    if let Some(extra) = args.get(parser.position) {
+        return Err(format!("{}: unexpected argument", extra).into());
+    }
+    Ok(result)
+}
+
+struct TestParser<'a> {
+    args: &'a [&'a str],
+    position: usize,
+}
+
+impl<'a> TestParser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.position).copied();
+        self.position += 1;
+        arg
+    }
+
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.position + offset).copied()
+    }
+
+    fn or(&mut self) -> Result<bool, AshError> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.position += 1;
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, AshError> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.position += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, AshError> {
+        if self.peek(0) == Some("!") && self.peek(1).is_some() {
+            self.position += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, AshError> {
+        let Some(first) = self.next() else {
+            return Err("argument expected".into());
+        };
+        if first == "(" {
+            let result = self.or()?;
+            if self.next() != Some(")") {
+                return Err("`)' expected".into());
+            }
+            return Ok(result);
+        }
+        if let Some(operator) = self.peek(0).filter(|f| is_binary_operator(f)) {
+            if let Some(right) = self.peek(1) {
+                self.position += 2;
+                return binary(first, operator, right);
+            }
+        }
+        if is_unary_operator(first) {
+            if let Some(operand) = self.next() {
+                return unary(first, operand);
+            }
+            return Err(format!("{}: argument expected", first).into());
+        }
+        Ok(!first.is_empty())
+    }
+}
+
+fn is_unary_operator(word: &str) -> bool {
+    matches!(
+        word,
+        "-b" | "-c"
+            | "-d"
+            | "-e"
+            | "-f"
+            | "-g"
+            | "-h"
+            | "-k"
+            | "-L"
+            | "-n"
+            | "-p"
+            | "-r"
+            | "-s"
+            | "-S"
+            | "-t"
+            | "-u"
+            | "-w"
+            | "-x"
+            | "-z"
+    )
+}
+
+fn is_binary_operator(word: &str) -> bool {
+    matches!(
+        word,
+        "=" | "=="
+            | "!="
+            | "<"
+            | ">"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
+    )
+}
+
+fn unary(operator: &str, operand: &str) -> Result<bool, AshError> {
+    let access = |mode| {
+        let Ok(path) = std::ffi::CString::new(operand) else {
+            return false;
+        };
+        unsafe { libc::access(path.as_ptr(), mode) == 0 }
+    };
+    let metadata = fs::metadata(operand).ok();
+    let result = match operator {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" => metadata.is_some(),
+        "-f" => metadata.is_some_and(|f| f.is_file()),
+        "-d" => metadata.is_some_and(|f| f.is_dir()),
+        "-b" => metadata.is_some_and(|f| f.file_type().is_block_device()),
+        "-c" => metadata.is_some_and(|f| f.file_type().is_char_device()),
+        "-p" => metadata.is_some_and(|f| f.file_type().is_fifo()),
+        "-S" => metadata.is_some_and(|f| f.file_type().is_socket()),
+        "-s" => metadata.is_some_and(|f| f.len() > 0),
+        "-g" => metadata.is_some_and(|f| f.mode() & libc::S_ISGID != 0),
+        "-u" => metadata.is_some_and(|f| f.mode() & libc::S_ISUID != 0),
+        "-k" => metadata.is_some_and(|f| f.mode() & libc::S_ISVTX != 0),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|f| f.file_type().is_symlink()),
+        "-r" => access(libc::R_OK),
+        "-w" => access(libc::W_OK),
+        "-x" => access(libc::X_OK),
+        "-t" => {
+            let fd = parse_integer(operand)?;
+            unsafe { libc::isatty(fd as libc::c_int) == 1 }
+        }
+        _ => unreachable!(),
+    };
+    Ok(result)
+}
+
+fn binary(left: &str, operator: &str, right: &str) -> Result<bool, AshError> {
+    let modified = |path: &str| fs::metadata(path).and_then(|f| f.modified()).ok();
+    let result = match operator {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, right) => left.is_some() && right.is_none(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (left, right) => left.is_none() && right.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+            _ => false,
+        },
+        operator => {
+            let (left, right) = (parse_integer(left)?, parse_integer(right)?);
+            match operator {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            }
+        }
+    };
+    Ok(result)
+}
+
+fn parse_integer(word: &str) -> Result<i64, AshError> {
+    word.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", word).into())
+}
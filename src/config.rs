@@ -0,0 +1,636 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, path::PathBuf, time::SystemTime};
+
+use crate::theme::ThemeConfig;
+
+pub const DEFAULT_PROMPT_FORMAT: &str =
+    "{prompt_color}{user_host}{dir_icon}{cwd} {separator} {reset}";
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub prompt: PromptConfig,
+    #[serde(deserialize_with = "ThemeConfig::deserialize")]
+    pub theme: ThemeConfig,
+    pub status_bar: StatusBarConfig,
+    /// Printed once when the shell starts; `"none"` keeps the start silent.
+    pub greeting: Option<Greeting>,
+    pub time: TimeConfig,
+    pub options: Options,
+    pub hooks: HooksConfig,
+    pub confirm: ConfirmConfig,
+    pub history: HistoryConfig,
+    pub completion: CompletionConfig,
+    pub editor: EditorConfig,
+    pub fzf: FzfConfig,
+    /// Variables exported at startup. Values may use `$VAR`, `${VAR}`, and
+    /// a leading `~/`.
+    pub env: BTreeMap<String, EnvValue>,
+    /// Key chords like `"ctrl-r"` mapped to an editor action such as
+    /// `"clear-screen"`, or to a command line to run.
+    pub keys: BTreeMap<String, String>,
+    /// Aliases defined at startup, as if by `alias NAME=VALUE`.
+    pub aliases: BTreeMap<String, String>,
+    /// Words that expand in place when typed as the command and followed
+    /// by a space or Enter, so the history shows what actually ran.
+    pub abbreviations: BTreeMap<String, String>,
+}
+
+/// A value of the `[env]` table: a string, or directories to add to a
+/// PATH-like variable, e.g. `PATH = { prepend = ["~/.cargo/bin"] }`.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum EnvValue {
+    Text(String),
+    Paths {
+        #[serde(default)]
+        prepend: Vec<String>,
+        #[serde(default)]
+        append: Vec<String>,
+    },
+}
+
+/// Exports the `[env]` table. Every value is worked out before any is set,
+/// so `$PATH` means the inherited PATH wherever it's used.
+pub fn apply_env(table: &BTreeMap<String, EnvValue>) {
+    let values = table
+        .keys()
+        .map(|name| (name, resolve_env(name, table, &mut vec![])))
+        .collect::<Vec<_>>();
+    for (name, value) in values {
+        env::set_var(name, value);
+    }
+}
+
+fn resolve_env<'a>(
+    name: &'a str,
+    table: &'a BTreeMap<String, EnvValue>,
+    resolving: &mut Vec<&'a str>,
+) -> String {
+    resolving.push(name);
+    let value = match &table[name] {
+        EnvValue::Text(text) => expand_env(text, table, resolving),
+        EnvValue::Paths { prepend, append } => {
+            let added = prepend
+                .iter()
+                .chain(append)
+                .map(|f| expand_env(f, table, resolving))
+                .collect::<Vec<_>>();
+            let inherited = env::var(name).unwrap_or_default();
+            let inherited = inherited
+                .split(':')
+                .filter(|f| !f.is_empty() && !added.iter().any(|a| a == f))
+                .map(str::to_string);
+            let (prepend, append) = added.split_at(prepend.len());
+            prepend
+                .iter()
+                .cloned()
+                .chain(inherited)
+                .chain(append.iter().cloned())
+                .collect::<Vec<_>>()
+                .join(":")
+        }
+    };
+    resolving.pop();
+    value
+}
+
+/// Replaces a leading `~/` and `$VAR`/`${VAR}`, preferring variables of the
+/// table to inherited ones unless that would refer back to itself.
+fn expand_env<'a>(
+    text: &str,
+    table: &'a BTreeMap<String, EnvValue>,
+    resolving: &mut Vec<&'a str>,
+) -> String {
+    let (mut expanded, mut rest) = match text.strip_prefix("~/") {
+        Some(rest) => (format!("{}/", home_dir().display()), rest),
+        None => (String::new(), text),
+    };
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, next) = match after.strip_prefix('{').and_then(|f| f.split_once('}')) {
+            Some((name, next)) => (name, next),
+            None => {
+                let end = after
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                after.split_at(end)
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else if let Some((key, _)) = table
+            .get_key_value(name)
+            .filter(|(key, _)| !resolving.contains(&key.as_str()))
+        {
+            expanded.push_str(&resolve_env(key, table, resolving));
+        } else {
+            expanded.push_str(&env::var(name).unwrap_or_default());
+        }
+        rest = next;
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Where commands are saved; `~/` means the home directory. Defaults
+    /// to `history` in the state directory.
+    pub file: Option<String>,
+    /// How many of the most recent commands are read at startup and kept
+    /// in memory for suggestions. Older ones are read from the file as the
+    /// history is scrolled back, and suggested from an index on disk.
+    pub preload: usize,
+    /// Keeps commands typed with a leading space out of the history.
+    pub ignore_space: bool,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            file: None,
+            preload: 100,
+            ignore_space: false,
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> PathBuf {
+        let Some(file) = &self.file else {
+            return state_dir().join("history");
+        };
+        match file.strip_prefix("~/") {
+            Some(rest) => home_dir().join(rest),
+            None => PathBuf::from(file),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompletionConfig {
+    /// Offers dotfiles even when the word being completed doesn't start
+    /// with a dot.
+    pub show_hidden: bool,
+    /// Asks bash's completion scripts for candidates of commands without a
+    /// spec of their own.
+    pub bash_bridge: bool,
+    /// Milliseconds a spec's candidate generator may run, unless the spec
+    /// sets its own `timeout`.
+    pub generator_timeout: u64,
+}
+
+impl Default for CompletionConfig {
+    fn default() -> Self {
+        CompletionConfig {
+            show_hidden: true,
+            bash_bridge: true,
+            generator_timeout: 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditorConfig {
+    /// Cursor shape while editing; commands get the terminal's own.
+    pub cursor: CursorShape,
+}
+
+/// The `[fzf]` section: Ctrl+R picks a history line and Ctrl+T files with
+/// fzf, when it can be found.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FzfConfig {
+    pub enabled: bool,
+    /// The fzf to run, looked up on PATH unless it contains a `/`.
+    pub command: String,
+    /// Options given to fzf before the query, e.g. `["--height=40%"]`.
+    pub options: Vec<String>,
+}
+
+impl Default for FzfConfig {
+    fn default() -> Self {
+        FzfConfig {
+            enabled: true,
+            command: "fzf".to_string(),
+            options: ["--height=40%", "--reverse"].map(String::from).to_vec(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorShape {
+    /// Whatever the terminal uses.
+    #[default]
+    Default,
+    Block,
+    Bar,
+    Underline,
+}
+
+/// The `[confirm]` section: asks before running commands that look
+/// destructive.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmConfig {
+    pub enabled: bool,
+    /// Patterns for the expanded command line: a wildcard for the
+    /// command, options that must all be given, and wildcards for the
+    /// operands, e.g. `rm -rf ~` or `dd of=/dev/*`.
+    pub patterns: Vec<String>,
+    /// Branches that a `git push --force` to, or from while checked out,
+    /// needs confirmation.
+    pub protected_branches: Vec<String>,
+}
+
+impl Default for ConfirmConfig {
+    fn default() -> Self {
+        ConfirmConfig {
+            enabled: false,
+            patterns: [
+                "rm -rf /",
+                "rm -Rf /",
+                // What `rm -rf /*` expands to.
+                "rm -rf /bin *",
+                "rm -rf ~",
+                "chmod -R 777 /",
+                "mkfs*",
+                "dd of=/dev/*",
+            ]
+            .map(String::from)
+            .to_vec(),
+            protected_branches: vec!["main".to_string(), "master".to_string()],
+        }
+    }
+}
+
+/// Shell commands run at points of the shell's life.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Runs in place of the "command not found" error, with the command
+    /// and its arguments as `$1`, `$2`, ..., e.g.
+    /// `'/usr/lib/command-not-found -- "$1"'`. Its status becomes the
+    /// command's.
+    pub command_not_found: Option<String>,
+    /// Runs before each command line, with the line after alias expansion
+    /// as `$1`.
+    pub preexec: Option<String>,
+    /// Runs before each prompt.
+    pub precmd: Option<String>,
+    /// Runs after a command line changed the cwd, with the new directory
+    /// as `$1` and the old one as `$2`, e.g. `"ls"`.
+    pub on_cd: Option<String>,
+    /// Runs after a command line failed, with its status as `$1` and its
+    /// text as `$2`.
+    pub on_command_error: Option<String>,
+    /// Runs once the prompt has waited `idle_seconds` for a key.
+    pub on_idle: Option<String>,
+    pub idle_seconds: u64,
+}
+
+impl HooksConfig {
+    /// Replaces the hooks that `other` sets.
+    pub fn overlay(&mut self, other: &HooksConfig) {
+        let hooks = [
+            (&mut self.command_not_found, &other.command_not_found),
+            (&mut self.preexec, &other.preexec),
+            (&mut self.precmd, &other.precmd),
+            (&mut self.on_cd, &other.on_cd),
+            (&mut self.on_command_error, &other.on_command_error),
+            (&mut self.on_idle, &other.on_idle),
+        ];
+        for (hook, other) in hooks {
+            if other.is_some() {
+                hook.clone_from(other);
+            }
+        }
+    }
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            command_not_found: None,
+            preexec: None,
+            precmd: None,
+            on_cd: None,
+            on_command_error: None,
+            on_idle: None,
+            idle_seconds: 300,
+        }
+    }
+}
+
+/// Interactive behaviors that can also be toggled at runtime with `shopt`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    /// Changes into a directory typed as a command.
+    pub autocd: bool,
+    /// Shows history suggestions as ghost text while typing.
+    pub autosuggestions: bool,
+    /// Colors the command word by whether it can be run.
+    pub highlighting: bool,
+    /// Refuses to overwrite existing files with `>`; `>|` still does.
+    pub noclobber: bool,
+    /// Matches completion candidates ignoring case.
+    pub nocasecomplete: bool,
+    /// Fails a pipeline when any stage fails, not only the last one.
+    pub pipefail: bool,
+    /// Sends SIGHUP to jobs that weren't disowned when the shell exits or
+    /// its terminal closes.
+    pub huponexit: bool,
+    /// Lets direnv load and unload the environment of `.envrc` files
+    /// before each prompt, if it's installed.
+    pub direnv: bool,
+    /// Tells zoxide about every `cd` and has `j`/`z` jump with its
+    /// database, if it's installed.
+    pub zoxide: bool,
+    /// Listens on a socket that editors and scripts can use to run
+    /// commands in the shell and ask for its cwd and status.
+    pub control_socket: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            autocd: false,
+            autosuggestions: true,
+            highlighting: false,
+            noclobber: false,
+            nocasecomplete: false,
+            pipefail: false,
+            huponexit: true,
+            direnv: true,
+            zoxide: true,
+            control_socket: false,
+        }
+    }
+}
+
+impl Options {
+    pub const NAMES: &[&str] = &[
+        "autocd",
+        "autosuggestions",
+        "highlighting",
+        "noclobber",
+        "nocasecomplete",
+        "pipefail",
+        "huponexit",
+        "direnv",
+        "zoxide",
+        "control_socket",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let flag = match name {
+            "autocd" => self.autocd,
+            "autosuggestions" => self.autosuggestions,
+            "highlighting" => self.highlighting,
+            "noclobber" => self.noclobber,
+            "nocasecomplete" => self.nocasecomplete,
+            "pipefail" => self.pipefail,
+            "huponexit" => self.huponexit,
+            "direnv" => self.direnv,
+            "zoxide" => self.zoxide,
+            "control_socket" => self.control_socket,
+            _ => return None,
+        };
+        Some(flag)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let flag = match name {
+            "autocd" => &mut self.autocd,
+            "autosuggestions" => &mut self.autosuggestions,
+            "highlighting" => &mut self.highlighting,
+            "noclobber" => &mut self.noclobber,
+            "nocasecomplete" => &mut self.nocasecomplete,
+            "pipefail" => &mut self.pipefail,
+            "huponexit" => &mut self.huponexit,
+            "direnv" => &mut self.direnv,
+            "zoxide" => &mut self.zoxide,
+            "control_socket" => &mut self.control_socket,
+            _ => return None,
+        };
+        Some(flag)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeConfig {
+    /// Report printed by the `time` prefix, with `{real}`, `{user}`, and
+    /// `{sys}` replaced by durations like `0m1.250s`.
+    pub format: String,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        TimeConfig {
+            format: "\nreal\t{real}\nuser\t{user}\nsys\t{sys}".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Greeting {
+    Text(String),
+    /// Shows the output of a shell command, e.g. `fortune`.
+    Command {
+        command: String,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusBarConfig {
+    /// Keeps a line at the bottom of the screen with the editor mode and
+    /// key hints while typing.
+    pub enabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    pub format: String,
+    /// Template that replaces the full prompt of a line once it is accepted,
+    /// keeping scrollback compact. Disabled when unset.
+    pub transient: Option<String>,
+    pub cwd_style: CwdStyle,
+    /// Whether to set the terminal title to the cwd at the prompt and to the
+    /// command line while it runs.
+    pub title: bool,
+    /// Whether to emit OSC 133 prompt/command marks for terminal shell
+    /// integration.
+    pub semantic_marks: bool,
+    pub glyphs: GlyphMode,
+    /// Replaces `{separator}` when running as root, styled with the theme's
+    /// `root` color.
+    pub root_symbol: String,
+    /// Template for the prompt of continuation lines of multi-line input.
+    pub continuation: String,
+    /// Right-aligns the continuation prompt under the primary prompt so the
+    /// input of every line starts in the same column.
+    pub align_continuation: bool,
+}
+
+/// Whether the prompt may use Nerd Font icons, which render as boxes
+/// without a patched font.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GlyphMode {
+    /// Nerd Font icons unless the terminal looks unable to show them.
+    #[default]
+    Auto,
+    Nerd,
+    Ascii,
+}
+
+impl GlyphMode {
+    /// Resolves `auto`: the Linux console and non-UTF-8 locales can't show
+    /// icons, anything else is assumed to have a capable font.
+    pub fn use_nerd_font(self) -> bool {
+        match self {
+            GlyphMode::Nerd => true,
+            GlyphMode::Ascii => false,
+            GlyphMode::Auto => {
+                let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+                    .iter()
+                    .find_map(|f| env::var(f).ok().filter(|f| !f.is_empty()))
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let utf8 = locale.contains("utf-8") || locale.contains("utf8");
+                utf8 && env::var("TERM").map_or(true, |term| term != "linux" && term != "dumb")
+            }
+        }
+    }
+}
+
+/// How `{cwd}` is displayed.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CwdStyle {
+    /// Only the last path component, e.g. `src`.
+    #[default]
+    Basename,
+    /// The absolute path.
+    Full,
+    /// The absolute path with the home directory shown as `~`.
+    Home,
+    /// Like `home`, with every parent shortened to its first letter, e.g.
+    /// `~/p/a/src`.
+    Fish,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        PromptConfig {
+            format: DEFAULT_PROMPT_FORMAT.to_string(),
+            transient: None,
+            cwd_style: CwdStyle::default(),
+            title: true,
+            semantic_marks: true,
+            glyphs: GlyphMode::default(),
+            root_symbol: "#".to_string(),
+            continuation: "{dim}…>{reset} ".to_string(),
+            align_continuation: true,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `config.toml` in the config directory, falling back to the defaults when the
+    /// file is missing or can't be parsed. Unknown keys are errors, so a typo
+    /// doesn't silently leave a setting at its default.
+    pub fn load() -> Self {
+        Config::try_load().unwrap_or_else(|e| {
+            eprintln!("ash: {}", e);
+            Config::default()
+        })
+    }
+
+    /// Like `load`, but reports an invalid file instead of using the
+    /// defaults.
+    pub fn try_load() -> Result<Self, String> {
+        let path = config_path();
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(Config::default());
+        };
+        toml::from_str(&content).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// `$XDG_CONFIG_HOME/ash`, by default `~/.config/ash`.
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("ash")
+}
+
+/// `$XDG_STATE_HOME/ash`, by default `~/.local/state/ash`: history,
+/// visited directories, and trusted project files.
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state").join("ash")
+}
+
+/// `$XDG_CACHE_HOME/ash`, by default `~/.cache/ash`: data ash can build
+/// again, like the index of the history.
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache").join("ash")
+}
+
+/// The spec says relative paths in the variables are to be ignored.
+fn xdg_dir(var: &str, default: &str) -> PathBuf {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|f| f.is_absolute())
+        .unwrap_or_else(|| home_dir().join(default))
+}
+
+/// Creates the state directory, moving in the files that older versions
+/// kept in the home directory unless they have been replaced already.
+pub fn migrate_state() {
+    let dir = state_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("ash: {}: {}", dir.display(), e);
+        return;
+    }
+    let home = home_dir();
+    for (legacy, name) in [
+        (".ash_history", "history"),
+        (".ash_dirs", "dirs"),
+        (".ash_trusted", "trusted"),
+    ] {
+        let (from, to) = (home.join(legacy), dir.join(name));
+        if from.is_file() && !to.exists() {
+            if let Err(e) = fs::rename(&from, &to) {
+                eprintln!("ash: moving {} to {}: {}", from.display(), to.display(), e);
+            }
+        }
+    }
+}
+
+/// When the config file was last changed, `None` if there is none.
+pub fn config_modified() -> Option<SystemTime> {
+    fs::metadata(config_path()).and_then(|f| f.modified()).ok()
+}
+
+pub fn home_dir() -> PathBuf {
+    env::var("HOME").map(PathBuf::from).unwrap_or_else(|_| {
+        PathBuf::from(format!(
+            "/home/{}",
+            env::var("USER").unwrap_or_else(|_| "Unknown".to_string())
+        ))
+    })
+}
@@ -0,0 +1,136 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+
+use crate::config;
+use crate::worker::{self, Message};
+
+/// What a client of the control socket asks the shell, one per line.
+pub enum Request {
+    /// `run LINE` runs a command line as if it had been typed.
+    Run(String),
+    /// `cd DIR` changes the cwd without running anything.
+    Cd(String),
+    /// `cwd` asks for the cwd.
+    Cwd,
+    /// `status` asks for the status of the last command line.
+    Status,
+}
+
+/// Where the shell sends the answer to a request.
+pub type Reply = Sender<Result<String, String>>;
+
+impl Request {
+    fn parse(line: &str) -> Result<Request, String> {
+        let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match (name, argument) {
+            ("run", "") | ("cd", "") => Err(format!("{}: missing argument", name)),
+            ("run", line) => Ok(Request::Run(line.to_string())),
+            ("cd", dir) => Ok(Request::Cd(dir.to_string())),
+            ("cwd", "") => Ok(Request::Cwd),
+            ("status", "") => Ok(Request::Status),
+            _ => Err(format!("unknown request `{}`", line)),
+        }
+    }
+}
+
+/// The socket of this shell. Clients connect, write requests, and read a
+/// reply line for each: `ok`, `ok VALUE`, or `error MESSAGE`. The main
+/// loop answers them while the prompt waits for keys, so while a command
+/// runs they wait for it. Its path is in `$ASH_CONTROL_SOCKET` for the
+/// commands the shell starts; it's removed when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl ControlSocket {
+    /// Listens on `ash-PID.sock` in the runtime directory, which only the
+    /// user can open.
+    pub fn listen() -> io::Result<ControlSocket> {
+        let dir = env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|f| f.is_absolute())
+            .map_or_else(config::state_dir, |f| f.join("ash"));
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+        remove_stale(&dir);
+        let path = dir.join(format!("ash-{}.sock", std::process::id()));
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        let accepting = listener.try_clone()?;
+        worker::spawn(move || {
+            // Ends once `drop` shuts the socket down.
+            for stream in accepting.incoming() {
+                let Ok(stream) = stream else {
+                    return;
+                };
+                worker::spawn(move || drop(serve(stream)));
+            }
+        });
+        env::set_var("ASH_CONTROL_SOCKET", &path);
+        Ok(ControlSocket { path, listener })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        env::remove_var("ASH_CONTROL_SOCKET");
+        let _ = fs::remove_file(&self.path);
+        unsafe { libc::shutdown(self.listener.as_raw_fd(), libc::SHUT_RDWR) };
+    }
+}
+
+/// Removes the sockets of shells that are gone, which a shell killed
+/// before it could clean up leaves behind.
+fn remove_stale(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|f| f.path()) {
+        let pid = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .and_then(|f| f.strip_prefix("ash-")?.strip_suffix(".sock"))
+            .and_then(|f| f.parse::<libc::pid_t>().ok());
+        let Some(pid) = pid else {
+            continue;
+        };
+        let gone = unsafe { libc::kill(pid, 0) } == -1
+            && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH);
+        if gone || pid == std::process::id() as libc::pid_t {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Answers the requests of one client until it disconnects.
+fn serve(stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let reply = match Request::parse(line.trim()) {
+            Ok(request) => {
+                let (sender, receiver) = mpsc::channel();
+                worker::send(Message::Control(request, sender));
+                receiver
+                    .recv()
+                    .unwrap_or_else(|_| Err("the shell stopped listening".to_string()))
+            }
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(value) if value.is_empty() => writeln!(writer, "ok")?,
+            Ok(value) => writeln!(writer, "ok {}", value)?,
+            Err(e) => writeln!(writer, "error {}", e)?,
+        }
+    }
+    Ok(())
+}
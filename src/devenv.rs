@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::autocomplete::run_with_timeout;
+
+const VERSION_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Default)]
+pub struct DevEnv {
+    pub node: Option<String>,
+    pub rust: Option<String>,
+}
+
+/// Remembers the node/rust versions detected for each directory. Unlike git
+/// status these only change when a version file is edited, so entries live
+/// for the whole session.
+#[derive(Default)]
+pub struct DevEnvCache {
+    environments: HashMap<PathBuf, DevEnv>,
+}
+
+impl DevEnvCache {
+    pub fn get(&mut self, cwd: &Path) -> DevEnv {
+        self.environments
+            .entry(cwd.to_path_buf())
+            .or_insert_with(|| DevEnv {
+                node: get_node_version(cwd),
+                rust: get_rust_toolchain(cwd),
+            })
+            .clone()
+    }
+}
+
+/// Name of the active Python virtualenv, read from the environment each
+/// time since `activate` can change it between prompts.
+pub fn get_virtualenv() -> Option<String> {
+    let venv = std::env::var("VIRTUAL_ENV").ok()?;
+    Path::new(&venv)
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+}
+
+fn get_node_version(cwd: &Path) -> Option<String> {
+    if let Some(nvmrc) = find_upwards(cwd, ".nvmrc") {
+        let version = fs::read_to_string(nvmrc).ok()?;
+        return Some(version.trim().to_string()).filter(|f| !f.is_empty());
+    }
+    find_upwards(cwd, "package.json")?;
+    let mut command = Command::new("node");
+    command.arg("--version");
+    let version = run_with_timeout(command, VERSION_TIMEOUT).ok()?;
+    Some(version.trim().to_string()).filter(|f| !f.is_empty())
+}
+
+fn get_rust_toolchain(cwd: &Path) -> Option<String> {
+    if let Ok(toolchain) = std::env::var("RUSTUP_TOOLCHAIN") {
+        return Some(toolchain);
+    }
+    if let Some(file) = find_upwards(cwd, "rust-toolchain.toml") {
+        let content = fs::read_to_string(file).ok()?;
+        let table = content.parse::<toml::Table>().ok()?;
+        return table
+            .get("toolchain")?
+            .get("channel")?
+            .as_str()
+            .map(|f| f.to_string());
+    }
+    let file = find_upwards(cwd, "rust-toolchain")?;
+    let content = fs::read_to_string(file).ok()?;
+    Some(content.trim().to_string()).filter(|f| !f.is_empty())
+}
+
+/// Asks direnv how the environment has to change for the cwd: the
+/// variables to set, and to unset for `None`. Its messages about loading
+/// and unloading go to the terminal.
+pub fn direnv_export() -> Option<HashMap<String, Option<String>>> {
+    let output = Command::new("direnv")
+        .args(["export", "json"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .ok()?;
+    // Nothing is printed when there is nothing to change.
+    if !output.status.success() || output.stdout.is_empty() {
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+fn find_upwards(cwd: &Path, name: &str) -> Option<PathBuf> {
+    cwd.ancestors()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a command line, builtin, or editor action failed.
+#[derive(Debug)]
+pub enum AshError {
+    /// The input isn't valid shell syntax.
+    Parse(String),
+    /// A file or process operation failed; `context` names what it was
+    /// done to, and may be empty.
+    Io { context: String, source: io::Error },
+    /// No builtin, alias, plugin, or executable has this name.
+    CommandNotFound(String),
+    /// Reading keys from or drawing on the terminal failed.
+    Terminal(io::Error),
+    /// A builtin or shell feature refused to run, with its message.
+    Message(String),
+}
+
+impl AshError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        AshError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// The status left in `$?`: 2 for syntax errors, 127 for unknown
+    /// commands and 126 for ones that can't be run, as in other shells.
+    pub fn status(&self) -> i32 {
+        match self {
+            AshError::Parse(_) => 2,
+            AshError::CommandNotFound(_) => 127,
+            AshError::Io { .. } if self.is_permission_denied() => 126,
+            _ => 1,
+        }
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(self, AshError::Io { source, .. } if source.kind() == io::ErrorKind::PermissionDenied)
+    }
+}
+
+impl fmt::Display for AshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AshError::Parse(message) => write!(f, "syntax error: {}", message),
+            AshError::Io { context, source } if context.is_empty() => write!(f, "{}", source),
+            AshError::Io { context, source } => write!(f, "{}: {}", context, source),
+            AshError::CommandNotFound(command) => write!(f, "{}: command not found", command),
+            AshError::Terminal(e) => write!(f, "terminal: {}", e),
+            AshError::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for AshError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AshError::Io { source, .. } | AshError::Terminal(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AshError {
+    fn from(source: io::Error) -> Self {
+        AshError::io("", source)
+    }
+}
+
+impl From<String> for AshError {
+    fn from(message: String) -> Self {
+        AshError::Message(message)
+    }
+}
+
+impl From<&str> for AshError {
+    fn from(message: &str) -> Self {
+        AshError::Message(message.to_string())
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Once the ranks add up to this, they are all scaled down so that
+/// directories that stopped being used eventually drop out.
+const MAX_TOTAL_RANK: f64 = 5000.0;
+
+struct Entry {
+    dir: PathBuf,
+    rank: f64,
+    last_visit: u64,
+}
+
+/// Directories the user cd'd into, ranked by how often and how recently
+/// they were visited, for `j`/`z`. Stored as `rank<TAB>time<TAB>path`
+/// lines.
+pub struct Frecency {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Frecency {
+    pub fn load(path: PathBuf) -> Self {
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                Some(Entry {
+                    rank: fields.next()?.parse().ok()?,
+                    last_visit: fields.next()?.parse().ok()?,
+                    dir: PathBuf::from(fields.next()?),
+                })
+            })
+            .collect();
+        Frecency { path, entries }
+    }
+
+    /// No directories and no file, for running scripts.
+    pub fn empty() -> Self {
+        Frecency {
+            path: PathBuf::new(),
+            entries: vec![],
+        }
+    }
+
+    pub fn visit(&mut self, dir: &Path) {
+        let now = now();
+        match self.entries.iter_mut().find(|f| f.dir == dir) {
+            Some(entry) => {
+                entry.rank += 1.0;
+                entry.last_visit = now;
+            }
+            None => self.entries.push(Entry {
+                dir: dir.to_path_buf(),
+                rank: 1.0,
+                last_visit: now,
+            }),
+        }
+        if self.entries.iter().map(|f| f.rank).sum::<f64>() > MAX_TOTAL_RANK {
+            for entry in &mut self.entries {
+                entry.rank *= 0.9;
+            }
+            self.entries.retain(|f| f.rank >= 1.0);
+        }
+        self.save();
+    }
+
+    /// Existing directories whose path contains every word of `query` in
+    /// order, ignoring case, best first. The last word has to match the
+    /// last path component, so `j proj` prefers `~/code/my-project` over
+    /// `~/project/src`.
+    pub fn matches(&self, query: &[String]) -> Vec<(f64, &Path)> {
+        let now = now();
+        let query = query.iter().map(|f| f.to_lowercase()).collect::<Vec<_>>();
+        let mut matches = self
+            .entries
+            .iter()
+            .filter(|f| is_match(&f.dir, &query) && f.dir.is_dir())
+            .map(|f| (score(f, now), f.dir.as_path()))
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        matches
+    }
+
+    fn save(&self) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        let content = self
+            .entries
+            .iter()
+            .map(|f| format!("{}\t{}\t{}\n", f.rank, f.last_visit, f.dir.display()))
+            .collect::<String>();
+        let _ = fs::write(&self.path, content);
+    }
+}
+
+fn is_match(dir: &Path, query: &[String]) -> bool {
+    let path = dir.to_string_lossy().to_lowercase();
+    let mut rest = path.as_str();
+    for word in query {
+        match rest.find(word.as_str()) {
+            Some(i) => rest = &rest[i + word.len()..],
+            None => return false,
+        }
+    }
+    let last_component = dir
+        .file_name()
+        .map(|f| f.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    query
+        .last()
+        .is_none_or(|f| last_component.contains(f.as_str()))
+}
+
+/// Rank weighted by how long ago the last visit was.
+fn score(entry: &Entry, now: u64) -> f64 {
+    let age = now.saturating_sub(entry.last_visit);
+    let weight = match age {
+        0..3600 => 4.0,
+        3600..86400 => 2.0,
+        86400..604800 => 0.5,
+        _ => 0.25,
+    };
+    entry.rank * weight
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |f| f.as_secs())
+}
@@ -0,0 +1,70 @@
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::config::FzfConfig;
+use crate::jobs;
+use crate::parser;
+use crate::worker;
+
+/// Runs fzf with `query` typed in, in the foreground so it gets the
+/// terminal, and returns the lines picked. `feed` writes the candidates,
+/// one per line, on a worker thread so fzf shows them as they come; it
+/// should stop once a write fails, which means fzf has exited.
+pub fn select(
+    config: &FzfConfig,
+    options: &[&str],
+    query: &str,
+    feed: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+) -> io::Result<Vec<String>> {
+    let mut command = Command::new(&config.command);
+    command
+        .args(&config.options)
+        .args(options)
+        .arg("--query")
+        .arg(query)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    jobs::prepare_child(&mut command, 0, true);
+    let mut child = command.spawn()?;
+    jobs::set_foreground(child.id() as libc::pid_t);
+    let stdin = child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?;
+    worker::spawn(move || {
+        let mut stdin = BufWriter::new(stdin);
+        let _ = feed(&mut stdin).and_then(|_| stdin.flush());
+    });
+    let mut output = String::new();
+    let read = child
+        .stdout
+        .take()
+        .ok_or(io::ErrorKind::BrokenPipe)?
+        .read_to_string(&mut output);
+    let _ = child.wait();
+    jobs::take_terminal();
+    read?;
+    Ok(output.lines().map(String::from).collect())
+}
+
+/// A path as a word of a command line, quoted only when it needs to be.
+pub fn as_word(path: &str) -> String {
+    let plain = path
+        .chars()
+        .all(|c| c.is_alphanumeric() || "/._-+@%:,=".contains(c));
+    if plain && !path.is_empty() {
+        path.to_string()
+    } else {
+        parser::quote(path)
+    }
+}
+
+/// Whether fzf can be run: it's enabled and the command exists.
+pub fn available(config: &FzfConfig, on_path: impl FnOnce(&str) -> bool) -> bool {
+    if !config.enabled {
+        return false;
+    }
+    if config.command.contains('/') {
+        Path::new(&config.command).is_file()
+    } else {
+        on_path(&config.command)
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::autocomplete::run_with_timeout;
+use crate::worker::{self, Message};
+
+const GIT_STATUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone, PartialEq)]
+pub struct GitStatus {
+    pub branch: String,
+    pub ahead: u32,
+    pub behind: u32,
+    pub dirty: bool,
+}
+
+impl GitStatus {
+    pub fn render(&self, unicode: bool) -> String {
+        let (ahead, behind) = if unicode { ("↑", "↓") } else { ("+", "-") };
+        let mut rendered = self.branch.clone();
+        if self.ahead > 0 {
+            rendered.push_str(&format!(" {}{}", ahead, self.ahead));
+        }
+        if self.behind > 0 {
+            rendered.push_str(&format!(" {}{}", behind, self.behind));
+        }
+        if self.dirty {
+            rendered.push('*');
+        }
+        rendered
+    }
+}
+
+struct Entry {
+    status: Option<GitStatus>,
+    /// Whether git was asked since the last command.
+    current: bool,
+}
+
+/// Remembers the git status of each directory so the prompt, which is
+/// redrawn on every keystroke, only asks git once per command. Git is asked
+/// in the background; until it answers, the prompt shows what it said last
+/// time, or just the branch from `.git/HEAD`.
+#[derive(Default)]
+pub struct GitCache {
+    statuses: HashMap<PathBuf, Entry>,
+}
+
+impl GitCache {
+    pub fn get(&mut self, cwd: &Path) -> Option<GitStatus> {
+        let entry = self
+            .statuses
+            .entry(cwd.to_path_buf())
+            .or_insert_with(|| Entry {
+                status: head_status(cwd),
+                current: false,
+            });
+        if !entry.current {
+            entry.current = true;
+            let cwd = cwd.to_path_buf();
+            worker::spawn(move || {
+                let status = get_git_status(&cwd);
+                worker::send(Message::GitStatus(cwd, status));
+            });
+        }
+        entry.status.clone()
+    }
+
+    /// Stores what git said about `cwd`, returning whether the prompt would
+    /// show something else now.
+    pub fn update(&mut self, cwd: PathBuf, status: Option<GitStatus>) -> bool {
+        let entry = self.statuses.entry(cwd).or_insert(Entry {
+            status: None,
+            current: true,
+        });
+        let changed = entry.status != status;
+        entry.status = status;
+        changed
+    }
+
+    /// Marks everything as out of date; called after each command since it
+    /// may have committed, checked out, or edited files.
+    pub fn clear(&mut self) {
+        for entry in self.statuses.values_mut() {
+            entry.current = false;
+        }
+    }
+}
+
+/// The branch checked out in the repository holding `cwd`, read from
+/// `.git/HEAD` right away.
+pub fn current_branch(cwd: &Path) -> Option<String> {
+    head_status(cwd).map(|f| f.branch)
+}
+
+fn get_git_status(cwd: &Path) -> Option<GitStatus> {
+    cwd.ancestors().find(|dir| dir.join(".git").exists())?;
+    let mut command = Command::new("git");
+    command
+        .arg("-C")
+        .arg(cwd)
+        .args(["status", "--porcelain=v2", "--branch"]);
+    match run_with_timeout(command, GIT_STATUS_TIMEOUT) {
+        Ok(output) if !output.is_empty() => Some(parse_porcelain(&output)),
+        _ => head_status(cwd),
+    }
+}
+
+fn head_status(cwd: &Path) -> Option<GitStatus> {
+    let repository = cwd.ancestors().find(|dir| dir.join(".git").exists())?;
+    read_head(repository).map(|branch| GitStatus {
+        branch,
+        ahead: 0,
+        behind: 0,
+        dirty: false,
+    })
+}
+
+fn parse_porcelain(output: &str) -> GitStatus {
+    let mut status = GitStatus {
+        branch: String::new(),
+        ahead: 0,
+        behind: 0,
+        dirty: false,
+    };
+    let mut commit = String::new();
+    for line in output.lines() {
+        if let Some(head) = line.strip_prefix("# branch.head ") {
+            status.branch = head.to_string();
+        } else if let Some(oid) = line.strip_prefix("# branch.oid ") {
+            commit = oid.chars().take(7).collect();
+        } else if let Some(counts) = line.strip_prefix("# branch.ab ") {
+            for count in counts.split_whitespace() {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    status.ahead = ahead.parse().unwrap_or(0);
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    status.behind = behind.parse().unwrap_or(0);
+                }
+            }
+        } else if !line.starts_with('#') {
+            status.dirty = true;
+        }
+    }
+    if status.branch == "(detached)" {
+        status.branch = commit;
+    }
+    status
+}
+
+/// Reads the branch straight from `.git/HEAD` for when git itself isn't
+/// available or is too slow to answer.
+fn read_head(repository: &Path) -> Option<String> {
+    let head = fs::read_to_string(repository.join(".git/HEAD")).ok()?;
+    match head.trim().strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(head.trim().chars().take(7).collect()),
+    }
+}
@@ -0,0 +1,123 @@
+use std::{env, fs};
+
+pub fn is_glob(word: &str) -> bool {
+    word.contains(['*', '?', '['])
+}
+
+/// Expands a glob pattern against the filesystem one path component at a
+/// time, returning the sorted matches in the same form they were written
+/// (relative, absolute, or `~/`-prefixed).
+pub fn expand(pattern: &str) -> Vec<String> {
+    let home = env::var("HOME").unwrap_or_default();
+    let (display_prefix, lookup_prefix, rest) = if let Some(rest) = pattern.strip_prefix("~/") {
+        ("~/".to_string(), format!("{}/", home), rest)
+    } else if let Some(rest) = pattern.strip_prefix('/') {
+        ("/".to_string(), "/".to_string(), rest)
+    } else {
+        (String::new(), String::new(), pattern)
+    };
+
+    let mut matches = vec![String::new()];
+    for component in rest.split('/') {
+        let mut next = vec![];
+        for matched in &matches {
+            if component.is_empty() || !is_glob(component) {
+                next.push(format!("{}{}", matched, component));
+                continue;
+            }
+            let dir = format!("{}{}", lookup_prefix, matched);
+            let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { &dir }) else {
+                continue;
+            };
+            let mut names = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| matches_pattern(component, name))
+                .collect::<Vec<_>>();
+            names.sort();
+            next.extend(names.into_iter().map(|name| format!("{}{}", matched, name)));
+        }
+        matches = next
+            .into_iter()
+            .filter(|f| {
+                let path = format!("{}{}", lookup_prefix, f);
+                fs::symlink_metadata(if path.is_empty() { "." } else { &path }).is_ok()
+            })
+            .map(|f| f + "/")
+            .collect();
+    }
+
+    matches
+        .into_iter()
+        .map(|f| {
+            let f = f.trim_end_matches('/');
+            let suffix = if rest.ends_with('/') { "/" } else { "" };
+            format!("{}{}{}", display_prefix, f, suffix)
+        })
+        .collect()
+}
+
+/// Shell-style wildcard matching supporting `*`, `?`, and `[...]` classes
+/// (with `!`/`^` negation and `a-z` ranges).
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    matches_from(&pattern, &name)
+}
+
+/// Matches left to right, remembering only the last `*` seen: on a
+/// mismatch it takes one more character and retries from there. An
+/// earlier `*` never needs revisiting, so this runs in linear time
+/// however many `*` the pattern has.
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, n));
+        } else if let Some(next) = matches_one(pattern, p, name[n]) {
+            p = next;
+            n += 1;
+        } else if let Some((after, taken)) = star {
+            p = after;
+            n = taken + 1;
+            star = Some((after, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Where the pattern continues if its element at `p` matches `c`.
+fn matches_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern.get(p)? {
+        '?' => Some(p + 1),
+        '[' => match pattern.iter().skip(p + 2).position(|f| *f == ']') {
+            Some(end) => matches_class(&pattern[p + 1..p + end + 2], c).then_some(p + end + 3),
+            None => (c == '[').then_some(p + 1),
+        },
+        f => (*f == c).then_some(p + 1),
+    }
+}
+
+fn matches_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
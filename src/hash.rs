@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::worker;
+
+struct Entry {
+    path: PathBuf,
+    hits: usize,
+}
+
+/// Remembers where commands were found on PATH so running them again
+/// doesn't scan every PATH directory. Forgotten when PATH changes.
+#[derive(Default)]
+pub struct CommandHash {
+    entries: BTreeMap<String, Entry>,
+    path: String,
+    index: PathIndex,
+}
+
+impl CommandHash {
+    /// Finds `command` on PATH, from the cache when its entry still exists.
+    pub fn resolve(&mut self, command: &str) -> Option<PathBuf> {
+        self.check_path();
+        if let Some(entry) = self.entries.get_mut(command) {
+            if entry.path.exists() {
+                entry.hits += 1;
+                return Some(entry.path.clone());
+            }
+        }
+        let path = self.index.find(command)?;
+        self.entries.insert(
+            command.to_string(),
+            Entry {
+                path: path.clone(),
+                hits: 1,
+            },
+        );
+        Some(path)
+    }
+
+    /// Whether `command` is cached or on PATH, without counting a hit.
+    pub fn contains(&mut self, command: &str) -> bool {
+        self.check_path();
+        self.entries.get(command).is_some_and(|f| f.path.exists())
+            || self.index.find(command).is_some()
+    }
+
+    /// Adds `command` without running it, as `hash name` does.
+    pub fn add(&mut self, command: &str) -> bool {
+        self.check_path();
+        let Some(path) = self.index.find(command) else {
+            return false;
+        };
+        self.entries
+            .insert(command.to_string(), Entry { path, hits: 0 });
+        true
+    }
+
+    pub fn remove(&mut self, command: &str) -> bool {
+        self.entries.remove(command).is_some()
+    }
+
+    /// Forgets the remembered paths and rescans PATH, as `rehash` does.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.refresh();
+    }
+
+    /// Names of every executable on PATH, sorted.
+    pub fn executables(&self) -> Arc<Vec<String>> {
+        self.index.names()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Path, usize)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name, entry.path.as_path(), entry.hits))
+    }
+
+    fn check_path(&mut self) {
+        let path = env::var("PATH").unwrap_or_default();
+        if path != self.path {
+            self.entries.clear();
+            self.path = path;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Scan {
+    /// The PATH that was scanned, `None` before the first scan finishes.
+    path: Option<String>,
+    commands: BTreeMap<String, PathBuf>,
+    names: Arc<Vec<String>>,
+    /// Set while a scan runs, so only one does at a time.
+    scanning: bool,
+}
+
+/// Every executable on PATH with the first directory that has it, scanned
+/// on a background thread when the names are first listed and again when
+/// PATH changes, a command turns up that the last scan missed, or on
+/// `rehash`. Lookups before a scan finishes search PATH directly.
+#[derive(Clone, Default)]
+pub struct PathIndex {
+    scan: Arc<Mutex<Scan>>,
+}
+
+impl PathIndex {
+    pub fn refresh(&self) {
+        let mut scan = self.scan.lock().unwrap();
+        if scan.scanning {
+            return;
+        }
+        scan.scanning = true;
+        let index = self.clone();
+        worker::spawn(move || {
+            let path = env::var("PATH").unwrap_or_default();
+            let commands = scan_path(&path);
+            let names = commands.keys().cloned().collect();
+            let mut scan = index.scan.lock().unwrap();
+            *scan = Scan {
+                path: Some(path),
+                commands,
+                names: Arc::new(names),
+                scanning: false,
+            };
+        });
+    }
+
+    pub fn find(&self, command: &str) -> Option<PathBuf> {
+        let path = env::var("PATH").unwrap_or_default();
+        let (indexed, scanned) = {
+            let scan = self.scan.lock().unwrap();
+            let indexed = match &scan.path {
+                Some(scanned) if *scanned == path => Some(scan.commands.get(command).cloned()),
+                _ => None,
+            };
+            (indexed, scan.path.is_some())
+        };
+        match indexed {
+            Some(Some(found)) if found.is_file() => Some(found),
+            Some(_) => {
+                // Installed or removed since the scan.
+                let found = find_on_path(command);
+                if found.is_some() {
+                    self.refresh();
+                }
+                found
+            }
+            None => {
+                // The first scan is left to the first listing of names, so
+                // the lookups startup does don't wait for it.
+                if scanned {
+                    self.refresh();
+                }
+                find_on_path(command)
+            }
+        }
+    }
+
+    /// The scanned names, empty until the first scan finishes and those of
+    /// the old PATH until a rescan does.
+    pub fn names(&self) -> Arc<Vec<String>> {
+        let (names, current) = {
+            let scan = self.scan.lock().unwrap();
+            let path = env::var("PATH").unwrap_or_default();
+            (scan.names.clone(), scan.path.as_ref() == Some(&path))
+        };
+        if !current {
+            self.refresh();
+        }
+        names
+    }
+}
+
+/// Maps each name to its first occurrence, like a PATH search would.
+fn scan_path(path: &str) -> BTreeMap<String, PathBuf> {
+    let mut commands = BTreeMap::new();
+    for location in path.split(':').filter(|f| !f.is_empty()) {
+        let Ok(entries) = fs::read_dir(location) else {
+            continue;
+        };
+        for entry in entries.filter_map(|f| f.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if commands.contains_key(&name) || !entry.path().is_file() {
+                continue;
+            }
+            commands.insert(name, entry.path());
+        }
+    }
+    commands
+}
+
+pub fn find_on_path(command: &str) -> Option<PathBuf> {
+    let path = env::var("PATH").unwrap_or_default();
+    path.split(':')
+        .map(|location| Path::new(location).join(command))
+        .find(|f| f.is_file())
+}
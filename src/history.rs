@@ -1,59 +1,254 @@
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     fs::File,
     io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
     path::PathBuf,
 };
 
+use crate::suggestion::{DiskIndex, HistoryIndex};
+use crate::worker::{self, Message};
+
+/// How many history lines are offered as suggestions for one input.
+const SUGGESTION_LIMIT: usize = 50;
+
+/// How many prefixes' lines from the disk index are kept.
+const CACHED_SEARCHES: usize = 64;
+
+/// How many lines of the history file are read at a time, and how many
+/// such chunks are kept in memory while browsing.
+const CHUNK_LINES: usize = 256;
+const CACHED_CHUNKS: usize = 4;
+
+/// The commands run in this shell and the history file behind them. Only
+/// recent lines are kept in memory: browsing further back reads the file a
+/// chunk at a time, and suggestions from older lines come from an index on
+/// disk, so a history of many years costs no more memory than a new one.
 pub struct History {
     path: PathBuf,
-    reader: LineReader,
-    pub commands: Vec<String>,
-    new_commands_count: u32,
+    /// Commands run this session and not saved yet, newest first.
+    session: Vec<String>,
+    /// Lines of the history file, `None` for scripts.
+    file: Option<FileLines>,
+    /// The session's commands and the preloaded lines, for suggestions.
+    index: HistoryIndex,
+    disk_index: Option<DiskIndex>,
+    /// Set once the disk index is being opened in the background, which
+    /// the first suggestion starts so it doesn't slow startup down. Until
+    /// it's done, suggestions come from the preloaded lines.
+    indexing: bool,
+    /// Lines found in the disk index by prefix. The index is searched on a
+    /// worker, one prefix at a time; the latest prefix asked for while a
+    /// search runs is searched next.
+    searches: HashMap<String, Vec<String>>,
+    searching: bool,
+    next_search: Option<String>,
 }
 
 impl History {
-    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+    /// Opens the history file, reading its `preload` most recent commands.
+    pub fn new(path: impl Into<PathBuf>, preload: usize) -> io::Result<Self> {
         let path = path.into();
 
         if !path.exists() {
             File::create(&path)?;
         }
 
-        let mut reader = LineReader::new(&path)?;
-        let commands = reader.read_lines(100)?;
+        let mut file = FileLines::new(path.clone());
+        let mut index = HistoryIndex::default();
+        index.add_older((0..preload).map_while(|f| file.get(f)));
 
         Ok(Self {
             path,
-            commands,
-            reader,
-            new_commands_count: 0,
+            session: vec![],
+            file: Some(file),
+            index,
+            disk_index: None,
+            indexing: false,
+            searches: HashMap::new(),
+            searching: false,
+            next_search: None,
         })
     }
 
-    pub fn add_command(&mut self, command: &str) {
-        if self.commands.first().map_or("", |f| f) != command {
-            self.commands.insert(0, command.to_string());
-            self.new_commands_count += 1;
+    /// A history without a file, for running scripts.
+    pub fn empty() -> Self {
+        Self {
+            path: PathBuf::new(),
+            session: vec![],
+            file: None,
+            index: HistoryIndex::default(),
+            disk_index: None,
+            indexing: true,
+            searches: HashMap::new(),
+            searching: false,
+            next_search: None,
         }
     }
 
-    pub fn get_command(&self, index: usize) -> Option<&String> {
-        self.commands.get(index)
+    pub fn add_command(&mut self, command: &str) {
+        if self.get_command(0).as_deref() != Some(command) {
+            self.session.insert(0, command.to_string());
+        }
+        self.index.add(command);
     }
 
-    pub fn fetch_more(&mut self) {
-        match self.reader.read_lines(10) {
-            Ok(mut cmds) => {
-                if cmds.len() > 0 {
-                    self.commands.append(&mut cmds);
+    /// Lines from the whole history starting with `prefix`, most recent
+    /// first, and whether they are all of them: not cut off at the limit,
+    /// and with the disk index searched for `prefix`. Until it is, they
+    /// come from memory, and `Message::HistorySearched` says when to ask
+    /// again.
+    pub fn suggest(&mut self, prefix: &str) -> (Vec<String>, bool) {
+        if !self.indexing {
+            self.indexing = true;
+            let path = self.path.clone();
+            worker::spawn(move || {
+                if let Ok(index) = DiskIndex::open(&path) {
+                    worker::send(Message::HistoryIndexed(index));
                 }
+            });
+        }
+        let mut matches = self.index.matches(prefix, SUGGESTION_LIMIT);
+        if matches.len() == SUGGESTION_LIMIT || self.disk_index.is_none() {
+            return (matches, false);
+        }
+        let Some(found) = self.searched(prefix) else {
+            self.search(prefix);
+            return (matches, false);
+        };
+        for line in found {
+            if matches.len() == SUGGESTION_LIMIT {
+                break;
+            }
+            if !matches.contains(&line) {
+                matches.push(line);
             }
-            Err(_) => (),
         }
+        let complete = matches.len() < SUGGESTION_LIMIT;
+        (matches, complete)
     }
 
-    pub fn count(&self) -> usize {
-        self.commands.len()
+    /// The lines of the disk index starting with `prefix`, from a search
+    /// for it or for a shorter prefix that found all its lines.
+    fn searched(&self, prefix: &str) -> Option<Vec<String>> {
+        if let Some(found) = self.searches.get(prefix) {
+            return Some(found.clone());
+        }
+        self.searches
+            .iter()
+            .find(|(searched, found)| {
+                prefix.starts_with(searched.as_str()) && found.len() < SUGGESTION_LIMIT
+            })
+            .map(|(_, found)| {
+                found
+                    .iter()
+                    .filter(|f| f.starts_with(prefix))
+                    .cloned()
+                    .collect()
+            })
+    }
+
+    fn search(&mut self, prefix: &str) {
+        if self.searching {
+            self.next_search = Some(prefix.to_string());
+            return;
+        }
+        let Some(index) = self.disk_index.clone() else {
+            return;
+        };
+        self.searching = true;
+        let prefix = prefix.to_string();
+        worker::spawn(move || {
+            let found = index.matches(&prefix, SUGGESTION_LIMIT);
+            worker::send(Message::HistorySearched(prefix, found));
+        });
+    }
+
+    /// Takes the disk index opened in the background.
+    pub fn indexed(&mut self, index: DiskIndex) {
+        self.disk_index = Some(index);
+    }
+
+    /// Takes the lines a search of the disk index found for `prefix`, and
+    /// starts the search waiting for it.
+    pub fn searched_for(&mut self, prefix: String, found: Vec<String>) {
+        if self.searches.len() == CACHED_SEARCHES {
+            self.searches.clear();
+        }
+        self.searches.insert(prefix, found);
+        self.searching = false;
+        if let Some(next) = self.next_search.take() {
+            if self.searched(&next).is_none() {
+                self.search(&next);
+            }
+        }
+    }
+
+    /// The command `index` entries back, 0 being the most recent.
+    pub fn get_command(&mut self, index: usize) -> Option<String> {
+        match self.session.get(index) {
+            Some(command) => Some(command.clone()),
+            None => self.file.as_mut()?.get(index - self.session.len()),
+        }
+    }
+
+    /// Every command in the history without repeats, newest first, e.g.
+    /// for a fuzzy finder. The file is read as the lines are taken, so a
+    /// worker can do it.
+    pub fn lines(&self) -> impl Iterator<Item = String> + Send + 'static {
+        let file = self
+            .file
+            .as_ref()
+            .and_then(|_| File::open(&self.path).ok())
+            .map(|f| {
+                let lines = BufReader::new(f).lines().map_while(Result::ok);
+                lines.map(|f| f.trim_end().to_string())
+            });
+        let mut seen = HashSet::new();
+        self.session
+            .clone()
+            .into_iter()
+            .chain(file.into_iter().flatten())
+            .filter(move |f| !f.trim().is_empty() && seen.insert(f.clone()))
+    }
+
+    /// The most recent commands, for completion and corrections.
+    pub fn recent(&mut self) -> Vec<String> {
+        (0..self.session.len() + CHUNK_LINES)
+            .map_while(|f| self.get_command(f))
+            .collect()
+    }
+
+    /// Takes the commands not saved yet, newest first, leaving them for
+    /// someone else to save.
+    pub fn take_unsaved(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.session)
+    }
+
+    /// Writes the commands added since the last flush to the front of the
+    /// history file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.session.is_empty() {
+            return Ok(());
+        }
+        let mut s = self
+            .session
+            .iter()
+            .filter(|f| !f.trim().is_empty())
+            .map(|f| f.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if !s.is_empty() {
+            s.push('\n')
+        };
+
+        self.session.clear();
+        self.prepend_to_file(s)?;
+        // Every line moved, so where the chunks start has to be found again.
+        if let Some(file) = &mut self.file {
+            *file = FileLines::new(self.path.clone());
+        }
+        Ok(())
     }
 
     fn prepend_to_file(&mut self, data: String) -> io::Result<()> {
@@ -68,52 +263,77 @@ impl History {
 
 impl Drop for History {
     fn drop(&mut self) {
-        let mut s = self
-            .commands
-            .iter()
-            .filter(|f| !f.trim().is_empty())
-            .enumerate()
-            .filter(|(i, _)| *i < self.new_commands_count as usize)
-            .map(|(_, a)| a.as_str())
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        if s.len() > 0 {
-            s.push_str("\n")
-        };
-
-        let _ = self.prepend_to_file(s);
+        let _ = self.flush();
     }
 }
 
-pub struct LineReader {
-    reader: BufReader<File>,
-    position: u64,
+/// The lines of the history file, newest first, read a chunk at a time.
+/// Where each chunk starts is remembered once found, so any line can be
+/// read again without keeping the file in memory.
+struct FileLines {
+    path: PathBuf,
+    /// Byte offsets of the chunks found so far.
+    chunks: Vec<u64>,
+    /// Set once the last chunk was read.
+    complete: bool,
+    cache: VecDeque<(usize, Vec<String>)>,
 }
 
-impl LineReader {
-    pub fn new(path: &PathBuf) -> io::Result<Self> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        Ok(Self {
-            reader,
-            position: 0,
-        })
+impl FileLines {
+    fn new(path: PathBuf) -> Self {
+        FileLines {
+            path,
+            chunks: vec![0],
+            complete: false,
+            cache: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, line: usize) -> Option<String> {
+        let chunk = self.chunk(line / CHUNK_LINES)?;
+        chunk.get(line % CHUNK_LINES).cloned()
+    }
+
+    fn chunk(&mut self, number: usize) -> Option<&Vec<String>> {
+        while self.chunks.len() <= number && !self.complete {
+            self.read_chunk(self.chunks.len() - 1).ok()?;
+        }
+        if !self.cache.iter().any(|f| f.0 == number) {
+            self.read_chunk(number).ok()?;
+        }
+        self.cache.iter().find(|f| f.0 == number).map(|f| &f.1)
     }
 
-    pub fn read_lines(&mut self, count: usize) -> io::Result<Vec<String>> {
+    /// Reads chunk `number`, whose start is known, into the cache, noting
+    /// where the next one starts.
+    fn read_chunk(&mut self, number: usize) -> io::Result<()> {
+        let Some(&start) = self.chunks.get(number) else {
+            return Ok(());
+        };
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(start))?;
         let mut lines = Vec::new();
-        let _ = self.reader.seek(SeekFrom::Start(self.position));
-        for _ in 0..count {
+        let mut position = start;
+        while lines.len() < CHUNK_LINES {
             let mut line = String::new();
-            let bytes_read = self.reader.read_line(&mut line)?;
+            let bytes_read = reader.read_line(&mut line)?;
             if bytes_read == 0 {
                 break;
-            } else {
-                self.position += bytes_read as u64;
             }
+            position += bytes_read as u64;
             lines.push(line.trim_end().to_string());
         }
-        Ok(lines)
+        if number + 1 == self.chunks.len() {
+            if lines.len() == CHUNK_LINES {
+                self.chunks.push(position);
+            } else {
+                self.complete = true;
+            }
+        }
+        if self.cache.len() == CACHED_CHUNKS {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((number, lines));
+        Ok(())
     }
 }
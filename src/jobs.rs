@@ -0,0 +1,401 @@
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Parses a signal given as a number or a name, with or without `SIG`,
+/// in any case.
+pub fn parse_signal(name: &str) -> Option<libc::c_int> {
+    if let Ok(number) = name.parse::<libc::c_int>() {
+        return Some(number);
+    }
+    let name = name.to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|f| f.0 == name).map(|f| f.1)
+}
+
+pub fn signal_name(signal: libc::c_int) -> Option<&'static str> {
+    SIGNALS.iter().find(|f| f.1 == signal).map(|f| f.0)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+impl JobState {
+    pub fn describe(&self) -> String {
+        match self {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done(0) => "Done".to_string(),
+            JobState::Done(status) if status - 128 == libc::SIGKILL => "Killed".to_string(),
+            JobState::Done(status) if status - 128 == libc::SIGTERM => "Terminated".to_string(),
+            JobState::Done(status) => format!("Exit {}", status),
+        }
+    }
+}
+
+pub struct Job {
+    pub id: usize,
+    /// The last process of the pipeline, whose status is the job's.
+    pub pid: libc::pid_t,
+    /// The other processes of the pipeline that weren't reaped yet.
+    pub pending: Vec<libc::pid_t>,
+    /// The process group of the whole pipeline, which signals go to.
+    pub pgid: libc::pid_t,
+    pub command: String,
+    pub state: JobState,
+    /// Set by `disown -h`: the job stays listed but isn't sent SIGHUP when
+    /// the shell exits.
+    pub no_hup: bool,
+}
+
+/// Commands started with `&`, numbered from 1 like in other shells. The
+/// most recently added job is the current one (`%+`).
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Processes of disowned jobs, only kept to be reaped silently.
+    disowned: Vec<libc::pid_t>,
+}
+
+impl JobTable {
+    /// Adds a pipeline given the pids of its processes, the last one
+    /// being the job's own.
+    pub fn add(
+        &mut self,
+        mut pids: Vec<libc::pid_t>,
+        pgid: libc::pid_t,
+        command: &str,
+        state: JobState,
+    ) -> usize {
+        let id = self.jobs.last().map_or(1, |f| f.id + 1);
+        let pid = pids.pop().unwrap_or(pgid);
+        self.jobs.push(Job {
+            id,
+            pid,
+            pending: pids,
+            pgid,
+            command: command.to_string(),
+            state,
+            no_hup: false,
+        });
+        id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// `+` for the current job and `-` for the previous one, as in `jobs`.
+    pub fn marker(&self, id: usize) -> char {
+        let mut ids = self.jobs.iter().rev().map(|f| f.id);
+        if ids.next() == Some(id) {
+            '+'
+        } else if ids.next() == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|f| f.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|f| f.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// Forgets a job without waiting for it or signaling it later.
+    pub fn disown(&mut self, id: usize) {
+        if let Some(job) = self.remove(id) {
+            self.disowned.push(job.pid);
+            self.disowned.extend(job.pending);
+        }
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|f| f.id).collect()
+    }
+
+    /// Resolves a job spec: `%N`, `%%`/`%+` (current), `%-` (previous), or
+    /// `%name`, the job whose command starts with `name`. No spec means the
+    /// current job.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = spec.unwrap_or("%+");
+        let Some(name) = spec.strip_prefix('%') else {
+            return Err(format!("{}: no such job", spec));
+        };
+        let job = match name {
+            "" | "%" | "+" => self.jobs.last(),
+            "-" => self.jobs.iter().rev().nth(1),
+            name => match name.parse::<usize>() {
+                Ok(id) => self.jobs.iter().find(|f| f.id == id),
+                Err(_) => self.jobs.iter().rev().find(|f| f.command.starts_with(name)),
+            },
+        };
+        job.map(|f| f.id)
+            .ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    /// Sends SIGHUP to every job not exempted with `disown -h`, waking
+    /// stopped ones so they get it.
+    pub fn hang_up(&self) {
+        for job in self.jobs.iter().filter(|f| !f.no_hup) {
+            let _ = signal(-job.pgid, libc::SIGHUP);
+            if job.state == JobState::Stopped {
+                let _ = signal(-job.pgid, libc::SIGCONT);
+            }
+        }
+    }
+
+    /// Polls every job without blocking and removes the ones that finished,
+    /// returning them so they can be reported.
+    pub fn reap(&mut self) -> Vec<Job> {
+        self.disowned
+            .retain(|pid| poll(*pid, libc::WNOHANG).is_none());
+        for job in &mut self.jobs {
+            if let Some(state) = poll(job.pid, libc::WNOHANG) {
+                job.state = state;
+            }
+            job.pending
+                .retain(|pid| !matches!(poll(*pid, libc::WNOHANG), Some(JobState::Done(_))));
+        }
+        let (done, running): (Vec<_>, _) = self
+            .jobs
+            .drain(..)
+            .partition(|f| matches!(f.state, JobState::Done(_)));
+        self.jobs = running;
+        // Earlier stages can outlive the last one, e.g. `cat | true`.
+        for job in &done {
+            self.disowned.extend(&job.pending);
+        }
+        done
+    }
+}
+
+/// Resources used by the processes of a job, as reported when they were
+/// reaped.
+#[derive(Default, Clone, Copy)]
+pub struct Usage {
+    pub user: Duration,
+    pub sys: Duration,
+    /// The largest resident set of any one process, in kilobytes.
+    pub max_rss: i64,
+    pub major_faults: i64,
+    pub minor_faults: i64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: Usage) {
+        self.user += other.user;
+        self.sys += other.sys;
+        self.max_rss = self.max_rss.max(other.max_rss);
+        self.major_faults += other.major_faults;
+        self.minor_faults += other.minor_faults;
+    }
+
+    fn from_rusage(usage: &libc::rusage) -> Self {
+        Usage {
+            user: duration(usage.ru_utime),
+            sys: duration(usage.ru_stime),
+            max_rss: usage.ru_maxrss,
+            major_faults: usage.ru_majflt,
+            minor_faults: usage.ru_minflt,
+        }
+    }
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+}
+
+/// User and system CPU time of all children that have been waited for.
+pub fn children_cpu_time() -> (Duration, Duration) {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) };
+    (duration(usage.ru_utime), duration(usage.ru_stime))
+}
+
+/// Blocks until the job exits or is stopped.
+pub fn wait(pid: libc::pid_t) -> JobState {
+    wait_with_usage(pid, &mut Usage::default())
+}
+
+/// Like `wait`, adding what the process used to `usage` once it exited.
+pub fn wait_with_usage(pid: libc::pid_t, usage: &mut Usage) -> JobState {
+    loop {
+        match poll_with_usage(pid, 0, usage) {
+            // Reported when the job was just sent SIGCONT.
+            Some(JobState::Running) => continue,
+            state => return state.unwrap_or(JobState::Done(1)),
+        }
+    }
+}
+
+/// Like `wait_with_usage`, but gives up at `deadline`, returning `None`
+/// while the process still runs.
+pub fn wait_until(pid: libc::pid_t, deadline: Instant, usage: &mut Usage) -> Option<JobState> {
+    loop {
+        match poll_with_usage(pid, libc::WNOHANG, usage) {
+            None | Some(JobState::Running) => {}
+            state => return state,
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Sends `signal` to a pipeline: its whole process group with job
+/// control, which also reaches what its commands started, or else each
+/// of its processes.
+pub fn signal_pipeline(pids: &[libc::pid_t], pgid: libc::pid_t, signal: libc::c_int) {
+    if JOB_CONTROL.load(Ordering::SeqCst) {
+        unsafe { libc::kill(-pgid, signal) };
+    } else {
+        for pid in pids {
+            unsafe { libc::kill(*pid, signal) };
+        }
+    }
+}
+
+/// Signals of the terminal keys the shell itself ignores: Ctrl+C and
+/// Ctrl+Z are only meant for the foreground job.
+const SHELL_IGNORED: &[libc::c_int] = &[libc::SIGINT, libc::SIGTSTP];
+
+/// Only an interactive shell moves jobs between process groups and hands
+/// them the terminal; a script stays in the group it was started in.
+static JOB_CONTROL: AtomicBool = AtomicBool::new(false);
+
+pub fn init_job_control() {
+    JOB_CONTROL.store(true, Ordering::SeqCst);
+    for signal in SHELL_IGNORED {
+        unsafe { libc::signal(*signal, libc::SIG_IGN) };
+    }
+}
+
+/// What a signal goes back to when its trap is removed: ignored if the
+/// shell ignores it for job control, else the default.
+pub fn default_handler(signal: libc::c_int) -> libc::sighandler_t {
+    if JOB_CONTROL.load(Ordering::SeqCst) && SHELL_IGNORED.contains(&signal) {
+        libc::SIG_IGN
+    } else {
+        libc::SIG_DFL
+    }
+}
+
+/// Puts a child in process group `pgid`, or a new one of its own for 0. A
+/// foreground child takes the terminal itself before running, so it can't
+/// be stopped for reading it before the shell gets to hand it over.
+pub fn prepare_child(command: &mut Command, pgid: libc::pid_t, foreground: bool) {
+    if !JOB_CONTROL.load(Ordering::SeqCst) {
+        return;
+    }
+    command.process_group(pgid);
+    unsafe {
+        command.pre_exec(move || {
+            if foreground {
+                set_foreground(libc::getpgrp());
+            }
+            // Ignored dispositions survive exec; the shell's must not.
+            for signal in SHELL_IGNORED {
+                libc::signal(*signal, libc::SIG_DFL);
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Hands the terminal to a process group so it can read input and receives
+/// Ctrl+C. The shell is in the background while it does so and would be
+/// stopped by SIGTTOU without ignoring it.
+pub fn set_foreground(pgid: libc::pid_t) {
+    if !JOB_CONTROL.load(Ordering::SeqCst) {
+        return;
+    }
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+/// Gives the terminal back to the shell after a foreground job.
+pub fn take_terminal() {
+    set_foreground(unsafe { libc::getpgrp() });
+}
+
+pub fn signal(pid: libc::pid_t, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid, signal) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn poll(pid: libc::pid_t, flags: libc::c_int) -> Option<JobState> {
+    poll_with_usage(pid, flags, &mut Usage::default())
+}
+
+fn poll_with_usage(pid: libc::pid_t, flags: libc::c_int, usage: &mut Usage) -> Option<JobState> {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let flags = flags | libc::WUNTRACED | libc::WCONTINUED;
+    let result = unsafe { libc::wait4(pid, &mut status, flags, &mut rusage) };
+    if result > 0 && (libc::WIFEXITED(status) || libc::WIFSIGNALED(status)) {
+        usage.add(Usage::from_rusage(&rusage));
+    }
+    match result {
+        0 => None,
+        // Already reaped or not our child; nothing left to wait for.
+        -1 => Some(JobState::Done(1)),
+        _ if libc::WIFSTOPPED(status) => Some(JobState::Stopped),
+        _ if libc::WIFCONTINUED(status) => Some(JobState::Running),
+        _ if libc::WIFSIGNALED(status) => Some(JobState::Done(128 + libc::WTERMSIG(status))),
+        _ => Some(JobState::Done(libc::WEXITSTATUS(status))),
+    }
+}
@@ -0,0 +1,236 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::BTreeMap;
+
+/// Something a key can do at the prompt.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditorAction {
+    /// Runs the line, or continues it when unfinished.
+    AcceptLine,
+    Complete,
+    /// Moves right, or at the end of the line accepts the suggestion.
+    ForwardChar,
+    BackwardChar,
+    BackwardDeleteChar,
+    /// Cycles suggestions, or goes back in history.
+    PreviousHistory,
+    NextHistory,
+    /// Discards the line.
+    CancelLine,
+    AcceptSuggestion,
+    ClearScreen,
+    /// Replaces the line with a history line picked with fzf.
+    FuzzyHistory,
+    /// Inserts files picked with fzf in place of the word before the
+    /// cursor.
+    FuzzyFile,
+    /// Copies the line to the system clipboard.
+    CopyLine,
+}
+
+/// Names of the actions in the `[keys]` table.
+pub const ACTIONS: &[(&str, EditorAction)] = &[
+    ("accept-line", EditorAction::AcceptLine),
+    ("complete", EditorAction::Complete),
+    ("forward-char", EditorAction::ForwardChar),
+    ("backward-char", EditorAction::BackwardChar),
+    ("backward-delete-char", EditorAction::BackwardDeleteChar),
+    ("previous-history", EditorAction::PreviousHistory),
+    ("next-history", EditorAction::NextHistory),
+    ("cancel-line", EditorAction::CancelLine),
+    ("accept-suggestion", EditorAction::AcceptSuggestion),
+    ("clear-screen", EditorAction::ClearScreen),
+    ("fuzzy-history", EditorAction::FuzzyHistory),
+    ("fuzzy-file", EditorAction::FuzzyFile),
+    ("copy-line", EditorAction::CopyLine),
+];
+
+impl EditorAction {
+    /// What the action does, shown by `help`.
+    pub fn description(self) -> &'static str {
+        match self {
+            EditorAction::AcceptLine => "Run the command, or continue an unfinished line",
+            EditorAction::Complete => "Complete the current word or list candidates",
+            EditorAction::ForwardChar => "Move the cursor right, or accept the suggestion",
+            EditorAction::BackwardChar => "Move the cursor left",
+            EditorAction::BackwardDeleteChar => "Delete the character before the cursor",
+            EditorAction::PreviousHistory => "Cycle suggestions, or go back in history",
+            EditorAction::NextHistory => "Cycle suggestions back, or go forward in history",
+            EditorAction::CancelLine => "Discard the line",
+            EditorAction::AcceptSuggestion => "Accept the suggestion",
+            EditorAction::ClearScreen => "Clear the screen",
+            EditorAction::FuzzyHistory => "Pick a history line with fzf",
+            EditorAction::FuzzyFile => "Pick files with fzf",
+            EditorAction::CopyLine => "Copy the line to the clipboard",
+        }
+    }
+}
+
+/// The keys bound when the `[keys]` table doesn't bind them. Keys without
+/// a modifier here do the same with any modifier held.
+const DEFAULT_KEYS: &[(&str, EditorAction)] = &[
+    ("enter", EditorAction::AcceptLine),
+    ("tab", EditorAction::Complete),
+    ("right", EditorAction::ForwardChar),
+    ("left", EditorAction::BackwardChar),
+    ("backspace", EditorAction::BackwardDeleteChar),
+    ("up", EditorAction::PreviousHistory),
+    ("down", EditorAction::NextHistory),
+    ("ctrl-c", EditorAction::CancelLine),
+    ("ctrl-r", EditorAction::FuzzyHistory),
+    ("ctrl-t", EditorAction::FuzzyFile),
+];
+
+#[derive(Clone)]
+pub enum Binding {
+    Action(EditorAction),
+    /// Runs this command line in place of what was typed.
+    Command(String),
+}
+
+/// The `[keys]` table, looked up before the default bindings, with each
+/// chord as it was written.
+#[derive(Default)]
+pub struct KeyBindings {
+    bindings: Vec<(String, (KeyModifiers, KeyCode), Binding)>,
+}
+
+impl KeyBindings {
+    /// Maps chords like `"ctrl-r"` or `"alt-."` to an action name, or to
+    /// command text when the value names no action. Invalid chords are
+    /// reported and skipped.
+    pub fn from_config(keys: &BTreeMap<String, String>) -> Self {
+        let mut bindings = vec![];
+        for (chord, value) in keys {
+            let Some(key) = parse_chord(chord) else {
+                eprintln!("ash: keys: invalid key `{}`", chord);
+                continue;
+            };
+            let binding = match ACTIONS.iter().find(|f| f.0 == value) {
+                Some((_, action)) => Binding::Action(*action),
+                None => Binding::Command(value.clone()),
+            };
+            bindings.push((chord.clone(), key, binding));
+        }
+        KeyBindings { bindings }
+    }
+
+    pub fn get(&self, event: &KeyEvent) -> Option<Binding> {
+        let key = normalize(event.modifiers, event.code);
+        if let Some((_, _, binding)) = self.bindings.iter().find(|f| f.1 == key) {
+            return Some(binding.clone());
+        }
+        default_action(key).map(Binding::Action)
+    }
+
+    /// Every bound key and what it does, the `[keys]` table first, for
+    /// `help`.
+    pub fn describe(&self) -> Vec<(String, String)> {
+        let mut keys = self
+            .bindings
+            .iter()
+            .map(|(chord, _, binding)| {
+                let description = match binding {
+                    Binding::Action(action) => action.description().to_string(),
+                    Binding::Command(command) => format!("Run `{}`", command),
+                };
+                (chord.clone(), description)
+            })
+            .collect::<Vec<_>>();
+        for (chord, action) in DEFAULT_KEYS {
+            if !self.rebinds(chord) {
+                keys.push((chord.to_string(), action.description().to_string()));
+            }
+        }
+        keys
+    }
+
+    /// The names of the actions no key is bound to, for `help` to point
+    /// at the `[keys]` table.
+    pub fn unbound_actions(&self) -> Vec<&'static str> {
+        let bound = |action: EditorAction| {
+            self.bindings
+                .iter()
+                .any(|f| matches!(f.2, Binding::Action(bound) if bound == action))
+                || DEFAULT_KEYS
+                    .iter()
+                    .any(|(chord, default)| *default == action && !self.rebinds(chord))
+        };
+        ACTIONS
+            .iter()
+            .filter(|f| !bound(f.1))
+            .map(|f| f.0)
+            .collect()
+    }
+
+    /// Whether the `[keys]` table binds a default key to something else.
+    fn rebinds(&self, chord: &str) -> bool {
+        let key = parse_chord(chord);
+        self.bindings.iter().any(|f| Some(f.1) == key)
+    }
+}
+
+fn default_action((modifiers, code): (KeyModifiers, KeyCode)) -> Option<EditorAction> {
+    DEFAULT_KEYS.iter().find_map(|(chord, action)| {
+        let (needed, key) = parse_chord(chord)?;
+        (key == code && modifiers.contains(needed)).then_some(*action)
+    })
+}
+
+/// Parses `-`-separated modifiers (`ctrl`, `alt`, `shift`) followed by a
+/// key: a single character, `f1`-`f12`, or a name like `enter` or `up`.
+pub fn parse_chord(chord: &str) -> Option<(KeyModifiers, KeyCode)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = chord;
+    while let Some((modifier, after)) = rest.split_once('-').filter(|f| !f.1.is_empty()) {
+        modifiers |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "c" => KeyModifiers::CONTROL,
+            "alt" | "meta" | "m" => KeyModifiers::ALT,
+            "shift" | "s" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        rest = after;
+    }
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "esc" | "escape" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "delete" | "del" => KeyCode::Delete,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name => KeyCode::F(
+                name.strip_prefix('f')?
+                    .parse()
+                    .ok()
+                    .filter(|f| (1..=12).contains(f))?,
+            ),
+        },
+    };
+    Some(normalize(modifiers, code))
+}
+
+/// Terminals report Ctrl+letter in lowercase and Shift+letter as the
+/// uppercase letter, so Shift is only kept for keys without a character.
+fn normalize(modifiers: KeyModifiers, code: KeyCode) -> (KeyModifiers, KeyCode) {
+    match code {
+        KeyCode::Char(c) => {
+            let c = if modifiers.contains(KeyModifiers::CONTROL) {
+                c.to_ascii_lowercase()
+            } else {
+                c
+            };
+            (modifiers - KeyModifiers::SHIFT, KeyCode::Char(c))
+        }
+        code => (modifiers, code),
+    }
+}
@@ -0,0 +1,38 @@
+//! The shell behind the `ash` binary. `Shell` runs the line editor or
+//! scripts, and the parser, history, completion engines, and edit buffer
+//! can be used on their own, e.g. to embed a prompt in another tool.
+pub mod autocomplete;
+pub mod buffer;
+pub mod config;
+pub mod error;
+pub mod history;
+pub mod parser;
+pub mod plugin;
+pub mod shell;
+pub mod suggestion;
+
+mod about;
+mod alias;
+mod bridge;
+mod builtins;
+mod control;
+mod devenv;
+mod frecency;
+mod fzf;
+mod git;
+mod glob;
+mod hash;
+mod jobs;
+mod keys;
+mod log;
+mod project;
+mod prompt;
+mod recovery;
+mod render;
+mod terminal;
+mod theme;
+mod timing;
+mod tmux;
+mod trap;
+mod worker;
+mod zoxide;
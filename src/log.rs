@@ -0,0 +1,140 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+
+/// A log file this large is moved to `ash.log.old` when the shell starts
+/// logging, so leaving `ASH_LOG` set doesn't fill the disk.
+const ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+/// Diagnostics for bug reports, written to `ash.log` in the state
+/// directory when `ASH_LOG` names a level. Each line is one event in
+/// logfmt: `ts=… pid=… level=debug event=spawn command="ls" …`.
+struct Logger {
+    level: Level,
+    file: Mutex<File>,
+}
+
+fn logger() -> Option<&'static Logger> {
+    static LOGGER: OnceLock<Option<Logger>> = OnceLock::new();
+    LOGGER
+        .get_or_init(|| {
+            let level = Level::parse(&env::var("ASH_LOG").ok()?)?;
+            let path = config::state_dir().join("ash.log");
+            if fs::metadata(&path).is_ok_and(|f| f.len() > ROTATE_BYTES) {
+                let _ = fs::rename(&path, path.with_extension("log.old"));
+            }
+            let _ = fs::create_dir_all(config::state_dir());
+            // Command lines can hold secrets, so only the user may read it,
+            // also when an older shell created it.
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(&path)
+                .ok()?;
+            let _ = file.set_permissions(fs::Permissions::from_mode(0o600));
+            Some(Logger {
+                level,
+                file: Mutex::new(file),
+            })
+        })
+        .as_ref()
+}
+
+pub fn enabled(level: Level) -> bool {
+    logger().is_some_and(|f| level <= f.level)
+}
+
+/// Writes one event with its fields, whose values are already formatted.
+pub fn write(level: Level, event: &str, fields: &[(&str, String)]) {
+    let Some(logger) = logger() else {
+        return;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "ts={}.{:03} pid={} level={} event={}",
+        now.as_secs(),
+        now.subsec_millis(),
+        std::process::id(),
+        level.name(),
+        event
+    );
+    for (key, value) in fields {
+        let _ = write!(line, " {}={}", key, value);
+    }
+    line.push('\n');
+    let _ = logger.file.lock().unwrap().write_all(line.as_bytes());
+}
+
+/// Logs `event` at `level` with `key = value` fields, each value written
+/// with `{:?}` so strings are quoted. Nothing is formatted unless the level
+/// is enabled.
+macro_rules! event {
+    ($level:expr, $event:literal $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write(
+                $level,
+                $event,
+                &[$((stringify!($key), format!("{:?}", $value))),*],
+            );
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::event!($crate::log::Level::Error, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::event!($crate::log::Level::Info, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::event!($crate::log::Level::Debug, $($arg)*) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::event!($crate::log::Level::Trace, $($arg)*) };
+}
+
+pub(crate) use {debug, error, event, info, trace};
@@ -1,15 +1,109 @@
-use shell::Shell;
-mod about;
-mod autocomplete;
-mod history;
-mod parser;
-mod shell;
-mod suggestion;
-extern crate toml;
+use a_shell::parser::CommandParser;
+use a_shell::shell::Shell;
+use std::io::{self, BufRead, IsTerminal, Read};
+use std::{env, fs, process};
+
 fn main() {
-    let shell = Shell::new();
+    let mut args = env::args().peekable();
+    // `login` and the like start login shells with a `-` before the name.
+    let mut login = args.next().is_some_and(|f| f.starts_with('-'));
+    let mut restricted = false;
+    let mut command = None;
+    let mut parse = false;
+    while let Some(arg) = args.next_if(|f| f.starts_with('-')) {
+        match arg.as_str() {
+            "-l" | "--login" => login = true,
+            "-r" => restricted = true,
+            "--parse" => parse = true,
+            "-c" => match args.next() {
+                Some(script) => command = Some(script),
+                None => fail("-c: option requires an argument"),
+            },
+            "--" => break,
+            _ => fail(&format!("{}: invalid option", arg)),
+        }
+    }
+    let rest = args.collect::<Vec<_>>();
+    if parse {
+        print_syntax(&rest);
+    }
+    // `-c` and scripts run without the line editor, as does input piped
+    // into the shell.
+    let script = match (command, rest.split_first()) {
+        (Some(command), _) => {
+            let name = rest.first().cloned().unwrap_or_else(|| "ash".to_string());
+            Some((command, name, rest.iter().skip(1).cloned().collect()))
+        }
+        (None, Some((path, args))) => match fs::read_to_string(path) {
+            Ok(script) => Some((script, path.clone(), args.to_vec())),
+            Err(e) => fail(&format!("{}: {}", path, e)),
+        },
+        (None, None) if !io::stdin().is_terminal() => {
+            let mut script = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut script) {
+                fail(&format!("stdin: {}", e));
+            }
+            Some((script, "ash".to_string(), vec![]))
+        }
+        (None, None) => None,
+    };
+
+    let shell = Shell::new(script.is_none());
     match shell {
-        Ok(mut app) => app.init(),
-        Err(e) => println!("Cannot init {:?}", e),
+        Ok(mut app) => {
+            if login {
+                app.login();
+            }
+            if restricted {
+                app.restrict();
+            }
+            match script {
+                Some((script, name, args)) => app.run_script(&script, &name, &args),
+                None => app.init(),
+            }
+        }
+        Err(e) => fail(&format!("cannot start: {}", e)),
     }
 }
+
+/// Prints how each line would be read as a line of JSON, without running
+/// anything: the arguments, or else the lines of stdin, where a line that
+/// isn't finished is continued by the next. Exits with 2 when any line is
+/// unfinished or has a syntax error.
+fn print_syntax(lines: &[String]) -> ! {
+    let parser = CommandParser::new();
+    let mut valid = true;
+    let mut print = |line: &str| {
+        let syntax = parser.syntax(line);
+        valid &= syntax.is_valid();
+        match serde_json::to_string(&syntax) {
+            Ok(json) => println!("{}", json),
+            Err(e) => fail(&format!("--parse: {}", e)),
+        }
+    };
+    if !lines.is_empty() {
+        lines.iter().for_each(|f| print(f));
+    } else {
+        let mut pending = vec![];
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => pending.push(line),
+                Err(e) => fail(&format!("stdin: {}", e)),
+            }
+            let joined = parser.join_lines(&pending);
+            if !parser.is_incomplete(&joined) {
+                print(&joined);
+                pending.clear();
+            }
+        }
+        if !pending.is_empty() {
+            print(&parser.join_lines(&pending));
+        }
+    }
+    process::exit(if valid { 0 } else { 2 });
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("ash: {}", message);
+    process::exit(2);
+}
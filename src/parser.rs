@@ -1,8 +1,13 @@
+use serde::Serialize;
+use std::collections::HashMap;
 use std::env;
+use std::sync::OnceLock;
 
 use toml::Table;
 
-#[derive(Debug)]
+use crate::config::home_dir;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParsedCommand {
     pub command: String,
     pub args: Vec<String>,
@@ -10,34 +15,67 @@ pub struct ParsedCommand {
 }
 
 pub struct CommandParser {
-    metadata: Table,
+    /// Shell variables like `?`, looked up before the environment and not
+    /// passed on to commands.
+    variables: HashMap<String, String>,
+}
+
+impl Default for CommandParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CommandParser {
     pub fn new() -> Self {
-        let metadata = toml::from_str(include_str!("./meta.toml")).unwrap();
-        CommandParser { metadata }
+        CommandParser {
+            variables: HashMap::new(),
+        }
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: String) {
+        self.variables.insert(name.to_string(), value);
     }
 
     pub fn parse(&self, command: &str) -> ParsedCommand {
-        let args = self.split_command_line(&command);
-        let mut iterable = args.iter();
-        let command = iterable.next().map_or("", |v| &v).to_string();
-        let mut args = iterable
-            .take(args.len() - 1)
-            .map(|f| f.clone())
+        self.parse_words(command).0
+    }
+
+    /// Parses a command and takes its output redirection out of the
+    /// arguments. Only a word starting with an unquoted `>` is one, so
+    /// `echo '>x'` prints `>x` rather than writing to `x`.
+    pub fn parse_redirected(
+        &self,
+        command: &str,
+    ) -> (ParsedCommand, Result<Option<Redirection>, String>) {
+        let (mut parsed, operators) = self.parse_words(command);
+        let redirection = take_redirection(&mut parsed.args, &operators);
+        (parsed, redirection)
+    }
+
+    /// The parsed command, and for each argument whether it could be an
+    /// operator: that it starts out unquoted.
+    fn parse_words(&self, command: &str) -> (ParsedCommand, Vec<bool>) {
+        let mut words = self.split_command_line(command).into_iter();
+        let command = words.next().map_or(String::new(), |f| f.text);
+        let words = words.collect::<Vec<_>>();
+        let operators = words
+            .iter()
+            .map(|f| matches!(f.first, Quoting::None))
+            .collect();
+        let mut args = words
+            .into_iter()
+            .map(|f| match f.first {
+                Quoting::None if f.text.starts_with('~') => self.parse_path(&f.text).join("/"),
+                Quoting::None | Quoting::Double if f.text.starts_with('$') => {
+                    self.replace_env_vars(&f.text)
+                }
+                _ => f.text,
+            })
             .collect::<Vec<_>>();
-        args.iter_mut().for_each(|f| {
-            if f.starts_with("~") {
-                *f = self.parse_path(f).join("/");
-            }
-            if f.starts_with("$") {
-                *f = self.replace_env_vars(f);
-            }
-        });
         let path = args.last().map_or("", |f| f).to_owned();
         let paths = self.parse_path(&path);
-        let meta = self.metadata.get(
+        let meta = metadata().get(
             command
                 .split_whitespace()
                 .collect::<Vec<_>>()
@@ -46,63 +84,164 @@ impl CommandParser {
         );
 
         if !path.is_empty() && meta.is_some() && meta.unwrap().get("expects").is_some() {
-            match args.last_mut() {
-                Some(arg) => *arg = paths.join("/"),
-                None => todo!(),
+            if let Some(arg) = args.last_mut() {
+                *arg = paths.join("/");
             }
         }
 
-        ParsedCommand {
+        let parsed = ParsedCommand {
             command,
             args,
             paths,
+        };
+        (parsed, operators)
+    }
+
+    /// How the shell reads `line`, without running any of it. Aliases
+    /// aren't expanded, as they come from the config of a running shell.
+    pub fn syntax(&self, line: &str) -> LineSyntax {
+        let (rest, background) = split_background(strip_terminator(line));
+        let pipelines = split_pipelines(rest)
+            .map(|pipeline| {
+                split_commands(pipeline.trim())
+                    .map(|f| self.command_syntax(f.trim()))
+                    .collect()
+            })
+            .collect();
+        LineSyntax {
+            incomplete: self.is_incomplete(line),
+            background,
+            pipelines,
         }
     }
 
-    fn split_command_line(&self, input: &str) -> Vec<String> {
-        let mut args = Vec::new();
-        let mut current = String::new();
-        let mut in_quotes = false;
-        let mut quote_type: Option<char> = None;
+    fn command_syntax(&self, command: &str) -> CommandSyntax {
+        let (
+            ParsedCommand {
+                command: name,
+                args,
+                ..
+            },
+            redirection,
+        ) = self.parse_redirected(command);
+        let (redirection, error) = match redirection {
+            Ok(redirection) => (redirection, None),
+            Err(e) => (None, Some(e)),
+        };
+        CommandSyntax {
+            words: self.split_command_line(command),
+            command: name,
+            args,
+            redirection,
+            error,
+        }
+    }
 
-        for c in input.chars() {
-            match c {
-                '"' | '\'' => {
-                    if in_quotes && quote_type == Some(c) {
-                        in_quotes = false;
-                        quote_type = None;
-                    } else if !in_quotes {
-                        in_quotes = true;
-                        quote_type = Some(c);
-                    } else {
-                        current.push(c);
-                    }
-                }
-                ' ' if !in_quotes => {
-                    if !current.is_empty() {
-                        args.push(current.clone());
-                        current.clear();
-                    }
+    /// Whether the line can't be run yet and another line should be read:
+    /// a trailing backslash, an unclosed quote, or a trailing `|`/`&&`.
+    pub fn is_incomplete(&self, input: &str) -> bool {
+        let trimmed = input.trim_end();
+        self.ends_with_escape(input)
+            || self.has_open_quote(input)
+            || trimmed.ends_with('|')
+            || trimmed.ends_with("&&")
+    }
+
+    /// Joins the lines of a multi-line input: a backslash-newline disappears,
+    /// a newline inside quotes is kept, and anything else becomes a space.
+    pub fn join_lines(&self, lines: &[String]) -> String {
+        let mut joined = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                if self.ends_with_escape(&joined) {
+                    joined.pop();
+                } else if self.has_open_quote(&joined) {
+                    joined.push('\n');
+                } else {
+                    joined.push(' ');
                 }
-                _ => {
-                    current.push(c);
+            }
+            joined.push_str(line);
+        }
+        joined
+    }
+
+    fn ends_with_escape(&self, input: &str) -> bool {
+        let backslashes = input.chars().rev().take_while(|c| *c == '\\').count();
+        backslashes % 2 == 1
+    }
+
+    fn has_open_quote(&self, input: &str) -> bool {
+        let mut quote_type: Option<char> = None;
+        let mut chars = input.chars();
+        while let Some(c) = chars.next() {
+            match (c, quote_type) {
+                ('\\', None | Some('"')) => {
+                    chars.next();
                 }
+                ('"' | '\'', None) => quote_type = Some(c),
+                (c, Some(open)) if c == open => quote_type = None,
+                _ => {}
             }
         }
+        quote_type.is_some()
+    }
 
-        if !current.is_empty() {
-            args.push(current);
+    pub fn get_metadata(&self, command: &str) -> Option<&toml::Value> {
+        metadata()
+            .get("commands")
+            .and_then(|commands| commands.get(command))
+    }
+
+    /// Splits the line into words, removing quotes and backslashes. A
+    /// backslash keeps the next character as it is, except inside single
+    /// quotes, and inside double quotes only before `"`, `\`, or `$`.
+    fn split_command_line(&self, input: &str) -> Vec<Word> {
+        let mut words = Vec::new();
+        let mut current: Option<Word> = None;
+        let mut quote_type: Option<char> = None;
+        let mut chars = input.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let (c, quoting) = match (c, quote_type) {
+                ('\'', Some('\'')) | ('"', Some('"')) => {
+                    quote_type = None;
+                    continue;
+                }
+                ('"' | '\'', None) => {
+                    quote_type = Some(c);
+                    // `''` is an empty word rather than nothing.
+                    current.get_or_insert_with(|| Word::new(quoting_of(c)));
+                    continue;
+                }
+                ('\\', None) => match chars.next() {
+                    Some(next) => (next, Quoting::Escaped),
+                    None => ('\\', Quoting::None),
+                },
+                ('\\', Some('"')) => match chars.next_if(|f| matches!(f, '"' | '\\' | '$')) {
+                    Some(next) => (next, Quoting::Escaped),
+                    None => ('\\', Quoting::Double),
+                },
+                (' ', None) => {
+                    words.extend(current.take());
+                    continue;
+                }
+                (c, open) => (c, open.map_or(Quoting::None, quoting_of)),
+            };
+            current
+                .get_or_insert_with(|| Word::new(quoting))
+                .text
+                .push(c);
         }
 
-        args
+        words.extend(current);
+        words
     }
 
     fn parse_path(&self, input: &str) -> Vec<String> {
         let mut input = input.to_string();
-        let userpath = &format!(
-            "/home/{}/",
-            env::var("USER").unwrap_or_else(|_| "Unknown".to_string())
-        );
+        let home = home_dir().display().to_string();
+        let userpath = &format!("{}/", home.trim_end_matches('/'));
 
         let home_indicators = ["~/", "~"];
 
@@ -114,11 +253,163 @@ impl CommandParser {
             input = format!("./{}", input);
         }
 
-        return input.split("/").map(|f| f.to_string()).collect::<Vec<_>>();
+        input.split("/").map(|f| f.to_string()).collect::<Vec<_>>()
     }
 
     fn replace_env_vars(&self, input: &str) -> String {
-        let val = env::var(input.replace("$", "")).unwrap_or_default();
-        return val;
+        let name = input.replace("$", "");
+        match self.variables.get(&name) {
+            Some(value) => value.clone(),
+            None => env::var(name).unwrap_or_default(),
+        }
+    }
+}
+
+/// What `meta.toml` says about commands, parsed the first time it's needed
+/// rather than at startup.
+fn metadata() -> &'static Table {
+    static METADATA: OnceLock<Table> = OnceLock::new();
+    METADATA.get_or_init(|| toml::from_str(include_str!("./meta.toml")).unwrap())
+}
+
+/// Takes a trailing `;` off a line, which ends it just as the newline
+/// does, e.g. in `exit;`. An escaped `\;` is left for the command.
+pub fn strip_terminator(line: &str) -> &str {
+    let trimmed = line.trim_end();
+    match trimmed.strip_suffix(';') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line,
+    }
+}
+
+/// Takes a trailing `&` off a line, which makes its last pipeline a
+/// background job, and says whether there was one.
+pub fn split_background(line: &str) -> (&str, bool) {
+    match line.trim_end().strip_suffix('&') {
+        Some(rest) if !rest.ends_with('&') => (rest, true),
+        _ => (line, false),
+    }
+}
+
+/// The pipelines of a line, each run only when the one before succeeded.
+pub fn split_pipelines(line: &str) -> impl Iterator<Item = &str> {
+    line.split(" && ")
+}
+
+/// The commands of a pipeline, each reading the output of the one before.
+pub fn split_commands(pipeline: &str) -> impl Iterator<Item = &str> {
+    pipeline.split(" | ")
+}
+
+/// Where a command's output goes instead of stdout: `> FILE` replaces the
+/// file, `>> FILE` appends to it, and `>| FILE` replaces it even with
+/// `noclobber`.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Redirection {
+    pub target: String,
+    pub append: bool,
+    pub force: bool,
+}
+
+/// Removes the first output redirection from the arguments of a command,
+/// looking only at those `operators` says start out unquoted. The file may
+/// be written right after the operator or as the next word.
+fn take_redirection(
+    args: &mut Vec<String>,
+    operators: &[bool],
+) -> Result<Option<Redirection>, String> {
+    let Some(index) = args
+        .iter()
+        .zip(operators)
+        .position(|(arg, operator)| *operator && arg.starts_with('>'))
+    else {
+        return Ok(None);
+    };
+    let operator = args.remove(index);
+    let (append, force, target) = if let Some(target) = operator.strip_prefix(">>") {
+        (true, false, target)
+    } else if let Some(target) = operator.strip_prefix(">|") {
+        (false, true, target)
+    } else {
+        (false, false, &operator[1..])
+    };
+    let target = match target {
+        "" if index < args.len() => args.remove(index),
+        "" => return Err("missing file after `>`".to_string()),
+        target => target.to_string(),
+    };
+    Ok(Some(Redirection {
+        target,
+        append,
+        force,
+    }))
+}
+
+/// A command line as the shell reads it, which `ash --parse` prints.
+#[derive(Serialize)]
+pub struct LineSyntax {
+    /// Whether the line needs more before it can run, like a closing quote.
+    pub incomplete: bool,
+    pub background: bool,
+    /// Each pipeline as its commands.
+    pub pipelines: Vec<Vec<CommandSyntax>>,
+}
+
+impl LineSyntax {
+    /// Whether the line would fail before running anything.
+    pub fn is_valid(&self) -> bool {
+        !self.incomplete && self.pipelines.iter().flatten().all(|f| f.error.is_none())
     }
 }
+
+#[derive(Serialize)]
+pub struct CommandSyntax {
+    /// The words as typed, quotes removed.
+    words: Vec<Word>,
+    command: String,
+    /// What the command gets, with `~` and variables expanded and the
+    /// redirection taken out.
+    args: Vec<String>,
+    redirection: Option<Redirection>,
+    error: Option<String>,
+}
+
+/// How the first character of a word was quoted, which decides whether a
+/// leading `~` or `$` is expanded.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Quoting {
+    None,
+    Single,
+    Double,
+    Escaped,
+}
+
+fn quoting_of(quote: char) -> Quoting {
+    match quote {
+        '\'' => Quoting::Single,
+        _ => Quoting::Double,
+    }
+}
+
+#[derive(Serialize)]
+struct Word {
+    text: String,
+    #[serde(rename = "quoting")]
+    first: Quoting,
+}
+
+impl Word {
+    fn new(first: Quoting) -> Self {
+        Word {
+            text: String::new(),
+            first,
+        }
+    }
+}
+
+/// Quotes `value` so the parser reads it back as one word, unchanged, e.g.
+/// when a listing is meant to be pasted back into the shell.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
@@ -0,0 +1,197 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::autocomplete::run_with_timeout;
+use crate::worker::{self, Message};
+
+const PLUGIN_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// What a plugin prints as JSON when run with `describe`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Manifest {
+    commands: Vec<PluginCommand>,
+    /// Commands whose arguments the plugin completes.
+    completions: Vec<String>,
+    /// Prompt placeholders the plugin renders.
+    segments: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PluginCommand {
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct CompletionReply {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+}
+
+#[derive(Deserialize)]
+struct Candidate {
+    value: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct SegmentReply {
+    #[serde(default)]
+    text: String,
+}
+
+/// Executables in the plugins directory that extend the shell. Each is
+/// asked once what it provides with `PLUGIN describe`, then run as:
+///
+/// - `PLUGIN run NAME ARGS...` for its commands, in the pipeline like any
+///   other command;
+/// - `PLUGIN complete '{"words": [...]}'` to complete the last word,
+///   answering `{"candidates": [{"value": ..., "description": ...}]}`;
+/// - `PLUGIN segment NAME '{"cwd": ..., "status": ...}'` to render the
+///   `{NAME}` prompt placeholder, answering `{"text": ...}`.
+#[derive(Default)]
+pub struct Plugins {
+    commands: HashMap<String, (PathBuf, String)>,
+    completers: HashMap<String, PathBuf>,
+    segments: HashMap<String, PathBuf>,
+    /// Rendered segments, kept until the next command like the git status.
+    rendered: HashMap<String, String>,
+    /// Plugins that didn't describe themselves, to be reported.
+    invalid: Vec<PathBuf>,
+}
+
+impl Plugins {
+    /// Loads the plugins in `dir` on a worker, so no slow `describe` holds
+    /// up the first prompt. They arrive as `Message::PluginsLoaded`.
+    pub fn load_in_background(dir: PathBuf) {
+        worker::spawn(move || worker::send(Message::PluginsLoaded(Plugins::load(&dir))));
+    }
+
+    fn load(dir: &Path) -> Self {
+        let mut plugins = Plugins::default();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return plugins;
+        };
+        let mut paths = entries
+            .filter_map(|f| f.ok().map(|f| f.path()))
+            .filter(|f| is_executable(f))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let mut command = Command::new(&path);
+            command.arg("describe");
+            let manifest = run_with_timeout(command, PLUGIN_TIMEOUT)
+                .ok()
+                .and_then(|f| serde_json::from_str::<Manifest>(&f).ok());
+            let Some(manifest) = manifest else {
+                plugins.invalid.push(path);
+                continue;
+            };
+            for command in manifest.commands {
+                plugins
+                    .commands
+                    .insert(command.name, (path.clone(), command.description));
+            }
+            for command in manifest.completions {
+                plugins.completers.insert(command, path.clone());
+            }
+            for segment in manifest.segments {
+                plugins.segments.insert(segment, path.clone());
+            }
+        }
+        plugins
+    }
+
+    pub fn has_invalid(&self) -> bool {
+        !self.invalid.is_empty()
+    }
+
+    /// Prints the plugins that had no valid description, once.
+    pub fn report_invalid(&mut self) {
+        for path in self.invalid.drain(..) {
+            eprintln!("ash: plugin {}: no valid description", path.display());
+        }
+    }
+
+    /// Whether the prompt `format` shows a segment of some plugin.
+    pub fn in_prompt(&self, format: &str) -> bool {
+        self.segments
+            .keys()
+            .any(|f| format.contains(&format!("{{{}}}", f)))
+    }
+
+    /// The plugin providing command `name`.
+    pub fn command(&self, name: &str) -> Option<&Path> {
+        self.commands.get(name).map(|f| f.0.as_path())
+    }
+
+    /// Names and descriptions of the plugin commands, sorted by name.
+    pub fn commands(&self) -> Vec<(&str, &str)> {
+        let mut commands = self
+            .commands
+            .iter()
+            .map(|(name, (_, description))| (name.as_str(), description.as_str()))
+            .collect::<Vec<_>>();
+        commands.sort();
+        commands
+    }
+
+    /// Candidates for the last of `words` from the plugin completing the
+    /// command, or `None` when no plugin does.
+    pub fn complete(&self, words: &[&str]) -> Option<Vec<(String, Option<String>)>> {
+        let plugin = self.completers.get(*words.first()?)?;
+        let request = serde_json::json!({ "words": words });
+        let mut command = Command::new(plugin);
+        command.arg("complete").arg(request.to_string());
+        let reply = run_with_timeout(command, PLUGIN_TIMEOUT)
+            .ok()
+            .and_then(|f| serde_json::from_str::<CompletionReply>(&f).ok())?;
+        let candidates = reply
+            .candidates
+            .into_iter()
+            .map(|f| (f.value, Some(f.description).filter(|f| !f.is_empty())))
+            .collect();
+        Some(candidates)
+    }
+
+    pub fn segment_names(&self) -> impl Iterator<Item = &String> {
+        self.segments.keys()
+    }
+
+    pub fn segment(&mut self, name: &str, cwd: &Path, status: i32) -> String {
+        if let Some(text) = self.rendered.get(name) {
+            return text.clone();
+        }
+        let Some(plugin) = self.segments.get(name) else {
+            return String::new();
+        };
+        let request = serde_json::json!({ "cwd": cwd, "status": status });
+        let mut command = Command::new(plugin);
+        command.arg("segment").arg(name).arg(request.to_string());
+        let text = run_with_timeout(command, PLUGIN_TIMEOUT)
+            .ok()
+            .and_then(|f| serde_json::from_str::<SegmentReply>(&f).ok())
+            .map(|f| f.text)
+            .unwrap_or_default();
+        self.rendered.insert(name.to_string(), text.clone());
+        text
+    }
+
+    /// Forgets the rendered segments; called after each command.
+    pub fn clear(&mut self) {
+        self.rendered.clear();
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|f| f.is_file() && f.permissions().mode() & 0o111 != 0)
+}
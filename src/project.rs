@@ -0,0 +1,93 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::{EnvValue, HooksConfig};
+
+pub const PROJECT_FILE: &str = ".ash.toml";
+
+/// A `.ash.toml` file: settings that apply while the cwd is in its
+/// directory or below.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub aliases: BTreeMap<String, String>,
+    pub env: BTreeMap<String, EnvValue>,
+    /// Hooks set here replace those of the config.
+    pub hooks: HooksConfig,
+}
+
+impl ProjectConfig {
+    pub fn parse(path: &Path, content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| format!("invalid {}: {}", path.display(), e))
+    }
+}
+
+/// The nearest `.ash.toml` at or above `cwd`.
+pub fn find_project_file(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|f| f.is_file())
+}
+
+/// Answers to "trust this `.ash.toml`?", stored as
+/// `yes|no<TAB>hash<TAB>path` lines. The hash is of the file's content, so
+/// an edited file gets asked about again.
+pub struct TrustStore {
+    path: PathBuf,
+    decisions: Vec<(bool, u64, PathBuf)>,
+}
+
+impl TrustStore {
+    pub fn load(path: PathBuf) -> Self {
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let decisions = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let trusted = match fields.next()? {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return None,
+                };
+                let hash = fields.next()?.parse().ok()?;
+                Some((trusted, hash, PathBuf::from(fields.next()?)))
+            })
+            .collect();
+        TrustStore { path, decisions }
+    }
+
+    /// The answer given for this version of the file, if any.
+    pub fn decision(&self, file: &Path, content: &str) -> Option<bool> {
+        let hash = hash(content);
+        self.decisions
+            .iter()
+            .rev()
+            .find(|f| f.1 == hash && f.2 == file)
+            .map(|f| f.0)
+    }
+
+    pub fn decide(&mut self, file: &Path, content: &str, trusted: bool) {
+        let hash = hash(content);
+        let answer = if trusted { "yes" } else { "no" };
+        let line = format!("{}\t{}\t{}\n", answer, hash, file.display());
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| f.write_all(line.as_bytes()));
+        if let Err(e) = appended {
+            eprintln!("ash: {}: {}", self.path.display(), e);
+        }
+        self.decisions.push((trusted, hash, file.to_path_buf()));
+    }
+}
+
+/// 64-bit FNV-1a, which unlike the std hasher is the same in every build.
+fn hash(content: &str) -> u64 {
+    content.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
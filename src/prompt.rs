@@ -0,0 +1,215 @@
+use std::{collections::HashMap, env, path::Path, time::Duration};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    about::get_hostname,
+    buffer,
+    config::{home_dir, CwdStyle},
+    devenv::{get_virtualenv, DevEnv},
+    git::GitStatus,
+    theme::{style, Theme},
+};
+
+pub struct PromptContext<'a> {
+    pub cwd: &'a Path,
+    pub cwd_style: CwdStyle,
+    pub last_status: i32,
+    /// How long the last command line took to run.
+    pub last_duration: Duration,
+    pub last_command: &'a str,
+    pub git: Option<GitStatus>,
+    pub dev_env: DevEnv,
+    pub theme: &'a Theme,
+    pub nerd_font: bool,
+    pub root_symbol: &'a str,
+    /// Number of directories saved by `pushd`.
+    pub dir_stack: usize,
+    /// Name of the tmux session ash runs in.
+    pub tmux_session: Option<String>,
+    /// Placeholders rendered by plugins.
+    pub segments: HashMap<String, String>,
+}
+
+/// Renders a prompt template, replacing `{placeholder}`s with their values.
+/// Unknown placeholders are left as written so typos are visible.
+pub fn render(format: &str, context: &PromptContext) -> String {
+    let mut rendered = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = &rest[start + 1..start + end];
+        match render_placeholder(name, context) {
+            Some(value) => rendered.push_str(&value),
+            None => rendered.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn render_placeholder(name: &str, context: &PromptContext) -> Option<String> {
+    let value = match name {
+        "cwd" => format_cwd(context.cwd, context.cwd_style),
+        "user" => env::var("USER").unwrap_or_default(),
+        "host" => get_hostname(),
+        "user_host" => {
+            if is_remote() || is_root() {
+                format!(
+                    "{}@{} ",
+                    env::var("USER").unwrap_or_default(),
+                    get_hostname()
+                )
+            } else {
+                String::new()
+            }
+        }
+        "git" => context
+            .git
+            .as_ref()
+            .map_or(String::new(), |f| f.render(context.nerd_font)),
+        "dir_icon" if context.nerd_font => "\u{f07c}  ".to_string(),
+        "dir_icon" => String::new(),
+        "separator" if is_root() => {
+            if context.theme.plain {
+                context.root_symbol.to_string()
+            } else {
+                format!("{}{}", context.theme.root, context.root_symbol)
+            }
+        }
+        "separator" if context.nerd_font => "\u{ea9c}".to_string(),
+        "separator" => ">".to_string(),
+        "status" => match context.last_status {
+            0 => String::new(),
+            status => status.to_string(),
+        },
+        "duration" => format_duration(context.last_duration),
+        "last_command" => context.last_command.to_string(),
+        "time" => get_time(),
+        "dirs" => match context.dir_stack {
+            0 => String::new(),
+            depth => format!("+{}", depth),
+        },
+        "tmux_session" => context.tmux_session.clone().unwrap_or_default(),
+        "venv" => get_virtualenv().unwrap_or_default(),
+        "node" => context.dev_env.node.clone().unwrap_or_default(),
+        "rust" => context.dev_env.rust.clone().unwrap_or_default(),
+        name if context.segments.contains_key(name) => context.segments[name].clone(),
+        _ => return get_color(name, context.theme),
+    };
+    Some(value)
+}
+
+pub fn format_cwd(cwd: &Path, style: CwdStyle) -> String {
+    let home = home_dir();
+    let abbreviated = match cwd.strip_prefix(&home) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.to_string_lossy()),
+        Err(_) => cwd.to_string_lossy().to_string(),
+    };
+    match style {
+        CwdStyle::Basename => cwd
+            .file_name()
+            .map_or("/".to_string(), |f| f.to_string_lossy().to_string()),
+        CwdStyle::Full => cwd.to_string_lossy().to_string(),
+        CwdStyle::Home => abbreviated,
+        CwdStyle::Fish => {
+            let components = abbreviated.split('/').collect::<Vec<_>>();
+            let last = components.len() - 1;
+            components
+                .iter()
+                .enumerate()
+                .map(|(i, component)| {
+                    if i == last {
+                        return component.to_string();
+                    }
+                    // Keep the dot of hidden directories so `~/.config` stays
+                    // recognisable as `~/.c`.
+                    let length = if component.starts_with('.') { 2 } else { 1 };
+                    component.chars().take(length).collect()
+                })
+                .collect::<Vec<String>>()
+                .join("/")
+        }
+    }
+}
+
+/// Color placeholders accept any theme style spec, e.g. `{red}` or
+/// `{bold #ff8800}`, and render as nothing under a plain theme.
+fn get_color(name: &str, theme: &Theme) -> Option<String> {
+    let color = match name {
+        "reset" => "\x1b[0m".to_string(),
+        "prompt_color" => theme.prompt.clone(),
+        name => Some(style(name)).filter(|f| !f.is_empty())?,
+    };
+    if theme.plain {
+        return Some(String::new());
+    }
+    Some(color)
+}
+
+fn is_remote() -> bool {
+    env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some()
+}
+
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Durations like `4.2s` or `3m12s`, and nothing for commands that took
+/// under a second.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0 => String::new(),
+        1..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m{}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn get_time() -> String {
+    // SAFETY: `localtime_r` only writes into the zeroed `tm` we hand it.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+/// Number of terminal columns a rendered prompt occupies, ignoring escapes.
+pub fn visible_width(prompt: &str) -> u16 {
+    let visible = strip_escapes(prompt);
+    visible.graphemes(true).map(buffer::width).sum::<usize>() as u16
+}
+
+/// The text of a rendered prompt without its colors and other escapes.
+pub fn strip_escapes(prompt: &str) -> String {
+    let mut visible = String::new();
+    let mut chars = prompt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            visible.push(c);
+        } else if chars.peek() == Some(&']') {
+            // OSC sequences run until BEL or ST (`ESC \`).
+            while let Some(c) = chars.next() {
+                if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+        } else {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+    visible
+}
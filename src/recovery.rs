@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+
+/// What a crash would lose: commands not written to the history file yet
+/// and the line being edited. The shell saves it when it panics or its
+/// terminal hangs up, and the next one to start takes it back.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Recovery {
+    /// Newest first, like the history file.
+    pub history: Vec<String>,
+    pub buffer: String,
+}
+
+fn path() -> PathBuf {
+    config::state_dir().join("recovery.json")
+}
+
+impl Recovery {
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty() && self.buffer.is_empty()
+    }
+
+    /// Writes the file, adding to what shells that crashed before left in
+    /// it. The newest buffer wins.
+    pub fn save(mut self) -> Option<PathBuf> {
+        if let Some(older) = Recovery::read() {
+            self.history.extend(older.history);
+            if self.buffer.is_empty() {
+                self.buffer = older.buffer;
+            }
+        }
+        let path = path();
+        fs::create_dir_all(config::state_dir()).ok()?;
+        fs::write(&path, serde_json::to_string(&self).ok()?).ok()?;
+        Some(path)
+    }
+
+    /// Reads and removes the file, so only one shell restores it.
+    pub fn take() -> Option<Recovery> {
+        let recovery = Recovery::read();
+        let _ = fs::remove_file(path());
+        recovery.filter(|f| !f.is_empty())
+    }
+
+    fn read() -> Option<Recovery> {
+        serde_json::from_str(&fs::read_to_string(path()).ok()?).ok()
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt::Write;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::autocomplete::Suggestion;
+use crate::buffer::{self, EditBuffer};
+
+#[derive(PartialEq)]
+struct Cell {
+    text: String,
+    style: String,
+    /// Columns the text takes.
+    width: usize,
+}
+
+/// Owns what the shell has drawn while reading input: the prompt line with
+/// its input and ghost text, the cursor, and the status bar. Each method
+/// returns the escape sequences that take the screen from what it shows to
+/// what is asked for, which is nothing when it already shows it.
+#[derive(Default)]
+pub struct Renderer {
+    /// The prompt and cells of the prompt line, `None` once forgotten.
+    line: Option<String>,
+    cells: Vec<Cell>,
+    /// The cells of the frame being drawn. The two vectors trade places
+    /// after each frame, so their strings are reused rather than allocated
+    /// on every key.
+    next: Vec<Cell>,
+    /// Column of the cursor on the prompt line.
+    cursor: Option<usize>,
+    /// Text of the status bar, `None` when it isn't drawn.
+    status_bar: Option<String>,
+}
+
+pub struct Frame<'a> {
+    pub prompt: &'a str,
+    pub prompt_width: u16,
+    pub input: &'a EditBuffer,
+    pub ghost: &'a str,
+    pub ghost_style: &'a str,
+    /// Styles of byte ranges of the input, for highlighting. The first
+    /// range containing a character wins.
+    pub highlights: &'a [(Range<usize>, String)],
+}
+
+impl Renderer {
+    /// Forgets the prompt line, e.g. because a fresh line was started.
+    pub fn invalidate(&mut self) {
+        self.line = None;
+        self.cursor = None;
+    }
+
+    /// Starts a new prompt, printing the lines of a multi-line prompt above
+    /// the one holding the input.
+    pub fn start(&mut self, info: Option<&str>) -> String {
+        self.invalidate();
+        match info {
+            Some(info) => format!("\r\x1b[2K{}\x1b[0m\r\n", info.replace('\n', "\r\n")),
+            None => String::new(),
+        }
+    }
+
+    /// Clears the screen so the next prompt starts at the top.
+    pub fn clear_screen(&mut self) -> String {
+        self.invalidate();
+        self.status_bar = None;
+        "\x1b[2J\x1b[H".to_string()
+    }
+
+    /// Moves to a fresh line below the input.
+    pub fn new_line(&mut self) -> String {
+        self.invalidate();
+        "\r\n".to_string()
+    }
+
+    /// Replaces the `rows` lines above the prompt line and the prompt line
+    /// itself with `text`, as a transient prompt does once a line is run.
+    pub fn collapse(&mut self, rows: u16, text: &str) -> String {
+        self.invalidate();
+        let up = if rows > 0 {
+            format!("\x1b[{}A", rows)
+        } else {
+            String::new()
+        };
+        format!("\r{}\x1b[J{}", up, text)
+    }
+
+    /// Returns the output that turns the line on screen into `frame`.
+    pub fn draw(&mut self, frame: &Frame) -> String {
+        let cursor = frame.prompt_width as usize + frame.input.cursor_column();
+        let mut count = 0;
+        for (i, text) in frame.input.as_str().grapheme_indices(true) {
+            let style = frame.highlights.iter().find(|f| f.0.contains(&i));
+            put(
+                &mut self.next,
+                count,
+                text,
+                style.map_or("", |f| f.1.as_str()),
+            );
+            count += 1;
+        }
+        for text in frame.ghost.graphemes(true) {
+            put(&mut self.next, count, text, frame.ghost_style);
+            count += 1;
+        }
+        self.next.truncate(count);
+
+        let mut output = String::new();
+        let start = match &self.line {
+            Some(prompt) if prompt == frame.prompt => {
+                let (drawn, cells) = (&self.cells, &self.next);
+                let same = drawn.iter().zip(cells).take_while(|(a, b)| a == b).count();
+                (same != drawn.len() || same != cells.len()).then_some(same)
+            }
+            _ => {
+                output.push('\r');
+                output.push_str(frame.prompt);
+                output.push_str("\x1b[0m");
+                Some(0)
+            }
+        };
+
+        if let Some(start) = start {
+            let column: usize = self.next[..start].iter().map(|f| f.width).sum();
+            let _ = write!(output, "\x1b[{}G", frame.prompt_width as usize + column + 1);
+            let mut style = "";
+            for cell in &self.next[start..] {
+                if cell.style != style {
+                    if !style.is_empty() {
+                        output.push_str("\x1b[0m");
+                    }
+                    output.push_str(&cell.style);
+                    style = &cell.style;
+                }
+                output.push_str(&cell.text);
+            }
+            if !style.is_empty() {
+                output.push_str("\x1b[0m");
+            }
+            output.push_str("\x1b[K");
+        }
+        if start.is_some() || self.cursor != Some(cursor) {
+            let _ = write!(output, "\x1b[{}G", cursor + 1);
+        }
+
+        match &mut self.line {
+            Some(prompt) if prompt == frame.prompt => {}
+            line => *line = Some(frame.prompt.to_string()),
+        }
+        std::mem::swap(&mut self.cells, &mut self.next);
+        self.cursor = Some(cursor);
+        output
+    }
+
+    /// Keeps the bottom row of a screen with `rows` rows for the status bar
+    /// by limiting the scroll region to the rows above it. `at_bottom` says
+    /// the cursor is on that row, which then scrolls up first.
+    pub fn reserve_status_bar(&mut self, rows: u16, at_bottom: bool) -> String {
+        self.status_bar = None;
+        // Scroll the screen so the prompt doesn't land on the bar's row.
+        let scroll = if at_bottom { "\n\x1b[A" } else { "" };
+        format!("{}\x1b7\x1b[1;{}r\x1b8", scroll, rows.saturating_sub(1))
+    }
+
+    /// Draws `text`, styled as `styled`, on the bottom row unless it shows
+    /// it already.
+    pub fn draw_status_bar(&mut self, rows: u16, text: &str, styled: &str) -> String {
+        if self.status_bar.as_deref() == Some(text) {
+            return String::new();
+        }
+        self.status_bar = Some(text.to_string());
+        format!("\x1b7\x1b[{};1H\x1b[2K{}\x1b8", rows, styled)
+    }
+
+    /// Gives the whole screen back, e.g. before a command runs.
+    pub fn release_status_bar(&mut self, rows: u16) -> String {
+        self.status_bar = None;
+        format!("\x1b7\x1b[r\x1b[{};1H\x1b[2K\x1b8", rows)
+    }
+}
+
+/// Sets cell `index` of `cells`, reusing the strings of the cell there.
+fn put(cells: &mut Vec<Cell>, index: usize, text: &str, style: &str) {
+    match cells.get_mut(index) {
+        Some(cell) => {
+            cell.text.clear();
+            cell.text.push_str(text);
+            cell.style.clear();
+            cell.style.push_str(style);
+            cell.width = buffer::width(text);
+        }
+        None => cells.push(Cell {
+            text: text.to_string(),
+            style: style.to_string(),
+            width: buffer::width(text),
+        }),
+    }
+}
+
+/// Lays out completion candidates below the prompt: in columns across
+/// `width`, or one per line when some have descriptions. Directories are
+/// styled with `directory_style`.
+pub fn menu(candidates: &[Suggestion], width: usize, directory_style: &str) -> String {
+    let max_width = candidates
+        .iter()
+        .map(|f| f.file_name.len())
+        .max()
+        .unwrap_or(0);
+    let columns = if candidates.iter().any(|f| f.description.is_some()) {
+        1
+    } else {
+        (width / (max_width + 2)).max(1)
+    };
+    let mut output = String::from("\r\n");
+    for (i, candidate) in candidates.iter().enumerate() {
+        let padded = format!("{:<width$}", candidate.file_name, width = max_width + 2);
+        if candidate.is_dir && !directory_style.is_empty() {
+            output.push_str(&format!("{}{}\x1b[0m", directory_style, padded));
+        } else {
+            output.push_str(&padded);
+        }
+        if let Some(description) = &candidate.description {
+            output.push_str(&format!("-- {}", description));
+        }
+        if (i + 1).is_multiple_of(columns) || i + 1 == candidates.len() {
+            output.push_str("\r\n");
+        }
+    }
+    output
+}
@@ -1,407 +1,3461 @@
 use crossterm::{
-    cursor::{self, MoveLeft, MoveRight, MoveTo},
-    event::{self, Event, KeyCode, KeyModifiers},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode},
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent},
+    terminal::{disable_raw_mode, enable_raw_mode, size},
 };
-use std::io::{self, BufRead, BufReader, Stdout, Write};
-use std::path::{Path, PathBuf};
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, PipeReader, Read, Write};
+use std::iter;
+use std::ops::Range;
+use std::os::unix::process::CommandExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::{env, error::Error};
-use std::{fs::File, io::stdout};
-use unicode_segmentation::UnicodeSegmentation;
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::{
-    about::print_about, autocomplete::AutoComplete, history::History, parser::CommandParser,
-    suggestion::get_command_suggestion,
+    about::print_about,
+    alias::Aliases,
+    autocomplete::{self, run_with_timeout, AutoComplete, Suggestion},
+    buffer::EditBuffer,
+    builtins::{self, Builtin},
+    config::{self, home_dir, Config, CursorShape, CwdStyle, Greeting, HooksConfig, Options},
+    control::{ControlSocket, Request},
+    devenv::{self, DevEnv, DevEnvCache},
+    error::AshError,
+    frecency::Frecency,
+    fzf,
+    git::{self, GitCache},
+    glob::matches_pattern,
+    hash::CommandHash,
+    history::History,
+    jobs::{self, JobState, JobTable, Usage},
+    keys::{Binding, EditorAction, KeyBindings},
+    log,
+    parser::{self, CommandParser, Redirection},
+    plugin::Plugins,
+    project::{self, ProjectConfig, TrustStore},
+    prompt::{self, PromptContext},
+    recovery::Recovery,
+    render::{self, Frame, Renderer},
+    suggestion::{get_command_correction, get_command_suggestion},
+    terminal,
+    theme::Theme,
+    timing::StartupTimer,
+    tmux,
+    trap::{self, Traps},
+    worker::{self, Message, Timer},
+    zoxide,
 };
 
+/// Something that happened in the shell, for the event hooks of the config
+/// to react to.
+enum ShellEvent {
+    /// A command line changed the cwd.
+    DirectoryChanged { from: PathBuf, to: PathBuf },
+    /// A command line finished with a nonzero status.
+    CommandFailed { status: i32, command: String },
+    /// Nobody typed at the prompt for a while.
+    Idle,
+}
+
+/// The states of the line editor. Each has its own handler for the keys
+/// it cares about, and passes the rest on to the insert handler, which
+/// does the plain editing every mode shares.
+enum Mode {
+    /// Typing into the line, with the first suggestion as ghost text.
+    Insert,
+    /// Cycling through the suggestions with Up and Down; the number is the
+    /// one shown, one past the last showing none.
+    Suggestions(usize),
+    /// Browsing history with Up and Down, `index` entries back. `typed` is
+    /// the line as it was before, for Down to come back to. Edits to a
+    /// recalled line keep the place in history.
+    History { index: usize, typed: String },
+}
+
+impl Mode {
+    /// What the ghost text shows after `input`: the rest of the suggestion
+    /// the mode picks.
+    fn ghost<'a>(&self, input: &str, suggestions: &'a [String]) -> Option<&'a str> {
+        if input.is_empty() {
+            return None;
+        }
+        let shown = match self {
+            Mode::Suggestions(shown) => *shown,
+            _ => 0,
+        };
+        suggestions.get(shown)?.strip_prefix(input)
+    }
+}
+
+/// A trusted `.ash.toml` in effect, with what it replaced.
+struct ActiveProject {
+    root: PathBuf,
+    aliases: Vec<(String, Option<String>)>,
+    env: Vec<(String, Option<String>)>,
+    hooks: HooksConfig,
+    /// The hooks of the project itself, laid over the config's.
+    overlay: HooksConfig,
+}
+
+type BuiltinFn = fn(&mut Shell, &[String], &mut dyn Read, &mut dyn Write) -> BuiltinResult;
+type BuiltinResult = Result<i32, AshError>;
+
+const BUILTINS: &[(&str, BuiltinFn)] = &[
+    (":", Shell::true_builtin),
+    (".", Shell::source),
+    ("[", Shell::bracket),
+    ("about", Shell::about),
+    ("alias", Shell::alias),
+    ("bg", Shell::background_job),
+    ("builtin", Shell::builtin),
+    ("cd", Shell::cd),
+    ("command", Shell::command),
+    ("copy", Shell::copy),
+    ("dirs", Shell::dirs),
+    ("disown", Shell::disown),
+    ("echo", Shell::echo),
+    ("exit", Shell::exit_builtin),
+    ("export", Shell::export),
+    ("false", Shell::false_builtin),
+    ("fg", Shell::foreground_job),
+    ("hash", Shell::hash),
+    ("help", Shell::help),
+    ("j", Shell::jump),
+    ("jobs", Shell::list_jobs),
+    ("kill", Shell::kill),
+    ("popd", Shell::popd),
+    ("printf", Shell::printf),
+    ("pushd", Shell::pushd),
+    ("pwd", Shell::print_pwd),
+    ("rehash", Shell::rehash),
+    ("reload", Shell::reload),
+    ("reload-shell", Shell::reload_shell),
+    ("retry-sudo", Shell::retry_sudo),
+    ("return", Shell::return_builtin),
+    ("set", Shell::set),
+    ("shopt", Shell::shopt),
+    ("source", Shell::source),
+    ("test", Shell::test),
+    ("trap", Shell::trap),
+    ("true", Shell::true_builtin),
+    ("unalias", Shell::unalias),
+    ("unset", Shell::unset),
+    ("z", Shell::jump),
+];
+
+fn get_builtin(name: &str) -> Option<&'static dyn Builtin> {
+    BUILTINS
+        .iter()
+        .find(|f| f.0 == name)
+        .map(|f| &f.1 as &dyn Builtin)
+}
+
+/// What a command of a pipeline leaves for the next one to read.
+enum Stage {
+    Process(Child),
+    /// Output of a builtin, written into a pipe by a thread.
+    Output(PipeReader),
+}
+
+const GREETING_TIMEOUT: Duration = Duration::from_millis(1000);
+
 pub struct Shell {
-    input: String,
-    temp_input: String,
+    /// The line being edited.
+    input: EditBuffer,
     history: History,
-    stdout: Stdout,
     autocompleter: AutoComplete,
     parser: CommandParser,
     prompt_length: u16,
+    /// The line of the prompt the input is on, rendered once per prompt
+    /// rather than on every key.
+    prompt_line: Option<String>,
+    prompt_rows: u16,
+    renderer: Renderer,
+    continued_lines: Vec<String>,
+    primary_prompt_length: u16,
+    aliases: Aliases,
+    /// Directories saved by `pushd`, most recent first. The cwd is the
+    /// implicit top of the stack.
+    dir_stack: Vec<PathBuf>,
+    jobs: JobTable,
+    traps: Traps,
+    command_hash: CommandHash,
+    /// Visited directories for `j`/`z`.
+    frecency: Frecency,
+    /// Whether the command line being run ended with `&`.
+    background: bool,
+    /// Whether the shell reads commands from the line editor rather than
+    /// running a script.
+    interactive: bool,
+    /// Set when the terminal can't take more than plain lines of text, so
+    /// they are read without the line editor.
+    line_mode: bool,
+    /// Set by `-r`: no changing directories or PATH, running commands by
+    /// path, or redirecting output.
+    restricted: bool,
+    /// Set by `-l` or a `-` before the name: the profiles get read first.
+    login: bool,
+    /// Set while `~/.ash_logout` runs.
+    exiting: bool,
+    /// Set while a line is being read, so a crash knows the input isn't a
+    /// command that already ran.
+    editing: bool,
+    /// The line a crashed shell was editing, for the first prompt.
+    restored: Option<String>,
+    /// Process group of the pipeline being started, that of its first
+    /// process.
+    pipeline_pgid: Option<libc::pid_t>,
     suggestions: Vec<String>,
-    suggestion_index: u8,
+    /// The input `suggestions` hold every match for, if they do.
+    suggested_for: Option<String>,
+    /// What the keys at the prompt act on.
+    mode: Mode,
+    config: Config,
+    theme: Theme,
+    nerd_font: bool,
+    /// Modification time of the config file when it was read, to reload it
+    /// once it changes.
+    config_modified: Option<SystemTime>,
+    last_status: i32,
+    /// Resources used by the foreground processes reaped since `time -v`
+    /// started counting.
+    usage: Usage,
+    /// When the foreground pipeline of a `--timeout` command gets killed,
+    /// and whether it was.
+    deadline: Option<Instant>,
+    timed_out: bool,
+    /// Wall-clock time and text of the last command line.
+    last_duration: Duration,
+    last_command: String,
+    /// Options set with `set -o` or `shopt`, which a reload keeps.
+    options_set: HashSet<String>,
+    /// The control socket, while the `control_socket` option is on.
+    control: Option<ControlSocket>,
+    /// Lines the control socket asked to run, one per prompt.
+    injected: VecDeque<String>,
+    /// Whether the builtin running reads the terminal rather than a pipe.
+    stdin_is_terminal: bool,
+    /// Status of the command before the one running, for `exit`.
+    previous_status: i32,
+    /// Number of files being sourced, so `return` knows it has a file to
+    /// leave.
+    source_depth: usize,
+    /// Status given to `return`, set until the sourced file stops.
+    returning: Option<i32>,
+    git_cache: GitCache,
+    tmux_session: tmux::SessionName,
+    plugins: Plugins,
+    keys: KeyBindings,
+    project: Option<ActiveProject>,
+    trust: TrustStore,
+    dev_env_cache: DevEnvCache,
+    /// Set until the first prompt, which reports how startup went.
+    startup: Option<StartupTimer>,
 }
 
 impl Drop for Shell {
     fn drop(&mut self) {
-        disable_raw_mode().unwrap();
+        let _ = disable_raw_mode();
     }
 }
 
 impl Shell {
-    pub fn new() -> io::Result<Self> {
-        let history = History::new(format!(
-            "/home/{}/.ash_history",
-            env::var("USER").unwrap_or_else(|_| "Unknown".to_string())
-        ))?;
+    /// A shell for the line editor, or with `interactive` off one that only
+    /// runs scripts: no history file is read and there is no job control.
+    pub fn new(interactive: bool) -> io::Result<Self> {
+        let mut startup = StartupTimer::start();
+        let config = Config::load();
+        config::apply_env(&config.env);
+        startup.step("config");
+        let history = if interactive {
+            config::migrate_state();
+            History::new(config.history.path(), config.history.preload)?
+        } else {
+            History::empty()
+        };
+        startup.step("history");
+        // Scripts get neither: they shouldn't change with what's in the
+        // user's config dir, nor wait on plugins describing themselves.
+        let frecency = if interactive {
+            Plugins::load_in_background(config::config_dir().join("plugins"));
+            Frecency::load(config::state_dir().join("dirs"))
+        } else {
+            Frecency::empty()
+        };
+        startup.step("plugins");
+        // Emacs shell buffers and the like say `TERM=dumb`: they show text
+        // as it's written and can't do colors or move the cursor.
+        let line_mode = interactive
+            && (env::var("TERM").is_ok_and(|f| f == "dumb") || !io::stdout().is_terminal());
+        let theme = load_theme(&config, line_mode);
+        let keys = KeyBindings::from_config(&config.keys);
+        let mut aliases = Aliases::default();
+        for (name, value) in &config.aliases {
+            aliases.set(name, value);
+        }
         Ok(Shell {
-            autocompleter: AutoComplete::new(),
-            stdout: stdout(),
-            input: "".to_string(),
-            temp_input: "".to_string(),
+            autocompleter: AutoComplete::new(config.completion.clone()),
+            input: EditBuffer::default(),
             history,
             prompt_length: 0,
+            prompt_line: None,
+            prompt_rows: 0,
+            renderer: Renderer::default(),
+            continued_lines: vec![],
+            primary_prompt_length: 0,
+            aliases,
+            dir_stack: vec![],
+            jobs: JobTable::default(),
+            traps: Traps::default(),
+            command_hash: CommandHash::default(),
+            frecency,
+            background: false,
+            restricted: false,
+            login: false,
+            exiting: false,
+            editing: false,
+            restored: None,
+            interactive,
+            line_mode,
+            pipeline_pgid: None,
             suggestions: vec![],
-            suggestion_index: 0,
+            suggested_for: None,
+            mode: Mode::Insert,
             parser: CommandParser::new(),
+            nerd_font: config.prompt.glyphs.use_nerd_font(),
+            config_modified: config::config_modified(),
+            config,
+            theme,
+            last_status: 0,
+            usage: Usage::default(),
+            deadline: None,
+            timed_out: false,
+            last_duration: Duration::ZERO,
+            last_command: String::new(),
+            options_set: HashSet::new(),
+            control: None,
+            injected: VecDeque::new(),
+            stdin_is_terminal: false,
+            previous_status: 0,
+            source_depth: 0,
+            returning: None,
+            git_cache: GitCache::default(),
+            tmux_session: tmux::SessionName::default(),
+            plugins: Plugins::default(),
+            keys,
+            project: None,
+            trust: TrustStore::load(config::state_dir().join("trusted")),
+            dev_env_cache: DevEnvCache::default(),
+            startup: Some(startup),
         })
     }
 
+    fn startup_step(&mut self, name: &'static str) {
+        if let Some(startup) = &mut self.startup {
+            startup.step(name);
+        }
+    }
+
+    pub fn login(&mut self) {
+        self.login = true;
+    }
+
+    pub fn restrict(&mut self) {
+        self.restricted = true;
+    }
+
     pub fn init(&mut self) {
+        env::set_var("PWD", logical_cwd());
+        terminal::install_panic_hook();
+        jobs::init_job_control();
+        trap::catch_hangup();
+        self.startup_step("state");
+        self.read_profiles();
+        self.source_startup_file(&home_dir().join(".ashrc"));
+        self.startup_step("rc files");
+        self.update_project();
+        self.startup_step("project");
+        self.print_greeting();
+        self.startup_step("greeting");
+        if let Some(startup) = self.startup.take() {
+            log::info!("startup", took = startup.total());
+            if let Some(report) = startup.report() {
+                eprintln!("{}", report);
+            }
+        }
+        self.recover();
+        // The panic hook has put the terminal back by the time the panic
+        // gets here, so saving what would be lost is all that's left.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.main_loop()));
+        if let Err(payload) = result {
+            self.save_recovery(true);
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn main_loop(&mut self) {
         loop {
-            self.input.clear();
-            if let Err(e) = self.collect_input() {
-                eprintln!("Error collecting input: {}", e);
+            if config::config_modified() != self.config_modified {
+                if let Err(e) = self.reload_config() {
+                    self.print_error(&e.to_string());
+                }
+            }
+            self.notify_jobs();
+            self.update_control();
+            self.update_direnv();
+            self.run_hook(self.config.hooks.precmd.as_deref(), &[]);
+            let restored = self.restored.take().unwrap_or_default();
+            self.set_input(restored);
+            let cursor = self.config.editor.cursor;
+            let shaped = !self.line_mode && !matches!(cursor, CursorShape::Default);
+            if shaped {
+                terminal::set_cursor_shape(cursor);
+            }
+            self.editing = true;
+            let collected = match self.line_mode {
+                true => self.read_line(),
+                false => self.collect_input(),
+            };
+            self.editing = false;
+            if shaped {
+                terminal::set_cursor_shape(CursorShape::Default);
+            }
+            if let Err(e) = collected {
+                // A closed terminal fails every read, and the SIGHUP it sent
+                // ends the shell before anything is printed to it.
+                self.run_traps();
+                self.print_error(&format!("ash: {}", e));
                 continue;
             }
 
-            if self.input.trim() == "exit" {
-                break;
+            self.hide_status_bar();
+            print!("{}", self.semantic_mark("C"));
+            if self.config.prompt.title && !self.line_mode {
+                terminal::set_title(self.input.as_str().trim());
             }
-
-            if let Err(e) = self.process_input() {
-                eprintln!("Error processing input: {}", e);
+            let input = self.input.as_str().to_string();
+            if !input.trim().is_empty() {
+                let expanded = self.aliases.expand(&input);
+                self.run_hook(self.config.hooks.preexec.as_deref(), &[expanded]);
+            }
+            let start = Instant::now();
+            let cwd = logical_cwd();
+            if let Err(e) = self.process_input(&input) {
+                jobs::take_terminal();
+                log::error!(
+                    "command",
+                    input = input,
+                    error = e.to_string(),
+                    status = e.status()
+                );
+                self.print_error(&format!("ash: {}", e));
+                self.last_status = e.status();
+            }
+            self.last_duration = start.elapsed();
+            self.last_command = input.trim().to_string();
+            self.parser.set_variable("?", self.last_status.to_string());
+            self.parser.set_variable(
+                "ASH_LAST_DURATION",
+                self.last_duration.as_millis().to_string(),
+            );
+            if logical_cwd() != cwd {
+                self.directory_changed(cwd);
+            }
+            if self.last_status != 0 && !input.trim().is_empty() {
+                self.emit(ShellEvent::CommandFailed {
+                    status: self.last_status,
+                    command: self.last_command.clone(),
+                });
             }
+            self.run_traps();
+            print!("{}", self.semantic_mark(&format!("D;{}", self.last_status)));
+            self.git_cache.clear();
+            self.tmux_session.clear();
+            self.plugins.clear();
             self.reset_states();
         }
     }
 
-    fn collect_input(&mut self) -> Result<(), Box<dyn Error>> {
-        enable_raw_mode()?;
-        let mut index: i8 = -1;
-        self.print_prompt();
+    fn collect_input(&mut self) -> Result<(), AshError> {
+        let _raw_mode = match terminal::RawMode::enable() {
+            Ok(raw_mode) => raw_mode,
+            Err(e) => {
+                log::error!("raw mode", error = e.to_string());
+                eprintln!(
+                    "ash: can't edit lines on this terminal ({}), reading them plainly",
+                    e
+                );
+                self.line_mode = true;
+                self.theme = load_theme(&self.config, true);
+                return self.read_line();
+            }
+        };
+        self.mode = Mode::Insert;
+        self.start_prompt();
+        self.set_idle_timer();
+        let collected = self.read_keys();
+        worker::cancel_timer(Timer::Idle);
+        collected
+    }
 
+    fn read_keys(&mut self) -> Result<(), AshError> {
         loop {
-            if let Ok(true) = event::poll(std::time::Duration::from_millis(500)) {
-                if let Event::Key(key_event) = event::read()? {
-                    if key_event.modifiers.contains(KeyModifiers::CONTROL)
-                        && key_event.code == KeyCode::Char('c')
-                    {
-                        self.reset_states();
-                        index = -1;
-                        print!("\n");
-                        self.print_prompt();
-                        continue;
+            for message in worker::messages() {
+                self.receive(message)?;
+            }
+            if let Some(line) = self.injected.pop_front() {
+                // What was typed, unfinished lines included, comes back at
+                // the next prompt rather than joining the injected line.
+                let typed = self.full_input();
+                if !typed.is_empty() {
+                    self.restored = Some(typed);
+                }
+                if !self.continued_lines.is_empty() {
+                    print!("{}", self.renderer.new_line());
+                    self.continued_lines.clear();
+                    self.prompt_line = None;
+                }
+                self.set_input(line);
+                self.print_prompt();
+                disable_raw_mode().map_err(AshError::Terminal)?;
+                self.handle_enter();
+                return Ok(());
+            }
+            if trap::has_pending() {
+                disable_raw_mode().map_err(AshError::Terminal)?;
+                // The terminal may be gone if the signal is SIGHUP.
+                let _ = writeln!(io::stdout());
+                self.run_traps();
+                enable_raw_mode().map_err(AshError::Terminal)?;
+                self.start_prompt();
+            }
+            // Input crossterm already read is buffered on its side, so the
+            // terminal is only waited on when there is none.
+            if !matches!(event::poll(Duration::ZERO), Ok(true)) {
+                terminal::wait_for_input(Duration::from_millis(100), worker::wake_fd())
+                    .map_err(AshError::Terminal)?;
+            }
+            if let Ok(true) = event::poll(Duration::ZERO) {
+                let event = event::read().map_err(AshError::Terminal)?;
+                if let Event::Resize(_, _) = event {
+                    self.show_status_bar();
+                    continue;
+                }
+                if let Event::Key(key_event) = event {
+                    log::debug!(
+                        "key",
+                        code = key_event.code,
+                        modifiers = key_event.modifiers
+                    );
+                    self.set_idle_timer();
+                    if self.handle_key(&key_event)? {
+                        return Ok(());
                     }
-                    match key_event.code {
-                        KeyCode::Char(c) => self.handle_char_input(c)?,
-                        KeyCode::Backspace => self.handle_backspace()?,
-                        KeyCode::Enter => {
-                            disable_raw_mode()?;
-                            self.handle_enter();
-                            return Ok(());
-                        }
-                        KeyCode::Up => {
-                            if self.suggestions.len() > 0 {
-                                if self.suggestion_index < self.suggestions.len() as u8 {
-                                    self.suggestion_index += 1;
-                                    self.print_prompt();
-                                }
-                                continue;
-                            }
-
-                            if self.history.count() > 0 && index < (self.history.count() - 1) as i8
-                            {
-                                if index == -1 {
-                                    self.temp_input = self.input.clone();
-                                }
-
-                                index += 1;
-                                if self.history.count() >= 10
-                                    && index as usize == self.history.count() - 2
-                                {
-                                    self.history.fetch_more();
-                                }
-                                self.handle_arrow(index as usize)?;
-                            }
-                        }
-                        KeyCode::Down => {
-                            if self.suggestions.len() > 0 && self.suggestion_index > 0 {
-                                self.suggestion_index -= 1;
-                                self.print_prompt();
-                                continue;
-                            }
-                            if index < 0 {
-                                continue;
-                            }
-                            if index > 0 {
-                                index -= 1;
-                                self.handle_arrow(index as usize)?;
-                            } else {
-                                index = -1;
-                                self.input = self.temp_input.clone();
-                                self.print_prompt();
-                            }
-                        }
-                        KeyCode::Tab => {
-                            if !self.input.is_empty() {
-                                self.autocomplete()?
-                            };
-                        }
-                        KeyCode::Left => {
-                            let (x, _) = cursor::position().unwrap();
-                            if x <= self.prompt_length {
-                                continue;
-                            }
-                            execute!(self.stdout, MoveLeft(1)).unwrap();
-                        }
-                        KeyCode::Right => {
-                            let (x, _) = cursor::position().unwrap();
-                            if x > self.prompt_length - 1 + self.input.len() as u16 {
-                                if !self.suggestions.is_empty() {
-                                    self.input = format!(
-                                        "{}{}",
-                                        self.input,
-                                        self.suggestions
-                                            .get(self.suggestion_index as usize)
-                                            .map_or("", |x| x)
-                                            .replacen(&self.input, "", 1)
-                                    );
-                                    self.print_prompt();
-                                    continue;
-                                } else {
-                                    continue;
-                                }
-                            }
-
-                            execute!(self.stdout, MoveRight(1)).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Starts waiting for the `on_idle` hook again.
+    fn set_idle_timer(&self) {
+        if self.config.hooks.on_idle.is_some() {
+            let idle = Duration::from_secs(self.config.hooks.idle_seconds);
+            worker::set_timer(Timer::Idle, idle);
+        }
+    }
+
+    /// Takes in what background work sent while the prompt waits for keys.
+    fn receive(&mut self, message: Message) -> Result<(), AshError> {
+        match message {
+            Message::HistoryIndexed(index) => {
+                log::trace!("message", kind = "history indexed");
+                self.history.indexed(index);
+                self.refresh_suggestions();
+            }
+            Message::HistorySearched(prefix, found) => {
+                log::trace!("message", kind = "history searched", prefix = prefix);
+                // A search for a shorter prefix can answer the current
+                // input too, and then no search for it follows.
+                let current = self.input.as_str().starts_with(prefix.as_str());
+                self.history.searched_for(prefix, found);
+                if current {
+                    self.refresh_suggestions();
+                }
+            }
+            Message::PluginsLoaded(mut plugins) => {
+                log::trace!("message", kind = "plugins loaded");
+                let shown = plugins.in_prompt(&self.config.prompt.format)
+                    && self.continued_lines.is_empty();
+                let mut redraw = shown;
+                if plugins.has_invalid() {
+                    disable_raw_mode().map_err(AshError::Terminal)?;
+                    println!();
+                    plugins.report_invalid();
+                    enable_raw_mode().map_err(AshError::Terminal)?;
+                    redraw = true;
+                } else if shown {
+                    print!("{}", self.renderer.collapse(self.prompt_rows, ""));
+                }
+                self.plugins = plugins;
+                if redraw {
+                    self.start_prompt();
+                }
+            }
+            Message::GitStatus(cwd, status) => {
+                log::trace!("message", kind = "git status", cwd = cwd);
+                let shown = self.config.prompt.format.contains("{git}")
+                    && self.continued_lines.is_empty()
+                    && cwd == logical_cwd();
+                if self.git_cache.update(cwd, status) && shown {
+                    print!("{}", self.renderer.collapse(self.prompt_rows, ""));
+                    self.start_prompt();
+                }
+            }
+            Message::TmuxSession(name) => {
+                log::trace!("message", kind = "tmux session");
+                let shown = self.config.prompt.format.contains("{tmux_session}")
+                    && self.continued_lines.is_empty();
+                if self.tmux_session.update(name) && shown {
+                    print!("{}", self.renderer.collapse(self.prompt_rows, ""));
+                    self.start_prompt();
+                }
+            }
+            Message::Timer(Timer::Idle) => {
+                log::trace!("message", kind = "idle");
+                disable_raw_mode().map_err(AshError::Terminal)?;
+                println!();
+                self.emit(ShellEvent::Idle);
+                enable_raw_mode().map_err(AshError::Terminal)?;
+                self.start_prompt();
+            }
+            Message::Control(request, reply) => {
+                log::trace!("message", kind = "control");
+                let answer = match request {
+                    Request::Run(line) => {
+                        self.injected.push_back(line);
+                        Ok(String::new())
+                    }
+                    Request::Cd(dir) => {
+                        disable_raw_mode().map_err(AshError::Terminal)?;
+                        println!();
+                        let from = logical_cwd();
+                        let changed = self.change_directory(Path::new(&dir));
+                        if changed.is_ok() {
+                            self.directory_changed(from);
+                            self.update_direnv();
                         }
-                        _ => {}
+                        enable_raw_mode().map_err(AshError::Terminal)?;
+                        self.start_prompt();
+                        changed.map(|_| String::new()).map_err(|e| e.to_string())
                     }
+                    Request::Cwd => Ok(logical_cwd().display().to_string()),
+                    Request::Status => Ok(self.last_status.to_string()),
+                };
+                let _ = reply.send(answer);
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens or closes the control socket as the option says.
+    fn update_control(&mut self) {
+        let wanted = self.config.options.control_socket && !self.line_mode;
+        if wanted == self.control.is_some() {
+            return;
+        }
+        self.control = None;
+        if wanted {
+            match ControlSocket::listen() {
+                Ok(control) => self.control = Some(control),
+                Err(e) => {
+                    self.print_error(&format!("ash: control socket: {}", e));
+                    self.config.options.control_socket = false;
                 }
             }
         }
     }
 
-    fn autocomplete(&mut self) -> Result<(), Box<dyn Error>> {
-        disable_raw_mode()?;
-        match self
-            .autocompleter
-            .autocomplete(self.input.as_str(), &self.parser)
-        {
-            Ok(new_command) => {
-                self.input = new_command;
+    /// Hands a key to the handler of the current mode, and to the insert
+    /// handler when that one doesn't take it. Returns whether the line was
+    /// accepted.
+    fn handle_key(&mut self, key_event: &KeyEvent) -> Result<bool, AshError> {
+        let action = match self.keys.get(key_event) {
+            Some(Binding::Action(action)) => Some(action),
+            Some(Binding::Command(command)) => {
+                self.set_input(command);
                 self.print_prompt();
+                disable_raw_mode().map_err(AshError::Terminal)?;
+                self.handle_enter();
+                return Ok(true);
             }
-            Err(_) => todo!(),
+            None => None,
+        };
+        let handled = match self.mode {
+            Mode::Insert => false,
+            Mode::Suggestions(_) => self.cycle_suggestions(action),
+            Mode::History { .. } => self.browse_history(action),
+        };
+        if handled {
+            return Ok(false);
+        }
+        match (action, key_event.code) {
+            (Some(action), _) => self.perform(action),
+            (None, KeyCode::Char(c)) => {
+                self.handle_char_input(c)?;
+                Ok(false)
+            }
+            (None, _) => Ok(false),
         }
-        enable_raw_mode()?;
-        Ok(())
     }
 
-    fn print_prompt(&mut self) {
-        let cwd = env::current_dir()
-            .unwrap_or_default()
-            .into_os_string()
-            .into_string()
-            .unwrap_or("".to_string());
-        let wdir = cwd.split("/").last().unwrap_or_default();
-        let prompt = format!("{}{}  ", "  ", wdir);
-        self.prompt_length = prompt.graphemes(true).count() as u16;
-        execute!(self.stdout, cursor::Hide).unwrap();
-        print!("\r\x1b[2K\x1b[34m{}\x1b[0m{}", prompt, self.input);
-        if self.input.len() > 0 {
-            print!(
-                "\x1b[2m{}\x1b[0m",
-                self.suggestions
-                    .get(self.suggestion_index as usize)
-                    .map_or("", |x| x)
-                    .replacen(&self.input, "", 1)
-            );
+    /// Keys while cycling suggestions: Up and Down move through them and
+    /// accepting takes the one shown. Anything else goes back to typing.
+    fn cycle_suggestions(&mut self, action: Option<EditorAction>) -> bool {
+        let Mode::Suggestions(shown) = self.mode else {
+            return false;
+        };
+        match action {
+            Some(EditorAction::PreviousHistory) => {
+                if shown < self.suggestions.len() {
+                    self.mode = Mode::Suggestions(shown + 1);
+                    self.print_prompt();
+                }
+                true
+            }
+            Some(EditorAction::NextHistory) => {
+                self.mode = match shown {
+                    0 | 1 => Mode::Insert,
+                    shown => Mode::Suggestions(shown - 1),
+                };
+                self.print_prompt();
+                true
+            }
+            Some(EditorAction::AcceptSuggestion) => {
+                self.accept_suggestion();
+                true
+            }
+            Some(EditorAction::ForwardChar) if self.input.at_end() => {
+                self.accept_suggestion();
+                true
+            }
+            _ => {
+                self.mode = Mode::Insert;
+                false
+            }
         }
-        let (_, y) = cursor::position().unwrap();
-        execute!(
-            self.stdout,
-            MoveTo(self.prompt_length + self.input.len() as u16, y)
-        )
-        .unwrap();
-        execute!(self.stdout, cursor::Show).unwrap();
-        io::stdout().flush().unwrap();
     }
 
-    fn handle_char_input(&mut self, c: char) -> Result<(), Box<dyn Error>> {
-        let (x, y) = cursor::position().unwrap();
-        self.input.insert((x - self.prompt_length) as usize, c);
-        if self.input.len() > 0 {
-            self.suggestions = get_command_suggestion(&self.history.commands, &self.input)
+    /// Keys while browsing history: Up goes further back and Down comes
+    /// forward, back to the typed line past the newest entry.
+    fn browse_history(&mut self, action: Option<EditorAction>) -> bool {
+        let Mode::History { index, typed } = &mut self.mode else {
+            return false;
+        };
+        match action {
+            Some(EditorAction::PreviousHistory) => {
+                if let Some(command) = self.history.get_command(*index + 1) {
+                    *index += 1;
+                    self.set_input(command);
+                    self.print_prompt();
+                }
+                true
+            }
+            Some(EditorAction::NextHistory) => {
+                let input = match index.checked_sub(1) {
+                    Some(newer) => {
+                        *index = newer;
+                        self.history.get_command(newer).unwrap_or_default()
+                    }
+                    None => {
+                        let typed = std::mem::take(typed);
+                        self.mode = Mode::Insert;
+                        typed
+                    }
+                };
+                self.set_input(input);
+                self.print_prompt();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Plain editing, and what starts the other modes.
+    fn perform(&mut self, action: EditorAction) -> Result<bool, AshError> {
+        match action {
+            EditorAction::CancelLine => {
+                self.reset_states();
+                println!();
+                self.start_prompt();
+            }
+            EditorAction::BackwardDeleteChar => self.handle_backspace()?,
+            EditorAction::AcceptLine => {
+                if self.expand_abbreviation() {
+                    self.print_prompt();
+                }
+                if self.parser.is_incomplete(&self.full_input()) {
+                    self.continue_line();
+                    return Ok(false);
+                }
+                self.mode = Mode::Insert;
+                disable_raw_mode()?;
+                self.handle_enter();
+                return Ok(true);
+            }
+            EditorAction::PreviousHistory => {
+                // Up cycles the suggestions of a line being typed, and
+                // recalls history otherwise.
+                if !self.input.is_empty() && !self.suggestions.is_empty() {
+                    self.mode = Mode::Suggestions(1);
+                    self.print_prompt();
+                } else if let Some(command) = self.history.get_command(0) {
+                    let typed = self.input.take();
+                    self.set_input(command);
+                    self.mode = Mode::History { index: 0, typed };
+                    self.print_prompt();
+                }
+            }
+            EditorAction::NextHistory => {}
+            EditorAction::Complete => self.autocomplete()?,
+            EditorAction::BackwardChar => {
+                self.input.move_left();
+                self.print_prompt();
+            }
+            EditorAction::ForwardChar => {
+                if self.input.at_end() {
+                    self.accept_suggestion();
+                } else {
+                    self.input.move_right();
+                    self.print_prompt();
+                }
+            }
+            EditorAction::AcceptSuggestion => self.accept_suggestion(),
+            EditorAction::ClearScreen => {
+                print!("{}", self.renderer.clear_screen());
+                self.start_prompt();
+            }
+            EditorAction::FuzzyHistory => self.fuzzy_history()?,
+            EditorAction::FuzzyFile => self.fuzzy_file()?,
+            EditorAction::CopyLine => terminal::copy_to_clipboard(self.input.as_str()),
+        }
+        Ok(false)
+    }
+
+    /// Replaces the line with a history line picked with fzf, starting
+    /// from what was typed.
+    fn fuzzy_history(&mut self) -> Result<(), AshError> {
+        let mut lines = self.history.lines();
+        let query = self.input.as_str().to_string();
+        let picked = self.run_fzf(&["--no-multi", "--tiebreak=index"], &query, move |out| {
+            lines.try_for_each(|f| writeln!(out, "{}", f))
+        })?;
+        if let Some(line) = picked.into_iter().next() {
+            self.set_input(line);
+            self.mode = Mode::Insert;
+            self.update_suggestions();
+        }
+        self.print_prompt();
+        Ok(())
+    }
+
+    /// Puts files under the cwd picked with fzf in place of the word
+    /// before the cursor, which starts the search.
+    fn fuzzy_file(&mut self) -> Result<(), AshError> {
+        let before = &self.input.as_str()[..self.input.cursor()];
+        let query = before.rsplit(char::is_whitespace).next().unwrap_or("");
+        let query = query.to_string();
+        let show_hidden = self.config.completion.show_hidden;
+        let picked = self.run_fzf(&["--multi"], &query, move |out| {
+            autocomplete::walk_files(Path::new("."), show_hidden, out)
+        })?;
+        if !picked.is_empty() {
+            if !query.is_empty() {
+                self.input.delete_word_backward();
+            }
+            let words = picked.iter().map(|f| fzf::as_word(f)).collect::<Vec<_>>();
+            self.input.insert_str(&words.join(" "));
+            self.update_suggestions();
         }
         self.print_prompt();
-        execute!(self.stdout, MoveTo(x + 1, y)).unwrap();
         Ok(())
     }
 
-    fn handle_backspace(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.input.len() == 0 {
+    /// Runs fzf below the prompt, giving it the terminal as it was before
+    /// the line editor, and starts the prompt over once it's done. Nothing
+    /// is picked when fzf is off or can't be found.
+    fn run_fzf(
+        &mut self,
+        options: &[&str],
+        query: &str,
+        feed: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    ) -> Result<Vec<String>, AshError> {
+        let command_hash = &mut self.command_hash;
+        if !fzf::available(&self.config.fzf, |f| command_hash.contains(f)) {
+            return Ok(vec![]);
+        }
+        self.hide_status_bar();
+        disable_raw_mode()?;
+        let picked = fzf::select(&self.config.fzf, options, query, feed);
+        enable_raw_mode()?;
+        print!("{}", self.renderer.collapse(self.prompt_rows, ""));
+        self.start_prompt();
+        Ok(picked?)
+    }
+
+    fn accept_suggestion(&mut self) {
+        let Some(suggestion) = self.mode.ghost(self.input.as_str(), &self.suggestions) else {
+            return;
+        };
+        let suggestion = suggestion.to_string();
+        self.input.move_to_end();
+        self.input.insert_str(&suggestion);
+        self.mode = Mode::Insert;
+        self.print_prompt();
+    }
+
+    /// Replaces the input, with the cursor at its end.
+    fn set_input(&mut self, input: String) {
+        self.input.set(input);
+    }
+
+    fn autocomplete(&mut self) -> Result<(), AshError> {
+        disable_raw_mode()?;
+        if self.complete_jump() {
+            enable_raw_mode()?;
             return Ok(());
         }
-        let (x, y) = cursor::position().unwrap();
-        let pos = (x - self.prompt_length) as usize;
-        if pos > 0 {
-            self.input.remove(pos - 1);
-            if self.input.len() > 0 {
-                self.suggestions = get_command_suggestion(&self.history.commands, &self.input)
+        self.autocompleter.ignore_case = self.config.options.nocasecomplete;
+        match self.autocompleter.autocomplete(
+            self.input.as_str(),
+            &self.parser,
+            &self.history.recent(),
+            &self.command_hash.executables(),
+            &self.plugins,
+        ) {
+            Ok(completion) => {
+                self.set_input(completion.value);
+                if completion.listing.is_empty() {
+                    self.print_prompt();
+                } else {
+                    let width = size().map_or(80, |f| f.0 as usize);
+                    print!(
+                        "{}",
+                        render::menu(&completion.listing, width, &self.theme.completion)
+                    );
+                    self.start_prompt();
+                }
+            }
+            Err(e) => {
+                self.print_error(&format!("ash: {}", e));
+                self.start_prompt();
             }
-            self.print_prompt();
-            execute!(self.stdout, MoveTo(if x > 0 { x - 1 } else { x }, y)).unwrap();
         }
+        enable_raw_mode()?;
         Ok(())
     }
 
-    fn handle_enter(&mut self) {
-        println!();
-        if !self.input.trim().is_empty() {
-            self.history.add_command(&self.input);
+    /// Completes the argument of `j`/`z` with the directories it would jump
+    /// to: a single match replaces the query, several are listed best first.
+    fn complete_jump(&mut self) -> bool {
+        let input = self.input.as_str();
+        let Some(query) = ["j ", "z "].iter().find_map(|f| input.strip_prefix(f)) else {
+            return false;
+        };
+        let words = self.parser.parse(&format!("j {}", query)).args;
+        let matches = self.jump_matches(&words);
+        match matches.as_slice() {
+            [] => self.print_prompt(),
+            [(_, dir)] => {
+                self.set_input(format!(
+                    "{}{}",
+                    &self.input.as_str()[..2],
+                    parser::quote(&dir.to_string_lossy())
+                ));
+                self.print_prompt();
+            }
+            matches => {
+                let listing = matches
+                    .iter()
+                    .map(|(_, dir)| Suggestion {
+                        file_name: dir.to_string_lossy().to_string(),
+                        is_dir: true,
+                        description: None,
+                    })
+                    .collect::<Vec<_>>();
+                // One per line, so the best match stays first.
+                print!("{}", render::menu(&listing, 0, &self.theme.completion));
+                self.start_prompt();
+            }
         }
+        true
     }
 
-    fn handle_arrow(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
-        if index < self.history.count() {
-            self.input = self
-                .history
-                .get_command(index)
-                .map_or("", |f| f)
-                .to_string();
-            self.print_prompt();
+    /// Prints a fresh prompt on the current line. A multi-line template gets
+    /// its leading lines printed once here; redraws only touch the last line,
+    /// which is where the input lives.
+    fn start_prompt(&mut self) {
+        self.renderer.invalidate();
+        self.prompt_line = None;
+        self.show_status_bar();
+        if self.config.prompt.title {
+            let cwd = logical_cwd();
+            terminal::set_title(&prompt::format_cwd(&cwd, CwdStyle::Home));
+        }
+        print!("{}", self.semantic_mark("A"));
+        let rendered = self.render_prompt();
+        self.prompt_rows = rendered.matches('\n').count() as u16;
+        let info = rendered.rsplit_once('\n').map(|f| f.0);
+        print!("{}", self.renderer.start(info));
+        self.print_prompt();
+    }
+
+    /// Collapses the prompt of the line just accepted, including the info
+    /// lines of a multi-line prompt, into the transient template.
+    fn print_transient_prompt(&mut self, transient: &str) {
+        let rows = self.prompt_rows + self.continued_lines.len() as u16;
+        let prompt = self.render_template(transient);
+        let mut lines = self.continued_lines.clone();
+        lines.push(self.input.as_str().to_string());
+        let separator = format!("\r\n{}\x1b[0m", self.render_continuation_prompt());
+        let text = format!("{}\x1b[0m{}", prompt, lines.join(&separator));
+        print!("{}", self.renderer.collapse(rows, &text));
+    }
+
+    fn print_error(&self, message: &str) {
+        if io::stderr().is_terminal() && !self.line_mode {
+            eprintln!("{}", self.theme.paint(&self.theme.error, message));
+        } else {
+            eprintln!("{}", message);
         }
-        Ok(())
     }
 
-    fn process_input(&mut self) -> Result<(), Box<dyn Error>> {
-        let input = self.input.clone();
-        let mut commands = input.split(" | ").peekable();
-        let mut previous_command: Option<Child> = None;
+    fn semantic_mark(&self, mark: &str) -> String {
+        if self.config.prompt.semantic_marks && !self.line_mode {
+            terminal::semantic_mark(mark)
+        } else {
+            String::new()
+        }
+    }
 
-        while let Some(command_group) = commands.next() {
-            let mut split_commands = command_group.split(" && ").peekable();
+    fn render_prompt(&mut self) -> String {
+        let format = self.config.prompt.format.clone();
+        self.render_template(&format)
+    }
 
-            while let Some(command) = split_commands.next() {
-                // Execute the current command
-                let mut current_command = self.execute_command(
-                    command.trim(),
-                    previous_command.take(),
-                    commands.peek().is_some(),
-                )?;
+    fn render_template(&mut self, format: &str) -> String {
+        let cwd = logical_cwd();
+        let git = if format.contains("{git}") {
+            self.git_cache.get(&cwd)
+        } else {
+            None
+        };
+        let dev_env = if format.contains("{node}") || format.contains("{rust}") {
+            self.dev_env_cache.get(&cwd)
+        } else {
+            DevEnv::default()
+        };
+        let tmux_session = if format.contains("{tmux_session}") {
+            self.tmux_session.get()
+        } else {
+            None
+        };
+        let names = self
+            .plugins
+            .segment_names()
+            .filter(|f| format.contains(&format!("{{{}}}", f)))
+            .cloned()
+            .collect::<Vec<_>>();
+        let segments = names
+            .into_iter()
+            .map(|name| {
+                let text = self.plugins.segment(&name, &cwd, self.last_status);
+                (name, text)
+            })
+            .collect();
+        prompt::render(
+            format,
+            &PromptContext {
+                cwd: &cwd,
+                cwd_style: self.config.prompt.cwd_style,
+                last_status: self.last_status,
+                last_duration: self.last_duration,
+                last_command: &self.last_command,
+                git,
+                dev_env,
+                theme: &self.theme,
+                nerd_font: self.nerd_font,
+                root_symbol: &self.config.prompt.root_symbol,
+                dir_stack: self.dir_stack.len(),
+                tmux_session,
+                segments,
+            },
+        )
+    }
 
-                // If there are more commands after &&, check the success of the previous one
-                if split_commands.peek().is_some() {
-                    if let Some(ref mut child) = current_command {
-                        let status = child.wait()?;
-                        if !status.success() {
-                            // If the current command fails, stop processing this group
-                            break;
-                        }
+    fn print_prompt(&mut self) {
+        let start = Instant::now();
+        if self.prompt_line.is_none() {
+            let rendered = if self.continued_lines.is_empty() {
+                self.render_prompt()
+            } else {
+                self.render_continuation_prompt()
+            };
+            let prompt = rendered.rsplit('\n').next().unwrap_or_default();
+            self.prompt_length = prompt::visible_width(prompt);
+            self.prompt_line = Some(format!("{}\x1b[0m{}", prompt, self.semantic_mark("B")));
+        }
+        let highlights = self.highlights();
+        let ghost = self
+            .mode
+            .ghost(self.input.as_str(), &self.suggestions)
+            .unwrap_or_default();
+        let output = self.renderer.draw(&Frame {
+            prompt: self.prompt_line.as_deref().unwrap_or_default(),
+            prompt_width: self.prompt_length,
+            input: &self.input,
+            ghost,
+            ghost_style: &self.theme.suggestion,
+            highlights: &highlights,
+        });
+        print!("{}", output);
+        log::debug!("render", took = start.elapsed(), bytes = output.len());
+        self.draw_status_bar();
+        io::stdout().flush().unwrap();
+    }
+
+    /// Styled ranges of the input when highlighting is on, starting with
+    /// the command word.
+    fn highlights(&mut self) -> Vec<(Range<usize>, String)> {
+        if !self.config.options.highlighting || !self.continued_lines.is_empty() {
+            return vec![];
+        }
+        let input = self.input.as_str();
+        let leading = input.len() - input.trim_start().len();
+        let command_end = input[leading..]
+            .find(char::is_whitespace)
+            .map_or(input.len(), |i| leading + i);
+        let mut highlights = vec![];
+        if command_end > leading {
+            let command = input[leading..command_end].to_string();
+            highlights.push((leading..command_end, self.command_style(&command)));
+        }
+        highlights.extend(self.theme.highlight(self.input.as_str()));
+        highlights
+    }
+
+    /// Style of the command word being typed: the theme's command color when
+    /// it is a builtin, alias, or executable, and the error color otherwise.
+    fn command_style(&mut self, command: &str) -> String {
+        let runnable = get_builtin(command).is_some()
+            || self.aliases.get(command).is_some()
+            || if command.contains('/') {
+                Path::new(command).is_file()
+            } else {
+                self.command_hash.contains(command)
+            }
+            || self.plugins.command(command).is_some()
+            || self.is_autocd(command);
+        if runnable {
+            self.theme.command.clone()
+        } else {
+            self.theme.error.clone()
+        }
+    }
+
+    fn print_greeting(&self) {
+        let greeting = match &self.config.greeting {
+            Some(Greeting::Text(text)) if text != "none" => text.clone(),
+            Some(Greeting::Command { command }) => {
+                let mut child = Command::new("sh");
+                child.args(["-c", command]);
+                match run_with_timeout(child, GREETING_TIMEOUT) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        self.print_error(&format!("ash: greeting command failed: {}", e));
+                        return;
                     }
                 }
-
-                // Update previous_command for the next iteration
-                previous_command = current_command;
             }
+            _ => return,
+        };
+        let greeting = greeting.trim_end();
+        if !greeting.is_empty() {
+            println!("{}", greeting);
         }
+    }
 
-        // Wait for the last command in the pipeline to finish
-        if let Some(mut final_command) = previous_command {
-            final_command.wait()?;
+    /// Reserves the bottom row for the status bar by limiting the scroll
+    /// region to the rows above it, then draws the bar.
+    fn show_status_bar(&mut self) {
+        if !self.config.status_bar.enabled {
+            return;
         }
+        let (Ok((_, rows)), Ok((_, y))) = (size(), cursor::position()) else {
+            return;
+        };
+        print!("{}", self.renderer.reserve_status_bar(rows, y + 1 >= rows));
+        self.draw_status_bar();
+    }
 
-        Ok(())
+    fn draw_status_bar(&mut self) {
+        if !self.config.status_bar.enabled {
+            return;
+        }
+        let Ok((columns, rows)) = size() else {
+            return;
+        };
+        let hints = if self.input.is_empty() {
+            "TAB browse · ↑ history · ^C clear"
+        } else if self.suggestions.is_empty() {
+            "TAB complete · ^C clear"
+        } else {
+            "→ accept · ↑↓ cycle suggestions · TAB complete"
+        };
+        let jobs = match self.jobs.len() {
+            0 => String::new(),
+            1 => "1 job │ ".to_string(),
+            count => format!("{} jobs │ ", count),
+        };
+        let text = format!(" INSERT │ {}{}", jobs, hints)
+            .chars()
+            .take(columns as usize)
+            .collect::<String>();
+        let padded = format!("{:<width$}", text, width = columns as usize);
+        let styled = self.theme.paint(&self.theme.status_bar, &padded);
+        print!("{}", self.renderer.draw_status_bar(rows, &text, &styled));
     }
 
-    fn reset_states(&mut self) {
-        self.suggestion_index = 0;
-        self.input.clear();
-        self.suggestions.clear();
+    /// Gives the whole screen back before a command runs.
+    fn hide_status_bar(&mut self) {
+        if !self.config.status_bar.enabled || self.line_mode {
+            return;
+        }
+        let Ok((_, rows)) = size() else {
+            return;
+        };
+        print!("{}", self.renderer.release_status_bar(rows));
+        io::stdout().flush().unwrap();
     }
 
-    fn execute_command(
-        &mut self,
-        command_line: &str,
-        previous_command: Option<Child>,
-        has_more_commands: bool,
-    ) -> Result<Option<Child>, Box<dyn Error>> {
-        if command_line.is_empty() {
-            return Ok(None);
+    fn handle_char_input(&mut self, c: char) -> Result<(), AshError> {
+        if c == ' ' {
+            self.expand_abbreviation();
         }
-        let parsed_command = self.parser.parse(&command_line);
-        let command = parsed_command.command.as_str();
+        self.input.insert(c);
+        self.update_suggestions();
+        self.print_prompt();
+        Ok(())
+    }
+
+    /// Replaces the command word with its abbreviation when the cursor is
+    /// right after it.
+    fn expand_abbreviation(&mut self) -> bool {
+        if !self.continued_lines.is_empty() || self.input.as_str().contains(char::is_whitespace) {
+            return false;
+        }
+        if !self.input.at_end() {
+            return false;
+        }
+        let Some(expansion) = self.config.abbreviations.get(self.input.as_str()) else {
+            return false;
+        };
+        self.set_input(expansion.clone());
+        true
+    }
 
-        match command {
-            "cd" => {
-                self.change_directory(&parsed_command.paths)?;
-                Ok(None)
+    fn handle_backspace(&mut self) -> Result<(), AshError> {
+        if self.input.delete_backward() {
+            if !self.input.is_empty() {
+                self.update_suggestions();
             }
-            "exit" | "exit;" => {
-                std::process::exit(0);
+            self.print_prompt();
+        }
+        Ok(())
+    }
+
+    fn update_suggestions(&mut self) {
+        if !self.config.options.autosuggestions {
+            self.suggestions.clear();
+            return;
+        }
+        // Typing one more character of a word only narrows down a complete
+        // list, which saves searching history and PATH on every key.
+        let narrowed = match self.suggested_for.as_deref() {
+            Some(previous) if !previous.is_empty() && !previous.ends_with(char::is_whitespace) => {
+                let input = self.input.as_str();
+                let mut added = input.strip_prefix(previous).unwrap_or("").chars();
+                matches!(
+                    (added.next(), added.next()),
+                    (Some(c), None) if !c.is_whitespace() && c != '/'
+                )
             }
-            "about" => {
-                print_about();
-                Ok(None)
+            _ => false,
+        };
+        if narrowed {
+            let input = self.input.as_str();
+            self.suggestions
+                .retain(|f| f.len() > input.len() && f.starts_with(input));
+        } else {
+            let executables = self.command_hash.executables();
+            let (history, complete) = self.history.suggest(self.input.as_str());
+            self.suggestions = get_command_suggestion(history, &executables, self.input.as_str());
+            if !complete {
+                self.suggested_for = None;
+                return;
             }
-            "pwd" => {
-                self.print_pwd();
-                Ok(None)
+        }
+        match &mut self.suggested_for {
+            Some(previous) => {
+                previous.clear();
+                previous.push_str(self.input.as_str());
             }
-            _ => {
-                let stdin = self.get_stdin(previous_command);
-                let stdout = self.get_stdout(has_more_commands);
+            None => self.suggested_for = Some(self.input.as_str().to_string()),
+        }
+    }
 
-                let resolved_command = self.resolve_path(command)?;
+    /// Suggests again for the line being edited, with history that was
+    /// still being searched when it was typed.
+    fn refresh_suggestions(&mut self) {
+        if self.input.is_empty() || !self.config.options.autosuggestions {
+            return;
+        }
+        self.suggested_for = None;
+        self.update_suggestions();
+        self.print_prompt();
+    }
 
-                let child = Command::new(resolved_command)
-                    .args(parsed_command.args)
-                    .stdin(stdin)
-                    .stdout(stdout)
-                    .spawn()?;
+    fn full_input(&self) -> String {
+        let mut lines = self.continued_lines.clone();
+        lines.push(self.input.as_str().to_string());
+        self.parser.join_lines(&lines)
+    }
 
-                Ok(Some(child))
-            }
+    /// Freezes the current line of an incomplete input and starts a
+    /// continuation line below it.
+    fn continue_line(&mut self) {
+        if self.continued_lines.is_empty() {
+            self.primary_prompt_length = self.prompt_length;
         }
+        let line = self.input.take();
+        self.continued_lines.push(line);
+        self.prompt_line = None;
+        self.suggestions.clear();
+        self.mode = Mode::Insert;
+        print!("{}", self.renderer.new_line());
+        self.print_prompt();
     }
 
-    fn print_pwd(&self) {
-        let cwd = env::current_dir().unwrap_or_default();
-        println!("{}", cwd.to_string_lossy());
+    fn render_continuation_prompt(&mut self) -> String {
+        let continuation = self.config.prompt.continuation.clone();
+        let prompt = self.render_template(&continuation);
+        let padding = if self.config.prompt.align_continuation {
+            self.primary_prompt_length
+                .saturating_sub(prompt::visible_width(&prompt))
+        } else {
+            0
+        };
+        format!("{}{}", " ".repeat(padding as usize), prompt)
     }
 
-    fn change_directory(&self, args: &[String]) -> Result<(), Box<dyn Error>> {
-        let path = args.join("/");
-        let root = Path::new(&path);
-        env::set_current_dir(&root)?;
-        Ok(())
+    fn handle_enter(&mut self) {
+        if let Some(transient) = self.config.prompt.transient.clone() {
+            self.print_transient_prompt(&transient);
+        }
+        println!();
+        if !self.continued_lines.is_empty() {
+            self.set_input(self.full_input());
+            self.continued_lines.clear();
+        }
+        self.remember_input();
     }
 
-    fn resolve_path(&self, command: &str) -> Result<String, Box<dyn Error>> {
-        if command.contains('/') {
-            Ok(command.to_string())
-        } else {
-            let path = env::var("PATH").unwrap_or_default();
-            let binary_locations = path.split(":").collect::<Vec<_>>();
-            for location in binary_locations {
-                let full_path: PathBuf = Path::new(location).join(command);
-                if full_path.exists() {
-                    return Ok(full_path.to_string_lossy().to_string());
-                }
-            }
-            Err(format!("Command not found: {}", command).into())
+    fn remember_input(&mut self) {
+        let input = self.input.as_str();
+        let ignored = self.config.history.ignore_space && input.starts_with(' ');
+        if !input.trim().is_empty() && !ignored {
+            self.history.add_command(input);
         }
     }
 
-    fn get_stdin(&self, previous_command: Option<Child>) -> Stdio {
-        previous_command
-            .and_then(|mut child| child.stdout.take())
-            .map_or(Stdio::inherit(), Stdio::from)
+    /// Reads a line without the editor, for terminals that can only show
+    /// text as it's written: a plain prompt, and no colors, suggestions, or
+    /// cursor movement. Unfinished lines are continued as in the editor,
+    /// and the end of input exits.
+    fn read_line(&mut self) -> Result<(), AshError> {
+        let mut lines = vec![];
+        loop {
+            let prompt = match lines.is_empty() {
+                true => self.render_prompt(),
+                false => self.render_continuation_prompt(),
+            };
+            print!("{}", prompt::strip_escapes(&prompt));
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                println!();
+                self.exit(self.last_status);
+            }
+            lines.push(line.trim_end_matches(['\r', '\n']).to_string());
+            let joined = self.parser.join_lines(&lines);
+            if !self.parser.is_incomplete(&joined) {
+                self.set_input(joined);
+                break;
+            }
+        }
+        self.remember_input();
+        Ok(())
     }
 
-    fn get_stdout(&self, has_more_commands: bool) -> Stdio {
-        if has_more_commands {
-            Stdio::piped()
-        } else {
-            Stdio::inherit()
+    fn process_input(&mut self, input: &str) -> Result<(), AshError> {
+        if let Some(pipeline) = input.trim_start().strip_prefix("time ") {
+            return self.time(pipeline);
+        }
+        if let Some(rest) = input.trim_start().strip_prefix("--timeout") {
+            if rest.starts_with(['=', ' ']) {
+                return self.timeout(rest);
+            }
         }
+        let input = self.aliases.expand(input);
+        let (input, background) = parser::split_background(parser::strip_terminator(&input));
+        // With `&` only the last pipeline becomes a job; the ones before it
+        // decide whether it starts at all.
+        let mut pipelines = parser::split_pipelines(input).peekable();
+        while let Some(pipeline) = pipelines.next() {
+            let background = background && pipelines.peek().is_none();
+            if !self.run_pipeline(pipeline.trim(), background)? || self.last_status != 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts every stage of a pipeline, each reading the output of the one
+    /// before, then waits for all of them unless it runs in the background.
+    /// Returns `false` when the rest of the line mustn't run.
+    fn run_pipeline(&mut self, pipeline: &str, background: bool) -> Result<bool, AshError> {
+        self.background = background;
+        self.pipeline_pgid = None;
+        let mut commands = parser::split_commands(pipeline).peekable();
+        let mut previous_command: Option<Stage> = None;
+        // The process of every stage, or the status of a builtin.
+        let mut stages: Vec<(Option<libc::pid_t>, i32)> = vec![];
+
+        while let Some(command) = commands.next() {
+            self.parser.set_variable("?", self.last_status.to_string());
+            // Builtins that succeed leave the status alone
+            self.previous_status = self.last_status;
+            self.last_status = 0;
+            let current_command = match self.execute_command(
+                command.trim(),
+                previous_command.take(),
+                commands.peek().is_some(),
+            ) {
+                Ok(current_command) => current_command,
+                Err(e) => {
+                    // The stages already started still have to be reaped.
+                    for pid in stages.iter().filter_map(|f| f.0) {
+                        jobs::wait(pid);
+                    }
+                    jobs::take_terminal();
+                    return Err(e);
+                }
+            };
+            if self.returning.is_some() {
+                return Ok(false);
+            }
+            stages.push(match &current_command {
+                Some(Stage::Process(child)) => (Some(child.id() as libc::pid_t), 0),
+                _ => (None, self.last_status),
+            });
+            previous_command = current_command;
+        }
+
+        let pids = stages.iter().filter_map(|f| f.0).collect::<Vec<_>>();
+        if background {
+            if let (Some(pid), Some(pgid)) = (pids.last(), self.pipeline_pgid) {
+                let id = self
+                    .jobs
+                    .add(pids.clone(), pgid, pipeline, JobState::Running);
+                println!("[{}] {}", id, pid);
+            }
+            return Ok(true);
+        }
+        Ok(self.wait_pipeline(stages, pipeline))
+    }
+
+    /// Waits for every process of a foreground pipeline and takes the
+    /// terminal back. Their statuses go into `$pipestatus`; with `pipefail`
+    /// the pipeline fails with the last stage that failed. When Ctrl+Z
+    /// stopped it, it becomes a stopped job and `false` is returned.
+    fn wait_pipeline(&mut self, stages: Vec<(Option<libc::pid_t>, i32)>, command: &str) -> bool {
+        let mut statuses = vec![];
+        for (i, (pid, status)) in stages.iter().enumerate() {
+            let Some(pid) = pid else {
+                statuses.push(*status);
+                continue;
+            };
+            let state = match self.deadline {
+                Some(deadline) => self.wait_or_kill(*pid, deadline, &stages[i..]),
+                None => jobs::wait_with_usage(*pid, &mut self.usage),
+            };
+            match state {
+                JobState::Done(status) => statuses.push(status),
+                state => {
+                    jobs::take_terminal();
+                    let rest = stages[i..].iter().filter_map(|f| f.0).collect::<Vec<_>>();
+                    let pgid = self.pipeline_pgid.unwrap_or(*pid);
+                    let id = self.jobs.add(rest, pgid, command, JobState::Stopped);
+                    println!("\n[{}]+  {:<24}{}", id, state.describe(), command);
+                    self.last_status = 128 + libc::SIGTSTP;
+                    return false;
+                }
+            }
+        }
+        jobs::take_terminal();
+        // The prompt would otherwise start right after `^C`.
+        if statuses.contains(&(128 + libc::SIGINT)) {
+            println!();
+        }
+        self.last_status = statuses.last().copied().unwrap_or(0);
+        if self.config.options.pipefail {
+            if let Some(failed) = statuses.iter().rev().find(|f| **f != 0) {
+                self.last_status = *failed;
+            }
+        }
+        let pipestatus = statuses.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        self.parser.set_variable("pipestatus", pipestatus.join(" "));
+        self.parser.set_variable("?", self.last_status.to_string());
+        true
+    }
+
+    /// Waits for a stage until `deadline`, then terminates the rest of the
+    /// pipeline, killing it if it's still there a moment later.
+    fn wait_or_kill(
+        &mut self,
+        pid: libc::pid_t,
+        deadline: Instant,
+        stages: &[(Option<libc::pid_t>, i32)],
+    ) -> JobState {
+        if let Some(state) = jobs::wait_until(pid, deadline, &mut self.usage) {
+            return state;
+        }
+        self.timed_out = true;
+        let pids = stages.iter().filter_map(|f| f.0).collect::<Vec<_>>();
+        let pgid = self.pipeline_pgid.unwrap_or(pid);
+        jobs::signal_pipeline(&pids, pgid, libc::SIGTERM);
+        let grace = Instant::now() + Duration::from_secs(2);
+        if let Some(state) = jobs::wait_until(pid, grace, &mut self.usage) {
+            return state;
+        }
+        jobs::signal_pipeline(&pids, pgid, libc::SIGKILL);
+        jobs::wait_with_usage(pid, &mut self.usage)
+    }
+
+    /// Runs a command line prefixed with `--timeout DURATION`, killing its
+    /// foreground pipeline once that passed. The status is then 124, as
+    /// with coreutils `timeout`.
+    fn timeout(&mut self, input: &str) -> Result<(), AshError> {
+        let input = input.trim_start();
+        let (limit, rest) = match input.strip_prefix('=') {
+            Some(rest) => rest.split_once(' ').unwrap_or((rest, "")),
+            None => input.split_once(' ').unwrap_or((input, "")),
+        };
+        let Some(duration) = parse_duration(limit) else {
+            return Err(format!("--timeout: {}: invalid duration", limit).into());
+        };
+        self.deadline = Some(Instant::now() + duration);
+        self.timed_out = false;
+        let result = self.process_input(rest);
+        self.deadline = None;
+        if self.timed_out {
+            eprintln!("ash: timed out after {}", limit);
+            self.last_status = 124;
+            self.parser.set_variable("?", self.last_status.to_string());
+        }
+        result
+    }
+
+    /// Runs `pipeline` and reports how long it took on stderr. With `-v` the
+    /// report adds the peak memory and page faults of its processes.
+    fn time(&mut self, pipeline: &str) -> Result<(), AshError> {
+        let (verbose, pipeline) = match pipeline.trim_start().strip_prefix("-v ") {
+            Some(rest) => (true, rest),
+            None => (false, pipeline),
+        };
+        let start = Instant::now();
+        let (user, sys) = jobs::children_cpu_time();
+        self.usage = Usage::default();
+        let result = self.process_input(pipeline);
+        let (user_after, sys_after) = jobs::children_cpu_time();
+        let format = |duration: Duration| {
+            let seconds = duration.as_secs_f64();
+            format!("{}m{:.3}s", (seconds / 60.0) as u64, seconds % 60.0)
+        };
+        let report = self
+            .config
+            .time
+            .format
+            .replace("{real}", &format(start.elapsed()))
+            .replace("{user}", &format(user_after.saturating_sub(user)))
+            .replace("{sys}", &format(sys_after.saturating_sub(sys)));
+        eprintln!("{}", report);
+        if verbose {
+            eprintln!("max rss\t{} KB", self.usage.max_rss);
+            eprintln!(
+                "faults\t{} major, {} minor",
+                self.usage.major_faults, self.usage.minor_faults
+            );
+        }
+        result
+    }
+
+    fn reset_states(&mut self) {
+        self.mode = Mode::Insert;
+        self.suggested_for = None;
+        self.set_input(String::new());
+        self.continued_lines.clear();
+        self.prompt_line = None;
+        self.suggestions.clear();
+    }
+
+    fn execute_command(
+        &mut self,
+        command_line: &str,
+        previous_command: Option<Stage>,
+        has_more_commands: bool,
+    ) -> Result<Option<Stage>, AshError> {
+        if command_line.is_empty() {
+            return Ok(None);
+        }
+        // `command NAME` runs NAME itself; aliases were only expanded in the
+        // first word, so dropping the prefix is enough.
+        if let Some(rest) = command_line.strip_prefix("command ") {
+            let rest = rest.trim_start();
+            if !rest.starts_with('-') {
+                return self.execute_command(rest, previous_command, has_more_commands);
+            }
+        }
+        let (parsed_command, redirection) = self.parser.parse_redirected(command_line);
+        log::debug!(
+            "parse",
+            line = command_line,
+            command = parsed_command.command,
+            args = parsed_command.args
+        );
+        let redirect = self.open_redirection(redirection.map_err(AshError::Parse)?)?;
+        let command = parsed_command.command.as_str();
+
+        if let Some(builtin) = get_builtin(command) {
+            log::debug!("spawn", command = command, kind = "builtin");
+            return self.run_builtin(
+                builtin,
+                &parsed_command.args,
+                previous_command,
+                has_more_commands,
+                redirect,
+            );
+        }
+        if parsed_command.args.is_empty() && self.is_autocd(command) {
+            log::debug!("spawn", command = command, kind = "autocd");
+            self.change_directory(Path::new(command))?;
+            return Ok(None);
+        }
+        let plugin = self.plugins.command(command).map(Path::to_path_buf);
+        if plugin.is_some() && self.restricted {
+            return Err(format!("{}: restricted: cannot run plugin commands", command).into());
+        }
+        let kind = if plugin.is_some() {
+            "plugin"
+        } else {
+            "external"
+        };
+        let resolved_command = match plugin {
+            Some(plugin) => Ok(plugin.to_string_lossy().to_string()),
+            None => self.resolve_path(command),
+        };
+        let resolved_command = match resolved_command {
+            Ok(resolved_command) => resolved_command,
+            Err(e) => {
+                let names = BUILTINS.iter().map(|f| f.0).collect::<Vec<_>>();
+                let executables = self.command_hash.executables();
+                let correction =
+                    get_command_correction(&names, &self.history.recent(), &executables, command)
+                        .filter(|_| self.interactive);
+                log::debug!(
+                    "spawn",
+                    command = command,
+                    kind = "not found",
+                    correction = correction
+                );
+                let Some(correction) = correction else {
+                    let mut hook_args = vec![command.to_string()];
+                    hook_args.extend(parsed_command.args);
+                    let hook = self.config.hooks.command_not_found.clone();
+                    let Some(status) = self.run_hook(hook.as_deref(), &hook_args) else {
+                        return Err(e);
+                    };
+                    self.last_status = status;
+                    return Ok(None);
+                };
+                let question = format!(
+                    "ash: command not found: {}, did you mean `{}`? [y/N] ",
+                    command, correction
+                );
+                if !self.confirm(&question)? {
+                    self.last_status = e.status();
+                    return Ok(None);
+                }
+                let corrected_line = command_line.replacen(command, &correction, 1);
+                return self.execute_command(&corrected_line, previous_command, has_more_commands);
+            }
+        };
+        log::debug!(
+            "spawn",
+            command = command,
+            kind = kind,
+            path = resolved_command
+        );
+        if self.interactive && self.is_destructive(command, &parsed_command.args) {
+            let question = format!(
+                "ash: `{} {}` looks destructive, run it anyway? [y/N] ",
+                command,
+                parsed_command.args.join(" ")
+            );
+            if !self.confirm(&question)? {
+                self.last_status = 1;
+                return Ok(None);
+            }
+        }
+        let stdin = self.get_stdin(previous_command);
+        let stdout = match redirect {
+            Some(file) => Stdio::from(file),
+            None => self.get_stdout(has_more_commands),
+        };
+
+        let mut child = Command::new(resolved_command);
+        if self.plugins.command(command).is_some() {
+            child.arg("run").arg(command);
+        }
+        child.args(parsed_command.args).stdin(stdin).stdout(stdout);
+        // Every pipeline gets a process group of its own, so Ctrl+C and
+        // Ctrl+Z reach only the foreground one.
+        jobs::prepare_child(
+            &mut child,
+            self.pipeline_pgid.unwrap_or(0),
+            !self.background,
+        );
+        let child = child.spawn().map_err(|e| AshError::io(command, e))?;
+        if self.pipeline_pgid.is_none() {
+            self.pipeline_pgid = Some(child.id() as libc::pid_t);
+            if !self.background {
+                jobs::set_foreground(child.id() as libc::pid_t);
+            }
+        }
+
+        Ok(Some(Stage::Process(child)))
+    }
+
+    /// Runs a builtin in the shell process. Its stdin is the output of the
+    /// previous command, and when its own output is piped it is buffered
+    /// and fed to the next command from a thread, so a large output can't
+    /// block the shell on a full pipe.
+    fn run_builtin(
+        &mut self,
+        builtin: &dyn Builtin,
+        args: &[String],
+        previous_command: Option<Stage>,
+        has_more_commands: bool,
+        redirect: Option<File>,
+    ) -> Result<Option<Stage>, AshError> {
+        self.stdin_is_terminal = previous_command.is_none() && io::stdin().is_terminal();
+        let mut stdin: Box<dyn Read> = match previous_command {
+            Some(Stage::Process(mut child)) => match child.stdout.take() {
+                Some(stdout) => Box::new(stdout),
+                None => Box::new(io::empty()),
+            },
+            Some(Stage::Output(reader)) => Box::new(reader),
+            None => Box::new(io::stdin()),
+        };
+        if let Some(mut file) = redirect {
+            self.last_status = builtin.run(self, args, &mut stdin, &mut file)?;
+            // The next command reads an empty pipe, as its input went to the
+            // file.
+            let (reader, _) = io::pipe()?;
+            return Ok(has_more_commands.then_some(Stage::Output(reader)));
+        }
+        if !has_more_commands {
+            let mut stdout = io::stdout();
+            self.last_status = builtin.run(self, args, &mut stdin, &mut stdout)?;
+            stdout.flush()?;
+            return Ok(None);
+        }
+        let mut output = vec![];
+        self.last_status = builtin.run(self, args, &mut stdin, &mut output)?;
+        let (reader, mut writer) = io::pipe()?;
+        worker::spawn(move || drop(writer.write_all(&output)));
+        Ok(Some(Stage::Output(reader)))
+    }
+
+    /// Opens the file of an output redirection (`> FILE`, `>> FILE`, or
+    /// `>| FILE`). With `noclobber`, `>` refuses to replace an existing
+    /// file while `>|` still does.
+    fn open_redirection(&self, redirection: Option<Redirection>) -> Result<Option<File>, AshError> {
+        let Some(Redirection {
+            target,
+            append,
+            force,
+        }) = redirection
+        else {
+            return Ok(None);
+        };
+        if self.restricted {
+            return Err("restricted: cannot redirect output".into());
+        }
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if append {
+            options.append(true).create(true);
+        } else if self.config.options.noclobber && !force {
+            options.create_new(true);
+        } else {
+            options.truncate(true).create(true);
+        }
+        let file = options.open(&target).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                AshError::Message(format!("{}: cannot overwrite existing file", target))
+            }
+            // The shell opens the file, so `sudo` in front of the command
+            // (or `retry-sudo`) wouldn't help.
+            io::ErrorKind::PermissionDenied => AshError::Message(format!(
+                "{}: permission denied; to write it as root, pipe into `sudo tee{} {}`",
+                target,
+                if append { " -a" } else { "" },
+                target
+            )),
+            _ => AshError::io(target.as_str(), e),
+        })?;
+        Ok(Some(file))
+    }
+
+    /// Applies the `.ash.toml` of the project the cwd is in, once it's
+    /// trusted, and undoes that of the project it left.
+    fn update_project(&mut self) {
+        let file = project::find_project_file(&logical_cwd());
+        let root = file
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf);
+        if self.project.as_ref().map(|f| &f.root) == root.as_ref() {
+            return;
+        }
+        self.leave_project();
+        let (Some(file), Some(root)) = (file, root) else {
+            return;
+        };
+        let Ok(content) = fs::read_to_string(&file) else {
+            return;
+        };
+        let trusted = match self.trust.decision(&file, &content) {
+            Some(trusted) => trusted,
+            None => {
+                let question = format!("ash: trust {}? [y/N] ", file.display());
+                let trusted = self.confirm(&question).unwrap_or(false);
+                self.trust.decide(&file, &content, trusted);
+                trusted
+            }
+        };
+        if !trusted {
+            return;
+        }
+        let project = match ProjectConfig::parse(&file, &content) {
+            Ok(project) => project,
+            Err(e) => {
+                self.print_error(&e);
+                return;
+            }
+        };
+        let aliases = project
+            .aliases
+            .iter()
+            .map(|(name, value)| {
+                let previous = self.aliases.get(name).cloned();
+                self.aliases.set(name, value);
+                (name.clone(), previous)
+            })
+            .collect();
+        // `[env]` could change PATH, which restricted mode fixes.
+        let mut env = vec![];
+        if !self.restricted {
+            env = project
+                .env
+                .keys()
+                .map(|name| (name.clone(), env::var(name).ok()))
+                .collect();
+            config::apply_env(&project.env);
+        }
+        let hooks = self.config.hooks.clone();
+        self.config.hooks.overlay(&project.hooks);
+        self.project = Some(ActiveProject {
+            root,
+            aliases,
+            env,
+            hooks,
+            overlay: project.hooks,
+        });
+    }
+
+    /// Catches up with a cwd that was `from` before: the project and the
+    /// `on_cd` hook.
+    fn directory_changed(&mut self, from: PathBuf) {
+        self.update_project();
+        self.emit(ShellEvent::DirectoryChanged {
+            from,
+            to: logical_cwd(),
+        });
+    }
+
+    /// Applies the changes direnv has, loading the `.envrc` the cwd entered
+    /// or unloading the one it left. Like direnv's hooks for other shells
+    /// it's asked before every prompt, so an edited or newly allowed
+    /// `.envrc` takes effect without a `cd`.
+    fn update_direnv(&mut self) {
+        // An `.envrc` could change PATH, which restricted mode fixes.
+        if !self.config.options.direnv || self.restricted || !self.command_hash.contains("direnv") {
+            return;
+        }
+        for (name, value) in devenv::direnv_export().unwrap_or_default() {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+
+    fn leave_project(&mut self) {
+        let Some(project) = self.project.take() else {
+            return;
+        };
+        for (name, previous) in project.aliases {
+            match previous {
+                Some(value) => self.aliases.set(&name, &value),
+                None => {
+                    self.aliases.remove(&name);
+                }
+            }
+        }
+        for (name, previous) in project.env {
+            match previous {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+        self.config.hooks = project.hooks;
+    }
+
+    /// Runs the hook of the config that handles `event`.
+    fn emit(&mut self, event: ShellEvent) {
+        let hooks = &self.config.hooks;
+        let (hook, args) = match event {
+            ShellEvent::DirectoryChanged { from, to } => (
+                hooks.on_cd.clone(),
+                vec![to.display().to_string(), from.display().to_string()],
+            ),
+            ShellEvent::CommandFailed { status, command } => (
+                hooks.on_command_error.clone(),
+                vec![status.to_string(), command],
+            ),
+            ShellEvent::Idle => (hooks.on_idle.clone(), vec![]),
+        };
+        self.run_hook(hook.as_deref(), &args);
+    }
+
+    /// Runs a hook from the config with `sh`, `args` being `$1`, `$2`, ...
+    /// The status, duration in milliseconds, and text of the last command
+    /// line are in `$ASH_LAST_STATUS`, `$ASH_LAST_DURATION`, and
+    /// `$ASH_LAST_COMMAND`. Returns its status, or `None` when it isn't set
+    /// or can't be started.
+    fn run_hook(&self, hook: Option<&str>, args: &[String]) -> Option<i32> {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(hook?)
+            .arg("ash")
+            .args(args)
+            .env("ASH_LAST_STATUS", self.last_status.to_string())
+            .env(
+                "ASH_LAST_DURATION",
+                self.last_duration.as_millis().to_string(),
+            )
+            .env("ASH_LAST_COMMAND", &self.last_command)
+            .status()
+            .ok()?;
+        Some(status.code().unwrap_or(127))
+    }
+
+    fn confirm(&mut self, question: &str) -> Result<bool, AshError> {
+        print!("{}", question);
+        io::stdout().flush()?;
+        enable_raw_mode()?;
+        let answer = loop {
+            if let Event::Key(key_event) = event::read()? {
+                break matches!(key_event.code, KeyCode::Char('y') | KeyCode::Char('Y'));
+            }
+        };
+        disable_raw_mode()?;
+        println!();
+        Ok(answer)
+    }
+
+    /// Prints `$PWD`, which keeps the symlinks the user cd'd through, or
+    /// with `-P` the physical directory.
+    fn print_pwd(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let mut physical = false;
+        for arg in args {
+            match arg.as_str() {
+                "-P" => physical = true,
+                "-L" => physical = false,
+                _ => return Err(format!("pwd: {}: invalid option", arg).into()),
+            }
+        }
+        let cwd = if physical {
+            env::current_dir()?.canonicalize()?
+        } else {
+            logical_cwd()
+        };
+        writeln!(stdout, "{}", cwd.to_string_lossy())?;
+        Ok(0)
+    }
+
+    /// Reports background jobs that finished since the last prompt.
+    fn notify_jobs(&mut self) {
+        for job in self.jobs.reap() {
+            println!("[{}]  {:<24}{}", job.id, job.state.describe(), job.command);
+        }
+    }
+
+    /// Lists jobs; `-l` adds their pids and `-p` prints only the pids.
+    fn list_jobs(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let (mut pids, mut only_pids) = (false, false);
+        for arg in args {
+            match arg.as_str() {
+                "-l" => pids = true,
+                "-p" => only_pids = true,
+                _ => return Err(format!("jobs: {}: invalid option", arg).into()),
+            }
+        }
+        let done = self.jobs.reap();
+        let jobs = self.jobs.iter().chain(&done);
+        for job in jobs {
+            if only_pids {
+                writeln!(stdout, "{}", job.pid)?;
+                continue;
+            }
+            let pid = if pids {
+                format!("{} ", job.pid)
+            } else {
+                String::new()
+            };
+            let suffix = if job.state == JobState::Running {
+                " &"
+            } else {
+                ""
+            };
+            writeln!(
+                stdout,
+                "[{}]{} {}{:<24}{}{}",
+                job.id,
+                self.jobs.marker(job.id),
+                pid,
+                job.state.describe(),
+                job.command,
+                suffix
+            )?;
+        }
+        Ok(0)
+    }
+
+    /// Continues a job with the terminal and waits for it to exit or stop.
+    fn foreground_job(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let id = self.jobs.find(args.first().map(|f| f.as_str()))?;
+        let job = self.jobs.get_mut(id).ok_or("fg: no such job")?;
+        writeln!(stdout, "{}", job.command)?;
+        stdout.flush()?;
+        jobs::set_foreground(job.pgid);
+        jobs::signal(-job.pgid, libc::SIGCONT)?;
+        let state = jobs::wait(job.pid);
+        if let JobState::Done(_) = state {
+            for pid in job.pending.drain(..) {
+                jobs::wait(pid);
+            }
+        }
+        jobs::take_terminal();
+        job.state = state;
+        match state {
+            JobState::Done(status) => {
+                self.jobs.remove(id);
+                Ok(status)
+            }
+            _ => {
+                writeln!(
+                    stdout,
+                    "\n[{}]+  {:<24}{}",
+                    id,
+                    state.describe(),
+                    job.command
+                )?;
+                Ok(128 + libc::SIGTSTP)
+            }
+        }
+    }
+
+    /// Runs the builtin NAME even when an alias of the same name shadows
+    /// it.
+    fn builtin(
+        &mut self,
+        args: &[String],
+        stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let Some((name, args)) = args.split_first() else {
+            return Ok(0);
+        };
+        let builtin =
+            get_builtin(name).ok_or_else(|| format!("builtin: {}: not a shell builtin", name))?;
+        builtin.run(self, args, stdin, stdout)
+    }
+
+    /// `command -v NAME...` prints how each name would run and `-V`
+    /// describes it in words. Running a command is handled before builtins
+    /// are looked up, since it has to work for external commands too.
+    fn command(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let (verbose, names) = match args.first().map(|f| f.as_str()) {
+            Some("-v") => (false, &args[1..]),
+            Some("-V") => (true, &args[1..]),
+            Some(option) => return Err(format!("command: {}: invalid option", option).into()),
+            None => return Ok(0),
+        };
+        let mut status = 0;
+        for name in names {
+            let description = if let Some(value) = self.aliases.get(name) {
+                match verbose {
+                    true => format!("{} is aliased to `{}'", name, value),
+                    false => format!("alias {}={}", name, parser::quote(value)),
+                }
+            } else if get_builtin(name).is_some() {
+                match verbose {
+                    true => format!("{} is a shell builtin", name),
+                    false => name.to_string(),
+                }
+            } else if let Ok(path) = self.resolve_path(name) {
+                match verbose {
+                    true => format!("{} is {}", name, path),
+                    false => path,
+                }
+            } else {
+                if verbose {
+                    self.print_error(&format!("command: {}: not found", name));
+                }
+                status = 1;
+                continue;
+            };
+            writeln!(stdout, "{}", description)?;
+        }
+        Ok(status)
+    }
+
+    /// Removes jobs from the job table, or with `-h` keeps them listed but
+    /// exempt from SIGHUP on exit. `-a` applies to every job.
+    fn disown(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let (mut no_hup, mut all, mut specs) = (false, false, vec![]);
+        for arg in args {
+            match arg.as_str() {
+                "-h" => no_hup = true,
+                "-a" => all = true,
+                spec => specs.push(spec),
+            }
+        }
+        let ids = if all {
+            self.jobs.ids()
+        } else if specs.is_empty() {
+            vec![self.jobs.find(None)?]
+        } else {
+            specs
+                .into_iter()
+                .map(|f| self.jobs.find(Some(f)))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        for id in ids {
+            match self.jobs.get_mut(id) {
+                Some(job) if no_hup => job.no_hup = true,
+                _ => self.jobs.disown(id),
+            }
+        }
+        Ok(0)
+    }
+
+    /// Continues a stopped job without waiting for it.
+    fn background_job(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let id = self.jobs.find(args.first().map(|f| f.as_str()))?;
+        let job = self.jobs.get_mut(id).ok_or("bg: no such job")?;
+        jobs::signal(-job.pgid, libc::SIGCONT)?;
+        job.state = JobState::Running;
+        writeln!(stdout, "[{}]+ {} &", id, job.command)?;
+        Ok(0)
+    }
+
+    /// `trap COMMAND CONDITION...` registers a command for signals or
+    /// `EXIT`; `-` as the command restores the defaults. Without arguments
+    /// or with `-p`, lists the registered commands.
+    fn trap(
+        &mut self,
+        args: &[String],
+        stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        match args.first().map(|f| f.as_str()) {
+            None | Some("-p") => {
+                for (condition, command) in self.traps.iter() {
+                    let wanted = args.len() < 2 || args[1..].iter().any(|f| f == condition);
+                    if wanted {
+                        writeln!(stdout, "trap -- {} {}", parser::quote(command), condition)?;
+                    }
+                }
+                Ok(0)
+            }
+            Some("-l") => self.kill(&["-l".to_string()], stdin, stdout),
+            Some(_) if args.len() == 1 => {
+                Err("trap: usage: trap [-lp] [arg signal_spec ...]".into())
+            }
+            Some(command) => {
+                let command = (command != "-").then_some(command);
+                for condition in &args[1..] {
+                    self.traps.set(condition, command)?;
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /// Runs the trap commands of the signals that arrived, keeping `$?` as
+    /// it was before them.
+    fn run_traps(&mut self) {
+        for signal in trap::take_pending() {
+            if signal == "HUP" && self.traps.get(signal).is_none() {
+                self.save_recovery(false);
+                self.exit(128 + libc::SIGHUP);
+            }
+            if let Some(command) = self.traps.get(signal).cloned() {
+                let status = self.last_status;
+                if let Err(e) = self.process_input(&command) {
+                    self.print_error(&format!("trap: {}", e));
+                }
+                self.last_status = status;
+            }
+        }
+    }
+
+    /// Saves the line being edited for the next shell, and with `history`
+    /// the commands not written to the history file, which a panic may
+    /// have caught halfway through writing.
+    fn save_recovery(&mut self, history: bool) {
+        let mut recovery = Recovery::default();
+        if self.editing {
+            recovery.buffer = self.full_input();
+        }
+        if history {
+            recovery.history = self.history.take_unsaved();
+        }
+        if recovery.buffer.trim().is_empty() && recovery.history.is_empty() {
+            return;
+        }
+        match recovery.save() {
+            Some(path) => eprintln!("ash: saved unfinished work to {}", path.display()),
+            None => log::error!("recovery", error = "can't write the recovery file"),
+        }
+    }
+
+    /// Takes back what a shell that crashed saved: its commands go into
+    /// the history, and the line it was editing waits at the first prompt.
+    fn recover(&mut self) {
+        let Some(recovery) = Recovery::take() else {
+            return;
+        };
+        for command in recovery.history.iter().rev() {
+            self.history.add_command(command);
+        }
+        if recovery.buffer.trim().is_empty() {
+            return;
+        }
+        if self.line_mode {
+            println!("ash: the line being edited when ash stopped was:");
+            println!("{}", recovery.buffer);
+        } else {
+            println!("ash: restored the line being edited when ash stopped; ^C clears it");
+            self.restored = Some(recovery.buffer);
+        }
+    }
+
+    /// Leaves the shell with `status`, truncated to a byte like every exit
+    /// status, after running its cleanup: the `EXIT` trap, then
+    /// `~/.ash_logout` for interactive shells, then saving history and
+    /// hanging up the jobs unless `huponexit` is off.
+    fn exit(&mut self, status: i32) -> ! {
+        if let Some(command) = self.traps.get("EXIT").cloned() {
+            // An `exit` inside the trap must not run it again.
+            let _ = self.traps.set("EXIT", None);
+            if let Err(e) = self.process_input(&command) {
+                self.print_error(&format!("trap: {}", e));
+            }
+        }
+        if self.interactive && !self.exiting {
+            // Same for an `exit` inside the logout script.
+            self.exiting = true;
+            let logout = home_dir().join(".ash_logout");
+            if logout.is_file() {
+                if let Err(e) = self.source_file(&logout) {
+                    self.print_error(&e.to_string());
+                }
+            }
+        }
+        let _ = self.history.flush();
+        self.control = None;
+        if self.config.options.huponexit {
+            self.jobs.hang_up();
+        }
+        std::process::exit(status & 0xff);
+    }
+
+    /// Sends a signal (TERM by default) to pids and `%job`s. `-l` lists the
+    /// signal names, or translates the given numbers into names.
+    fn kill(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let mut signal = libc::SIGTERM;
+        let mut targets = args;
+        match targets.first().map(|f| f.as_str()) {
+            Some("-l") | Some("-L") => {
+                if targets.len() == 1 {
+                    let names = jobs::SIGNALS.iter().map(|f| f.0).collect::<Vec<_>>();
+                    writeln!(stdout, "{}", names.join(" "))?;
+                }
+                for number in &targets[1..] {
+                    // Exit statuses of signaled commands are 128 + signal.
+                    let signal = number.parse::<libc::c_int>().map(|f| f & 127).unwrap_or(0);
+                    let name = jobs::signal_name(signal)
+                        .ok_or_else(|| format!("kill: {}: invalid signal specification", number))?;
+                    writeln!(stdout, "{}", name)?;
+                }
+                return Ok(0);
+            }
+            Some("-s") | Some("-n") => {
+                let name = targets.get(1).ok_or("kill: option requires an argument")?;
+                signal = jobs::parse_signal(name)
+                    .ok_or_else(|| format!("kill: {}: invalid signal specification", name))?;
+                targets = &targets[2..];
+            }
+            Some(option) if option.starts_with('-') && option.len() > 1 => {
+                signal = jobs::parse_signal(&option[1..])
+                    .ok_or_else(|| format!("kill: {}: invalid signal specification", option))?;
+                targets = &targets[1..];
+            }
+            _ => {}
+        }
+        if targets.is_empty() {
+            return Err("kill: usage: kill [-s sigspec | -sigspec] pid | %job ...".into());
+        }
+        for target in targets {
+            let pid = if target.starts_with('%') {
+                let id = self.jobs.find(Some(target))?;
+                // Jobs run in their own process group; signal all of it.
+                -self.jobs.get_mut(id).ok_or("kill: no such job")?.pgid
+            } else {
+                target.parse::<libc::pid_t>().map_err(|_| {
+                    format!("kill: {}: arguments must be process or job IDs", target)
+                })?
+            };
+            jobs::signal(pid, signal).map_err(|e| format!("kill: ({}) - {}", target, e))?;
+        }
+        Ok(0)
+    }
+
+    /// Saves the cwd and changes to `dir`. Without arguments, swaps the two
+    /// top entries; `+N`/`-N` rotates the Nth entry from the left/right to
+    /// the top.
+    fn pushd(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let mut stack = self.full_dir_stack();
+        match args.first() {
+            None => {
+                if stack.len() < 2 {
+                    return Err("pushd: no other directory".into());
+                }
+                stack.swap(0, 1);
+            }
+            Some(arg) => match parse_stack_index(arg, stack.len())? {
+                Some(index) => stack.rotate_left(index),
+                None => stack.insert(0, normalize(&logical_cwd().join(arg))),
+            },
+        }
+        self.change_directory(&stack[0])?;
+        self.dir_stack = stack.split_off(1);
+        self.print_dir_stack(stdout, false, false)?;
+        Ok(0)
+    }
+
+    /// Removes the top entry and changes to the new top, or with `+N`/`-N`
+    /// removes the Nth entry.
+    fn popd(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        if self.dir_stack.is_empty() {
+            return Err("popd: directory stack empty".into());
+        }
+        let mut stack = self.full_dir_stack();
+        let index = match args.first() {
+            Some(arg) => parse_stack_index(arg, stack.len())?
+                .ok_or_else(|| format!("popd: {}: invalid argument", arg))?,
+            None => 0,
+        };
+        stack.remove(index);
+        if index == 0 {
+            self.change_directory(&stack[0])?;
+        }
+        self.dir_stack = stack.split_off(1);
+        self.print_dir_stack(stdout, false, false)?;
+        Ok(0)
+    }
+
+    /// Changes to the most frecent visited directory matching the query, or
+    /// lists the visited directories with their scores when given none.
+    fn jump(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        if args.is_empty() {
+            for (score, dir) in self.jump_matches(&[]).iter().rev() {
+                writeln!(stdout, "{:<10.1} {}", score, dir.display())?;
+            }
+            return Ok(0);
+        }
+        // Completion inserts the full path of the match.
+        if let [path] = args {
+            if Path::new(path).is_absolute() && Path::new(path).is_dir() {
+                self.change_directory(Path::new(path))?;
+                return Ok(0);
+            }
+        }
+        let target = self
+            .jump_matches(args)
+            .into_iter()
+            .map(|f| f.1)
+            .find(|f| *f != logical_cwd())
+            .ok_or_else(|| format!("j: no match for `{}`", args.join(" ")))?;
+        writeln!(stdout, "{}", target.display())?;
+        self.change_directory(&target)?;
+        Ok(0)
+    }
+
+    /// The directories `j` could jump to for `query`, best first: from
+    /// zoxide's database when it's used, and the shell's own otherwise.
+    fn jump_matches(&mut self, query: &[String]) -> Vec<(f64, PathBuf)> {
+        if self.uses_zoxide() {
+            return zoxide::matches(query);
+        }
+        self.frecency
+            .matches(query)
+            .into_iter()
+            .map(|(score, dir)| (score, dir.to_path_buf()))
+            .collect()
+    }
+
+    fn uses_zoxide(&mut self) -> bool {
+        self.config.options.zoxide && self.command_hash.contains("zoxide")
+    }
+
+    /// Lists the directory stack: `-v` numbers the entries one per line,
+    /// `-p` prints one per line, `-c` clears it.
+    fn dirs(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let (mut verbose, mut lines) = (false, false);
+        for arg in args {
+            match arg.as_str() {
+                "-c" => {
+                    self.dir_stack.clear();
+                    return Ok(0);
+                }
+                "-v" => verbose = true,
+                "-p" => lines = true,
+                _ => return Err(format!("dirs: {}: invalid option", arg).into()),
+            }
+        }
+        self.print_dir_stack(stdout, verbose, lines)?;
+        Ok(0)
+    }
+
+    fn full_dir_stack(&self) -> Vec<PathBuf> {
+        let mut stack = vec![logical_cwd()];
+        stack.extend(self.dir_stack.iter().cloned());
+        stack
+    }
+
+    fn print_dir_stack(
+        &self,
+        stdout: &mut dyn Write,
+        verbose: bool,
+        lines: bool,
+    ) -> io::Result<()> {
+        let entries = self
+            .full_dir_stack()
+            .iter()
+            .map(|f| prompt::format_cwd(f, CwdStyle::Home))
+            .collect::<Vec<_>>();
+        if verbose {
+            for (i, entry) in entries.iter().enumerate() {
+                writeln!(stdout, "{:2}  {}", i, entry)?;
+            }
+        } else if lines {
+            writeln!(stdout, "{}", entries.join("\n"))?;
+        } else {
+            writeln!(stdout, "{}", entries.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn about(&mut self, _: &[String], _: &mut dyn Read, stdout: &mut dyn Write) -> BuiltinResult {
+        print_about(stdout)?;
+        Ok(0)
+    }
+
+    fn exit_builtin(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let status = match args.first() {
+            Some(status) => status.parse::<i32>().unwrap_or_else(|_| {
+                self.print_error(&format!("exit: {}: numeric argument required", status));
+                2
+            }),
+            None => self.previous_status,
+        };
+        self.exit(status);
+    }
+
+    /// Reads the config again and applies it to this session. The shell also
+    /// does this by itself before a prompt once the file changed.
+    fn reload(
+        &mut self,
+        _args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        self.reload_config()?;
+        Ok(0)
+    }
+
+    /// Swaps in the current config file, keeping the old config when the
+    /// file is invalid. The history file and plugins stay as they are.
+    fn reload_config(&mut self) -> Result<(), AshError> {
+        self.config_modified = config::config_modified();
+        let mut config = Config::try_load().map_err(|e| format!("reload: {}", e))?;
+        // `[env]` could change PATH, which restricted mode fixes.
+        if !self.restricted {
+            let mut env = config.env.clone();
+            if let Some(project) = &self.project {
+                env.retain(|name, _| !project.env.iter().any(|f| f.0 == *name));
+            }
+            config::apply_env(&env);
+        }
+        self.theme = load_theme(&config, self.line_mode);
+        self.autocompleter = AutoComplete::new(config.completion.clone());
+        self.nerd_font = config.prompt.glyphs.use_nerd_font();
+        self.keys = KeyBindings::from_config(&config.keys);
+        // The project's aliases and hooks stay over the new config, and it
+        // gives back the new config's when it's left.
+        for (name, value) in &config.aliases {
+            let replaced = self
+                .project
+                .as_mut()
+                .and_then(|f| f.aliases.iter_mut().find(|f| f.0 == *name));
+            match replaced {
+                Some(replaced) => replaced.1 = Some(value.clone()),
+                None => self.aliases.set(name, value),
+            }
+        }
+        if let Some(project) = &mut self.project {
+            project.hooks = config.hooks.clone();
+            config.hooks.overlay(&project.overlay);
+        }
+        // Options set with `set -o` or `shopt` outlive the file's.
+        for name in &self.options_set {
+            let value = self.config.options.get(name).unwrap_or_default();
+            if let Some(flag) = config.options.get_mut(name) {
+                *flag = value;
+            }
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    /// Replaces the shell with a fresh run of the ash binary, e.g. after
+    /// upgrading it. The cwd and environment carry over; jobs, aliases, and
+    /// everything else held in memory don't.
+    fn reload_shell(
+        &mut self,
+        _args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let exe = env::current_exe()?;
+        // Once an upgrade replaced the binary, the link names the old file.
+        let exe = match exe.to_string_lossy().strip_suffix(" (deleted)") {
+            Some(path) => PathBuf::from(path),
+            None => exe,
+        };
+        self.hide_status_bar();
+        let error = Command::new(&exe).args(env::args_os().skip(1)).exec();
+        Err(format!("reload-shell: {}: {}", exe.display(), error).into())
+    }
+
+    /// Puts its input on the system clipboard, or the previous command line
+    /// when run at the prompt with nothing piped in.
+    fn copy(&mut self, _args: &[String], stdin: &mut dyn Read, _: &mut dyn Write) -> BuiltinResult {
+        if !io::stdout().is_terminal() {
+            return Err("copy: stdout is not a terminal".into());
+        }
+        let text = if self.stdin_is_terminal {
+            self.last_command.clone()
+        } else {
+            let mut input = vec![];
+            stdin.read_to_end(&mut input)?;
+            String::from_utf8_lossy(&input).into_owned()
+        };
+        if text.is_empty() {
+            return Err("copy: nothing to copy".into());
+        }
+        terminal::copy_to_clipboard(&text);
+        Ok(0)
+    }
+
+    /// Runs the previous command line again under `sudo`. Only the first
+    /// command of a pipeline gets elevated, as if `sudo` had been typed.
+    fn retry_sudo(
+        &mut self,
+        _args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let previous = self.last_command.clone();
+        if previous.is_empty() || previous.split_whitespace().next() == Some("retry-sudo") {
+            return Err("retry-sudo: no previous command".into());
+        }
+        self.process_input(&format!("sudo {}", previous))?;
+        Ok(self.last_status)
+    }
+
+    fn true_builtin(&mut self, _: &[String], _: &mut dyn Read, _: &mut dyn Write) -> BuiltinResult {
+        Ok(0)
+    }
+
+    fn false_builtin(
+        &mut self,
+        _: &[String],
+        _: &mut dyn Read,
+        _: &mut dyn Write,
+    ) -> BuiltinResult {
+        Ok(1)
+    }
+
+    fn echo(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        stdout.write_all(&builtins::echo(args))?;
+        Ok(0)
+    }
+
+    fn printf(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        stdout.write_all(&builtins::printf(args)?)?;
+        Ok(0)
+    }
+
+    fn help(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        write!(
+            stdout,
+            "{}",
+            builtins::help(args, &self.plugins.commands(), &self.keys)?
+        )?;
+        Ok(0)
+    }
+
+    /// `test` and `[`, which needs a closing `]`. Malformed expressions
+    /// fail with status 2 rather than aborting the command line.
+    fn test(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        self.evaluate("test", args)
+    }
+
+    /// `[ EXPRESSION ]`, which is `test` with a closing `]`.
+    fn bracket(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        match args.split_last() {
+            Some((last, rest)) if last == "]" => self.evaluate("[", rest),
+            _ => {
+                self.print_error("[: missing `]`");
+                Ok(2)
+            }
+        }
+    }
+
+    fn evaluate(&mut self, name: &str, args: &[String]) -> BuiltinResult {
+        match builtins::test(args) {
+            Ok(true) => Ok(0),
+            Ok(false) => Ok(1),
+            Err(e) => {
+                self.print_error(&format!("{}: {}", name, e));
+                Ok(2)
+            }
+        }
+    }
+
+    /// Stops the file being sourced with status N, by default that of the
+    /// previous command.
+    fn return_builtin(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        if self.source_depth == 0 {
+            return Err("return: can only `return' from a function or sourced script".into());
+        }
+        let status = match args.first() {
+            Some(status) => status
+                .parse::<i32>()
+                .map_err(|_| format!("return: {}: numeric argument required", status))?,
+            None => self.previous_status,
+        };
+        self.returning = Some(status & 0xff);
+        Ok(status & 0xff)
+    }
+
+    /// `set -o NAME` turns an option on and `set +o NAME` off, as `shopt`
+    /// does. Without a name `-o` lists the options and `+o` prints them as
+    /// `set` commands.
+    fn set(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = match flag.as_str() {
+                "-o" => true,
+                "+o" => false,
+                flag => return Err(format!("set: {}: invalid option", flag).into()),
+            };
+            let Some(name) = args.next() else {
+                for name in Options::NAMES {
+                    let enabled = self.config.options.get(name).unwrap();
+                    if value {
+                        let state = if enabled { "on" } else { "off" };
+                        writeln!(stdout, "{:<16}{}", name, state)?;
+                    } else {
+                        let flag = if enabled { "-o" } else { "+o" };
+                        writeln!(stdout, "set {} {}", flag, name)?;
+                    }
+                }
+                break;
+            };
+            if !self.set_option(name, value) {
+                return Err(format!("set: {}: invalid option name", name).into());
+            }
+        }
+        Ok(0)
+    }
+
+    /// Sets an option for the rest of the session, returning whether there
+    /// is one of that name.
+    fn set_option(&mut self, name: &str, value: bool) -> bool {
+        let Some(flag) = self.config.options.get_mut(name) else {
+            return false;
+        };
+        *flag = value;
+        self.options_set.insert(name.to_string());
+        true
+    }
+
+    /// Sets (`-s`) or unsets (`-u`) interactive options. Without a flag the
+    /// named options are reported, failing if any is off; without names all
+    /// of them are, and `-p` prints them as `shopt` commands.
+    fn shopt(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let mut value = None;
+        let mut reusable = false;
+        let mut names = vec![];
+        for arg in args {
+            match arg.as_str() {
+                "-s" => value = Some(true),
+                "-u" => value = Some(false),
+                "-p" => reusable = true,
+                name if self.config.options.get(name).is_some() => names.push(name),
+                name => return Err(format!("shopt: {}: invalid option name", name).into()),
+            }
+        }
+        if let (Some(value), false) = (value, names.is_empty()) {
+            for name in names {
+                self.set_option(name, value);
+            }
+            return Ok(0);
+        }
+        let queried = !names.is_empty();
+        if !queried {
+            names = Options::NAMES.to_vec();
+        }
+        let mut status = 0;
+        for name in names {
+            let enabled = self.config.options.get(name).unwrap();
+            if value.is_some_and(|f| f != enabled) {
+                continue;
+            }
+            if queried && !enabled {
+                status = 1;
+            }
+            if reusable {
+                let flag = if enabled { "-s" } else { "-u" };
+                writeln!(stdout, "shopt {} {}", flag, name)?;
+            } else {
+                let state = if enabled { "on" } else { "off" };
+                writeln!(stdout, "{:<16}{}", name, state)?;
+            }
+        }
+        Ok(status)
+    }
+
+    fn rehash(&mut self, _: &[String], _: &mut dyn Read, _: &mut dyn Write) -> BuiltinResult {
+        self.command_hash.clear();
+        Ok(0)
+    }
+
+    fn unalias(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        if args.first().is_some_and(|f| f == "-a") {
+            self.aliases.clear();
+        }
+        for name in args.iter().filter(|f| *f != "-a") {
+            if !self.aliases.remove(name) {
+                return Err(format!("unalias: {}: not found", name).into());
+            }
+        }
+        Ok(0)
+    }
+
+    fn unset(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        for name in args {
+            self.check_variable_writable("unset", name)?;
+            env::remove_var(name);
+        }
+        Ok(0)
+    }
+
+    /// A restricted shell keeps the PATH and SHELL it was started with.
+    fn check_variable_writable(&self, builtin: &str, name: &str) -> Result<(), String> {
+        if self.restricted && (name == "PATH" || name == "SHELL") {
+            return Err(format!("{}: {}: readonly variable", builtin, name));
+        }
+        Ok(())
+    }
+
+    fn source(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        _stdout: &mut dyn Write,
+    ) -> BuiltinResult {
+        let Some(path) = args.first() else {
+            return Err("source: filename argument required".into());
+        };
+        self.source_file(Path::new(path))?;
+        Ok(self.last_status)
+    }
+
+    /// Reads the startup files of login shells: the environment that
+    /// `/etc/profile` sets up, then `~/.ash_profile`.
+    fn read_profiles(&mut self) {
+        if !self.login {
+            return;
+        }
+        import_profile(Path::new("/etc/profile"));
+        self.source_startup_file(&home_dir().join(".ash_profile"));
+    }
+
+    /// Sources a startup script if it exists. Like in bash, restricted mode
+    /// only starts applying after the startup scripts.
+    fn source_startup_file(&mut self, path: &Path) {
+        if !path.is_file() {
+            return;
+        }
+        let restricted = std::mem::replace(&mut self.restricted, false);
+        if let Err(e) = self.source_file(path) {
+            self.print_error(&e.to_string());
+        }
+        self.restricted = restricted;
+    }
+
+    /// Runs every command of a script in this shell, so it can change the
+    /// cwd, environment, and aliases. Lines are joined the same way as
+    /// multi-line input at the prompt.
+    fn source_file(&mut self, path: &Path) -> Result<(), AshError> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("source: {}: {}", path.display(), e))?;
+        self.source_depth += 1;
+        self.run_lines(&content, &path.display().to_string());
+        self.source_depth -= 1;
+        Ok(())
+    }
+
+    /// Runs a script given as `-c`, a file, or stdin, with `name` as `$0`
+    /// and `args` as `$1`, `$2`, ..., and exits with its status.
+    pub fn run_script(&mut self, script: &str, name: &str, args: &[String]) -> ! {
+        self.read_profiles();
+        self.parser.set_variable("0", name.to_string());
+        for (i, arg) in args.iter().enumerate() {
+            self.parser.set_variable(&(i + 1).to_string(), arg.clone());
+        }
+        self.run_lines(script, name);
+        self.exit(self.last_status);
+    }
+
+    /// Runs the commands of a script, joining continued lines and skipping
+    /// comments. Errors are reported with `origin` and don't stop it.
+    fn run_lines(&mut self, content: &str, origin: &str) {
+        let mut lines: Vec<String> = vec![];
+        for line in content.lines() {
+            lines.push(line.to_string());
+            let command = self.parser.join_lines(&lines);
+            if self.parser.is_incomplete(&command) {
+                continue;
+            }
+            lines.clear();
+            let command = command.trim();
+            if command.is_empty() || command.starts_with('#') {
+                continue;
+            }
+            if let Err(e) = self.process_input(command) {
+                self.print_error(&format!("{}: {}", origin, e));
+                self.last_status = e.status();
+            }
+            if let Some(status) = self.returning.take() {
+                self.last_status = status;
+                break;
+            }
+        }
+    }
+
+    /// Defines `name=value` aliases, or prints the named ones. Without
+    /// arguments, lists every alias.
+    fn alias(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        if args.is_empty() {
+            for (name, value) in self.aliases.iter() {
+                writeln!(stdout, "alias {}={}", name, parser::quote(value))?;
+            }
+            return Ok(0);
+        }
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) if !name.is_empty() => self.aliases.set(name, value),
+                _ => match self.aliases.get(arg) {
+                    Some(value) => writeln!(stdout, "alias {}={}", arg, parser::quote(value))?,
+                    None => return Err(format!("alias: {}: not found", arg).into()),
+                },
+            }
+        }
+        Ok(0)
+    }
+
+    /// Sets `NAME=value` pairs in the shell's environment so every later
+    /// command inherits them. Without arguments, lists the environment.
+    fn export(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        if args.is_empty() {
+            let mut vars = env::vars().collect::<Vec<_>>();
+            vars.sort();
+            for (name, value) in vars {
+                writeln!(stdout, "export {}={}", name, value)?;
+            }
+            return Ok(0);
+        }
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(format!("export: not a valid identifier: {}", name).into());
+            }
+            self.check_variable_writable("export", name)?;
+            // A bare `NAME` only marks an existing variable as exported,
+            // which every variable already is.
+            if let Some(value) = value {
+                env::set_var(name, value);
+            }
+        }
+        Ok(0)
+    }
+
+    /// `cd` goes home without arguments and back to `$OLDPWD` with `-`.
+    /// Relative targets that aren't in the cwd are looked up in `$CDPATH`.
+    fn cd(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        let target = match args.first().map(|f| f.as_str()) {
+            None => home_dir(),
+            Some("-") => {
+                let previous = env::var("OLDPWD").map_err(|_| "cd: OLDPWD not set")?;
+                writeln!(stdout, "{}", previous)?;
+                PathBuf::from(previous)
+            }
+            Some(path) => match find_in_cdpath(path) {
+                Some(found) => {
+                    writeln!(stdout, "{}", found.display())?;
+                    found
+                }
+                None => PathBuf::from(path),
+            },
+        };
+        self.change_directory(&target)?;
+        Ok(0)
+    }
+
+    fn change_directory(&mut self, path: &Path) -> Result<(), AshError> {
+        if self.restricted {
+            return Err("cd: restricted".into());
+        }
+        let previous = logical_cwd();
+        let target = normalize(&previous.join(path));
+        env::set_current_dir(&target).map_err(|e| format!("cd: {}: {}", path.display(), e))?;
+        env::set_var("OLDPWD", previous);
+        if self.interactive {
+            // Both keep counting, so turning zoxide off loses nothing.
+            self.frecency.visit(&target);
+            if self.uses_zoxide() {
+                zoxide::add(&target);
+            }
+        }
+        env::set_var("PWD", target);
+        Ok(())
+    }
+
+    /// Whether the `[confirm]` section asks before running this command:
+    /// it matches one of the patterns or force-pushes a protected branch.
+    fn is_destructive(&mut self, command: &str, args: &[String]) -> bool {
+        let confirm = &self.config.confirm;
+        if !confirm.enabled {
+            return false;
+        }
+        let words = iter::once(command)
+            .chain(args.iter().map(|f| f.as_str()))
+            .collect::<Vec<_>>();
+        if confirm.patterns.iter().any(|f| matches_command(f, &words)) {
+            return true;
+        }
+        if command != "git" || args.first().map(|f| f.as_str()) != Some("push") {
+            return false;
+        }
+        let force = args
+            .iter()
+            .any(|f| f == "-f" || f == "--force" || f.starts_with("--force-with-lease"));
+        if !force {
+            return false;
+        }
+        // `git push [OPTION...] REMOTE REFSPEC...`, where a refspec is
+        // `SRC:DST` or a branch pushed to the one of the same name.
+        let refspecs = args[1..]
+            .iter()
+            .filter(|f| !f.starts_with('-'))
+            .skip(1)
+            .map(|f| f.trim_start_matches('+'))
+            .map(|f| f.rsplit(':').next().unwrap_or(f).to_string())
+            .collect::<Vec<_>>();
+        let branches = if refspecs.is_empty() {
+            git::current_branch(&logical_cwd()).into_iter().collect()
+        } else {
+            refspecs
+        };
+        branches.iter().any(|branch| {
+            let branch = branch.trim_start_matches("refs/heads/");
+            self.config
+                .confirm
+                .protected_branches
+                .iter()
+                .any(|f| f == branch)
+        })
+    }
+
+    /// Whether the autocd option turns `command` into a `cd`: it names a
+    /// directory and no command.
+    fn is_autocd(&mut self, command: &str) -> bool {
+        self.config.options.autocd
+            && Path::new(command).is_dir()
+            && (command.contains('/') || !self.command_hash.contains(command))
+    }
+
+    fn resolve_path(&mut self, command: &str) -> Result<String, AshError> {
+        if command.contains('/') && self.restricted {
+            Err(format!("{}: restricted: cannot run commands by path", command).into())
+        } else if command.contains('/') {
+            Ok(command.to_string())
+        } else {
+            match self.command_hash.resolve(command) {
+                Some(path) => Ok(path.to_string_lossy().to_string()),
+                None => Err(AshError::CommandNotFound(command.to_string())),
+            }
+        }
+    }
+
+    /// Lists the remembered command paths, or with names, looks them up
+    /// ahead of time. `-r` forgets everything and `-d` forgets the named
+    /// commands.
+    fn hash(
+        &mut self,
+        args: &[String],
+        _stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> Result<i32, AshError> {
+        match args.first().map(|f| f.as_str()) {
+            None => {
+                if self.command_hash.iter().next().is_none() {
+                    writeln!(stdout, "hash: hash table empty")?;
+                    return Ok(0);
+                }
+                writeln!(stdout, "hits\tcommand")?;
+                for (_, path, hits) in self.command_hash.iter() {
+                    writeln!(stdout, "{:4}\t{}", hits, path.display())?;
+                }
+            }
+            Some("-r") => self.command_hash.clear(),
+            Some("-d") => {
+                for name in &args[1..] {
+                    if !self.command_hash.remove(name) {
+                        return Err(format!("hash: {}: not found", name).into());
+                    }
+                }
+            }
+            Some(_) => {
+                for name in args {
+                    if !self.command_hash.add(name) {
+                        return Err(format!("hash: {}: not found", name).into());
+                    }
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    fn get_stdin(&self, previous_command: Option<Stage>) -> Stdio {
+        let stdin = match previous_command {
+            // Redirected to a file, so nothing comes through the pipe.
+            Some(Stage::Process(mut child)) => {
+                Some(child.stdout.take().map_or_else(Stdio::null, Stdio::from))
+            }
+            Some(Stage::Output(reader)) => Some(Stdio::from(reader)),
+            None => None,
+        };
+        stdin.unwrap_or_else(|| match self.background {
+            true => Stdio::null(),
+            false => Stdio::inherit(),
+        })
+    }
+
+    fn get_stdout(&self, has_more_commands: bool) -> Stdio {
+        if has_more_commands {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        }
+    }
+}
+
+/// The working directory as reached through symlinks, from `$PWD` when it
+/// still points at the actual cwd.
+pub fn logical_cwd() -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_default();
+    match env::var("PWD") {
+        Ok(pwd)
+            if Path::new(&pwd).is_absolute()
+                && fs::canonicalize(&pwd).ok() == fs::canonicalize(&cwd).ok() =>
+        {
+            PathBuf::from(pwd)
+        }
+        _ => cwd,
+    }
+}
+
+/// Resolves `.` and `..` without following symlinks, like `cd -L`.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Whether a `[confirm]` pattern matches an expanded command line. The
+/// command is matched as a wildcard; the pattern's options must all be
+/// given, in any order or grouping, so `rm -rf` matches `rm -f -r -v`; and
+/// its operands must match all of the line's, or any one of them. A
+/// leading `~` in them stands for `$HOME`.
+fn matches_command(pattern: &str, words: &[&str]) -> bool {
+    let home = home_dir().display().to_string();
+    let home = home.trim_end_matches('/');
+    let pattern = pattern.split_whitespace().collect::<Vec<_>>();
+    let Some((command, rest)) = pattern.split_first() else {
+        return false;
+    };
+    let Some((name, args)) = words.split_first() else {
+        return false;
+    };
+    if !matches_pattern(command, name) {
+        return false;
+    }
+    let (wanted, operands) = split_options(rest);
+    let (given, args) = split_options(args);
+    if !wanted.iter().all(|f| given.contains(f)) {
+        return false;
+    }
+    if operands.is_empty() {
+        return true;
+    }
+    let operands = operands
+        .iter()
+        .map(|f| match f.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+            _ => f.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    // `rm -rf ~/` removes the same as `rm -rf ~`.
+    let args = args
+        .iter()
+        .map(|f| match f.trim_end_matches('/') {
+            "" => *f,
+            trimmed => trimmed,
+        })
+        .collect::<Vec<_>>();
+    matches_pattern(&operands, &args.join(" "))
+        || args.iter().any(|f| matches_pattern(&operands, f))
+}
+
+/// Splits words into the options they give, with `-rf` as `-r` and `-f`,
+/// and the operands, which are everything after `--` too.
+fn split_options<'a>(words: &[&'a str]) -> (Vec<String>, Vec<&'a str>) {
+    let mut options = vec![];
+    let mut operands = vec![];
+    let mut words = words.iter();
+    for word in words.by_ref() {
+        if *word == "--" {
+            break;
+        } else if word.starts_with("--") {
+            options.push(word.to_string());
+        } else if let Some(letters) = word.strip_prefix('-').filter(|f| !f.is_empty()) {
+            options.extend(letters.chars().map(|f| format!("-{}", f)));
+        } else {
+            operands.push(*word);
+        }
+    }
+    operands.extend(words);
+    (options, operands)
+}
+
+/// Takes over the environment a POSIX sh profile sets up. ash can't run
+/// what such scripts usually contain, so sh runs it and prints the result.
+fn import_profile(path: &Path) {
+    if !path.is_file() {
+        return;
+    }
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(". \"$1\" >/dev/null 2>&1; env -0")
+        .arg("sh")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output();
+    let Ok(output) = output else {
+        return;
+    };
+    let environment = String::from_utf8_lossy(&output.stdout);
+    for variable in environment.split('\0') {
+        let Some((name, value)) = variable.split_once('=') else {
+            continue;
+        };
+        // These describe the sh process rather than the session.
+        if matches!(name, "PWD" | "OLDPWD" | "SHLVL" | "_") {
+            continue;
+        }
+        if env::var(name).ok().as_deref() != Some(value) {
+            env::set_var(name, value);
+        }
+    }
+}
+
+/// Parses durations like `30`, `30s`, `500ms`, `1.5m`, or `2h`; bare numbers
+/// are seconds.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parses a `+N`/`-N` stack argument into an index from the top. `Ok(None)`
+/// means the argument is a directory instead.
+fn parse_stack_index(arg: &str, len: usize) -> Result<Option<usize>, AshError> {
+    let (from_right, number) = match (arg.strip_prefix('+'), arg.strip_prefix('-')) {
+        (Some(number), _) => (false, number),
+        (_, Some(number)) => (true, number),
+        _ => return Ok(None),
+    };
+    let Ok(n) = number.parse::<usize>() else {
+        return Ok(None);
+    };
+    if n >= len {
+        return Err(format!("{}: directory stack index out of range", arg).into());
+    }
+    Ok(Some(if from_right { len - 1 - n } else { n }))
+}
+
+/// Finds a relative `cd` target under one of the `$CDPATH` directories.
+/// Targets starting with `.` and ones that exist in the cwd are left to the
+/// caller, as are empty `$CDPATH` entries, which stand for the cwd.
+fn find_in_cdpath(path: &str) -> Option<PathBuf> {
+    if path.starts_with('/') || path.starts_with('.') || Path::new(path).is_dir() {
+        return None;
+    }
+    let cdpath = env::var("CDPATH").ok()?;
+    cdpath
+        .split(':')
+        .filter(|f| !f.is_empty())
+        .map(|dir| Path::new(dir).join(path))
+        .find(|f| f.is_dir())
+}
+
+/// The theme of the config, or no colors at all in line mode.
+fn load_theme(config: &Config, line_mode: bool) -> Theme {
+    match line_mode {
+        true => Theme::bundled("nocolor").unwrap(),
+        false => Theme::from_config(&config.theme),
     }
 }
@@ -1,9 +1,528 @@
-pub fn get_command_suggestion(commands: &Vec<String>, input: &str) -> Vec<String> {
-    let mut suggestions: Vec<String> = vec![];
-    for command in commands {
-        if command.starts_with(input) {
-            suggestions.push(command.clone());
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use crate::config;
+
+/// History lines sorted both by text, for prefix lookups, and by the rank
+/// of their latest use: commands run this session count up from 1, and
+/// lines of the history file count down from 0 with their age.
+#[derive(Default)]
+pub struct HistoryIndex {
+    lines: BTreeMap<String, i64>,
+    ranks: BTreeMap<i64, String>,
+    newest: i64,
+    oldest: i64,
+}
+
+impl HistoryIndex {
+    /// Adds a line run just now.
+    pub fn add(&mut self, line: &str) {
+        self.newest += 1;
+        if let Some(rank) = self.lines.insert(line.to_string(), self.newest) {
+            self.ranks.remove(&rank);
+        }
+        self.ranks.insert(self.newest, line.to_string());
+    }
+
+    /// Adds lines older than everything indexed so far, newest first.
+    pub fn add_older(&mut self, lines: impl IntoIterator<Item = String>) {
+        for line in lines {
+            if !self.lines.contains_key(&line) {
+                self.ranks.insert(self.oldest, line.clone());
+                self.lines.insert(line, self.oldest);
+            }
+            self.oldest -= 1;
+        }
+    }
+
+    /// Up to `limit` lines starting with `prefix`, most recent first.
+    pub fn matches(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let by_text = self
+            .lines
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(line, rank)| (line.clone(), *rank));
+        let by_rank = self.ranks.values().rev().cloned();
+        lockstep(by_text, by_rank, prefix, limit)
+    }
+}
+
+/// How many history lines are sorted in memory at a time while building
+/// the index; longer histories are sorted in runs and merged.
+const RUN_LINES: usize = 100_000;
+
+/// The unique lines of a history file sorted by text, each followed by a
+/// tab and the rank of its latest use, counting up from 0 for the oldest
+/// line, kept in the cache directory so finding the lines with a prefix
+/// doesn't need the history in memory. A header line says how many
+/// lines and bytes of the history it covers: as the history only grows at
+/// the front, lines added since are merged in without a rebuild.
+#[derive(Clone)]
+pub struct DiskIndex {
+    path: PathBuf,
+    history: PathBuf,
+}
+
+/// Where the index of `history` is kept: in the cache directory, named
+/// after the full path so each history file gets its own.
+fn index_path(history: &Path) -> PathBuf {
+    let history = fs::canonicalize(history).unwrap_or_else(|_| history.to_path_buf());
+    let name = history
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F");
+    config::cache_dir().join(format!("{}.index", name))
+}
+
+/// Sorted lines with their ranks, as read from an index or a run.
+type Entries = Box<dyn Iterator<Item = (String, i64)>>;
+
+impl DiskIndex {
+    /// Opens the index of `history`, building it first when it's missing
+    /// or doesn't fit the history, which takes a while for a long history,
+    /// or adding the lines the history gained since.
+    pub fn open(history: &Path) -> io::Result<Self> {
+        let index = DiskIndex {
+            path: index_path(history),
+            history: history.to_path_buf(),
+        };
+        fs::create_dir_all(config::cache_dir())?;
+        let length = fs::metadata(history)?.len();
+        match index.header() {
+            Some((_, bytes)) if bytes == length => {}
+            Some((lines, bytes)) if bytes < length && index.starts_line(length - bytes) => {
+                index.update(lines, length - bytes)?
+            }
+            _ => index.build()?,
+        }
+        Ok(index)
+    }
+
+    /// The lines and bytes of the history the index covers.
+    fn header(&self) -> Option<(i64, u64)> {
+        let mut header = String::new();
+        BufReader::new(File::open(&self.path).ok()?)
+            .read_line(&mut header)
+            .ok()?;
+        let (lines, bytes) = header.strip_prefix("#")?.trim().split_once(' ')?;
+        Some((lines.parse().ok()?, bytes.parse().ok()?))
+    }
+
+    /// Whether a line of the history starts `offset` bytes in, as the
+    /// oldest line the index covers does unless the history was rewritten.
+    fn starts_line(&self, offset: u64) -> bool {
+        let mut byte = [0];
+        File::open(&self.history)
+            .and_then(|mut f| {
+                f.seek(SeekFrom::Start(offset - 1))?;
+                f.read_exact(&mut byte)
+            })
+            .is_ok_and(|_| byte[0] == b'\n')
+    }
+
+    /// Sorts the history in runs of `RUN_LINES` written aside, then merges
+    /// them, so memory doesn't grow with the history.
+    fn build(&self) -> io::Result<()> {
+        self.remove_stale();
+        let mut reader = BufReader::new(File::open(&self.history)?);
+        let mut runs = vec![];
+        let (mut lines, mut bytes) = (0, 0);
+        loop {
+            let mut ranks = HashMap::new();
+            let mut line = String::new();
+            while ranks.len() < RUN_LINES {
+                line.clear();
+                let read = reader.read_line(&mut line)?;
+                if read == 0 {
+                    break;
+                }
+                bytes += read as u64;
+                let text = line.strip_suffix('\n').unwrap_or(&line);
+                // Counted from the newest until the number of lines is known.
+                ranks.entry(text.to_string()).or_insert(-lines);
+                lines += 1;
+            }
+            if ranks.is_empty() {
+                break;
+            }
+            let path = self.aside(&format!("run{}", runs.len()));
+            write_sorted(&path, None, vec![sorted(ranks).into_iter()])?;
+            runs.push(path);
+        }
+        let entries = runs
+            .iter()
+            .map(|f| {
+                let entries = read_entries(BufReader::new(File::open(f)?));
+                Ok(Box::new(entries.map(move |(line, rank)| (line, rank + lines - 1))) as Entries)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let written = self.replace(lines, bytes, entries);
+        for run in runs {
+            let _ = fs::remove_file(run);
+        }
+        written
+    }
+
+    /// Merges in the `added` bytes at the front of the history, which
+    /// came after the `covered` lines the index has.
+    fn update(&self, covered: i64, added: u64) -> io::Result<()> {
+        self.remove_stale();
+        let mut new = String::new();
+        File::open(&self.history)?
+            .take(added)
+            .read_to_string(&mut new)?;
+        let new = new.lines().collect::<Vec<_>>();
+        let mut ranks = HashMap::new();
+        for (age, line) in new.iter().enumerate() {
+            ranks
+                .entry(line.to_string())
+                .or_insert(covered + (new.len() - 1 - age) as i64);
+        }
+        let mut index = BufReader::new(File::open(&self.path)?);
+        index.read_line(&mut String::new())?;
+        let old = Box::new(read_entries(index)) as Entries;
+        let length = fs::metadata(&self.history)?.len();
+        let lines = covered + new.len() as i64;
+        self.replace(
+            lines,
+            length,
+            vec![old, Box::new(sorted(ranks).into_iter())],
+        )
+    }
+
+    /// Writes the index of the first `lines` and `bytes` of the history
+    /// from sorted entries, aside and renamed so another shell never reads
+    /// half of it.
+    fn replace(&self, lines: i64, bytes: u64, entries: Vec<Entries>) -> io::Result<()> {
+        let partial = self.aside("partial");
+        write_sorted(&partial, Some((lines, bytes)), entries)?;
+        fs::rename(partial, &self.path)
+    }
+
+    /// A file next to the index for this process to write.
+    fn aside(&self, name: &str) -> PathBuf {
+        let extension = format!("index.{}.{}", name, std::process::id());
+        self.path.with_extension(extension)
+    }
+
+    /// Removes the files written aside by shells that are gone, which one
+    /// killed while writing leaves behind.
+    fn remove_stale(&self) {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+        for path in entries.flatten().map(|f| f.path()) {
+            let pid = path
+                .file_name()
+                .and_then(|f| f.to_str())
+                .filter(|f| f.starts_with(&prefix))
+                .and_then(|f| f.rsplit('.').next()?.parse::<libc::pid_t>().ok());
+            let Some(pid) = pid else {
+                continue;
+            };
+            let gone = unsafe { libc::kill(pid, 0) } == -1
+                && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH);
+            if gone {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Up to `limit` lines starting with `prefix`, most recent first.
+    pub fn matches(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let (Ok(index), Ok(history)) = (File::open(&self.path), File::open(&self.history)) else {
+            return vec![];
+        };
+        let mut index = BufReader::new(index);
+        let mut header = String::new();
+        if index.read_line(&mut header).is_err() {
+            return vec![];
+        }
+        let Ok(start) = first_at_least(&mut index, header.len() as u64, prefix) else {
+            return vec![];
+        };
+        if index.seek(SeekFrom::Start(start)).is_err() {
+            return vec![];
+        }
+        let by_rank = BufReader::new(history).lines().map_while(Result::ok);
+        lockstep(read_entries(index), by_rank, prefix, limit)
+    }
+}
+
+fn sorted(ranks: HashMap<String, i64>) -> Vec<(String, i64)> {
+    let mut sorted = ranks.into_iter().collect::<Vec<_>>();
+    sorted.sort_unstable();
+    sorted
+}
+
+/// The `line<TAB>rank` entries of an index or a run.
+fn read_entries(reader: impl BufRead) -> impl Iterator<Item = (String, i64)> {
+    reader.lines().map_while(Result::ok).filter_map(|f| {
+        let (line, rank) = f.rsplit_once('\t')?;
+        Some((line.to_string(), rank.parse().ok()?))
+    })
+}
+
+/// Writes the sorted `entries` merged into one sorted list, with the
+/// latest rank of a line found in several, under the optional header.
+fn write_sorted(
+    path: &Path,
+    header: Option<(i64, u64)>,
+    mut entries: Vec<impl Iterator<Item = (String, i64)>>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if let Some((lines, bytes)) = header {
+        writeln!(writer, "# {} {}", lines, bytes)?;
+    }
+    let mut heap = BinaryHeap::new();
+    for (i, entries) in entries.iter_mut().enumerate() {
+        if let Some((line, rank)) = entries.next() {
+            heap.push(Reverse((line, i, rank)));
+        }
+    }
+    let mut last: Option<(String, i64)> = None;
+    while let Some(Reverse((line, i, rank))) = heap.pop() {
+        if let Some(next) = entries[i].next() {
+            heap.push(Reverse((next.0, i, next.1)));
+        }
+        match &mut last {
+            Some(last) if last.0 == line => last.1 = last.1.max(rank),
+            _ => {
+                if let Some((line, rank)) = last.replace((line, rank)) {
+                    writeln!(writer, "{}\t{}", line, rank)?;
+                }
+            }
+        }
+    }
+    if let Some((line, rank)) = last {
+        writeln!(writer, "{}\t{}", line, rank)?;
+    }
+    writer.flush()
+}
+
+/// Where the first line of the sorted `index` from `start` on that isn't
+/// less than `prefix` starts, found by bisecting byte offsets.
+fn first_at_least(index: &mut BufReader<File>, start: u64, prefix: &str) -> io::Result<u64> {
+    let (mut low, mut high) = (start, index.get_ref().metadata()?.len());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let (start, line) = line_from(index, middle)?;
+        match line {
+            Some(line) if line.as_slice() < prefix.as_bytes() => low = start + 1,
+            _ => high = middle,
+        }
+    }
+    Ok(line_from(index, low)?.0)
+}
+
+/// The first line starting at `offset` or after, and where it starts.
+fn line_from(index: &mut BufReader<File>, offset: u64) -> io::Result<(u64, Option<Vec<u8>>)> {
+    let mut start = offset;
+    let mut line = vec![];
+    if offset > 0 {
+        // The line `offset` falls in is skipped, unless it starts right there.
+        index.seek(SeekFrom::Start(offset - 1))?;
+        start += index.read_until(b'\n', &mut line)? as u64 - 1;
+        line.clear();
+    } else {
+        index.seek(SeekFrom::Start(0))?;
+    }
+    if index.read_until(b'\n', &mut line)? == 0 {
+        return Ok((start, None));
+    }
+    let end = line.iter().rposition(|f| *f == b'\t').unwrap_or(line.len());
+    line.truncate(end);
+    Ok((start, Some(line)))
+}
+
+/// Up to `limit` lines starting with `prefix`, most recent first, from
+/// the lines sorted by text with their ranks and the lines from the most
+/// recent.
+///
+/// Walks both in lockstep: a rare prefix runs out of matches first and
+/// they are sorted, a common one fills `limit` from the recent lines
+/// first, so neither visits much more than it returns.
+fn lockstep(
+    by_text: impl Iterator<Item = (String, i64)>,
+    mut by_rank: impl Iterator<Item = String>,
+    prefix: &str,
+    limit: usize,
+) -> Vec<String> {
+    let mut by_text = by_text.take_while(|(line, _)| line.starts_with(prefix));
+    let mut sorted = vec![];
+    let mut recent: Vec<String> = vec![];
+    loop {
+        let Some(found) = by_text.next() else {
+            sorted.sort_by_key(|(_, rank): &(String, i64)| -rank);
+            return sorted
+                .into_iter()
+                .take(limit)
+                .map(|(line, _)| line)
+                .collect();
+        };
+        sorted.push(found);
+        match by_rank.next() {
+            Some(line) if line.starts_with(prefix) && !recent.contains(&line) => recent.push(line),
+            Some(_) => {}
+            None => return recent,
+        }
+        if recent.len() == limit {
+            return recent;
+        }
+    }
+}
+
+/// `history` lines starting with `input`, then completions of its last
+/// word from the filesystem, or from `executables` for the command word.
+pub fn get_command_suggestion(
+    history: Vec<String>,
+    executables: &[String],
+    input: &str,
+) -> Vec<String> {
+    let mut suggestions = history;
+
+    // History matches are what the user actually typed before, so they always
+    // rank above anything guessed from the filesystem.
+    for suggestion in get_filesystem_suggestion(executables, input) {
+        if !suggestions.contains(&suggestion) {
+            suggestions.push(suggestion);
         }
     }
     suggestions
 }
+
+fn get_filesystem_suggestion(executables: &[String], input: &str) -> Vec<String> {
+    if input.ends_with(' ') {
+        return vec![];
+    }
+    let (head, word) = match input.rfind(' ') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    };
+    if word.is_empty() {
+        return vec![];
+    }
+
+    let mut candidates = if head.trim().is_empty() && !word.contains('/') {
+        get_executable_candidates(executables, word)
+    } else {
+        get_path_candidates(word)
+    };
+
+    // Shorter completions are closer to what has been typed so far.
+    candidates.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+    candidates
+        .into_iter()
+        .map(|candidate| format!("{}{}", head, candidate))
+        .collect()
+}
+
+fn get_path_candidates(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let search_dir = if dir.is_empty() {
+        ".".to_string()
+    } else if let Some(rest) = dir.strip_prefix('~') {
+        format!("{}{}", env::var("HOME").unwrap_or_default(), rest)
+    } else {
+        dir.to_string()
+    };
+
+    let Ok(entries) = fs::read_dir(search_dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with(prefix)
+                || file_name == prefix
+                || (file_name.starts_with('.') && !prefix.starts_with('.'))
+            {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(format!(
+                "{}{}{}",
+                dir,
+                file_name,
+                if is_dir { "/" } else { "" }
+            ))
+        })
+        .collect()
+}
+
+fn get_executable_candidates(executables: &[String], prefix: &str) -> Vec<String> {
+    executables
+        .iter()
+        .filter(|f| f.starts_with(prefix) && *f != prefix)
+        .cloned()
+        .collect()
+}
+
+/// Finds the closest known command to a mistyped one, looking at builtins,
+/// commands from history and executables on PATH, in that order of preference.
+pub fn get_command_correction(
+    builtins: &[&str],
+    commands: &[String],
+    executables: &[String],
+    command: &str,
+) -> Option<String> {
+    let max_distance = if command.chars().count() <= 3 { 1 } else { 2 };
+    let history_commands = commands
+        .iter()
+        .filter_map(|line| line.split_whitespace().next().map(|f| f.to_string()));
+    let candidates = builtins
+        .iter()
+        .map(|f| f.to_string())
+        .chain(history_commands)
+        .chain(executables.iter().cloned());
+
+    let mut best: Option<(usize, String)> = None;
+    for candidate in candidates {
+        if candidate == command {
+            continue;
+        }
+        let distance = edit_distance(command, &candidate);
+        if distance <= max_distance && best.as_ref().is_none_or(|(d, _)| distance < *d) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+/// Optimal string alignment distance, so a swapped pair of letters (`gti`)
+/// costs a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            rows[i][j] = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    rows[a.len()][b.len()]
+}
@@ -0,0 +1,115 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use std::io::{self, Write};
+use std::os::fd::RawFd;
+use std::panic;
+use std::time::Duration;
+
+use crate::config::CursorShape;
+use crate::tmux;
+
+/// Sets the terminal window/tab title with OSC 0, or the pane title with
+/// OSC 2 inside tmux, dropping control characters that would end the
+/// sequence early.
+pub fn set_title(title: &str) {
+    let title = title
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    if tmux::inside() {
+        print!("\x1b]2;{}\x1b\\", title);
+    } else {
+        print!("\x1b]0;{}\x07", title);
+    }
+    let _ = io::stdout().flush();
+}
+
+/// Puts `text` on the system clipboard with OSC 52. The terminal does the
+/// copying, so it works over SSH where no clipboard tool can.
+pub fn copy_to_clipboard(text: &str) {
+    let sequence = format!("\x1b]52;c;{}\x07", BASE64_STANDARD.encode(text));
+    if tmux::inside() {
+        print!("{}", tmux::passthrough(&sequence));
+    } else {
+        print!("{}", sequence);
+    }
+    let _ = io::stdout().flush();
+}
+
+/// Sets the cursor shape with DECSCUSR; `Default` hands it back to the
+/// terminal's own setting.
+pub fn set_cursor_shape(shape: CursorShape) {
+    let code = match shape {
+        CursorShape::Default => 0,
+        CursorShape::Block => 2,
+        CursorShape::Underline => 4,
+        CursorShape::Bar => 6,
+    };
+    print!("\x1b[{} q", code);
+    let _ = io::stdout().flush();
+}
+
+/// OSC 133 shell-integration mark: `A` prompt start, `B` input start, `C`
+/// command output start and `D;<status>` command end. Terminals use these to
+/// jump between prompts and flag failed commands.
+pub fn semantic_mark(mark: &str) -> String {
+    format!("\x1b]133;{}\x07", mark)
+}
+
+/// Waits until the terminal has input, `wake` is readable, or `timeout`
+/// passes. Fails once the terminal is gone, where crossterm would keep
+/// polling it forever.
+pub fn wait_for_input(timeout: Duration, wake: RawFd) -> io::Result<()> {
+    let mut fds = [libc::STDIN_FILENO, wake].map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    if unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout.as_millis() as libc::c_int) } == -1 {
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    if fds[0].revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0 {
+        return Err(io::Error::new(io::ErrorKind::BrokenPipe, "terminal closed"));
+    }
+    Ok(())
+}
+
+/// Keeps the terminal in raw mode while alive and restores it when dropped,
+/// including while unwinding from a panic.
+pub struct RawMode;
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Puts the terminal back the way other programs expect it: cooked mode,
+/// a visible cursor, no scroll region, and default colors.
+pub fn restore() {
+    let _ = disable_raw_mode();
+    print!("\x1b[?25h\x1b[r\x1b[0m");
+    let _ = io::stdout().flush();
+}
+
+/// Restores the terminal before a panic message is printed, so it isn't
+/// smeared across a raw-mode screen and the shell that started ash stays
+/// usable.
+pub fn install_panic_hook() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        eprintln!();
+        default(info);
+    }));
+}
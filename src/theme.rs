@@ -0,0 +1,291 @@
+use serde::{Deserialize, Deserializer};
+use std::ops::Range;
+
+/// Names accepted by `theme = "..."`.
+pub const THEMES: &[&str] = &["default", "minimal", "solarized", "nocolor"];
+
+/// The `[theme]` section: a bundled theme to start from plus optional
+/// per-group overrides written as style specs like `"bold #ff8800"`.
+/// `theme = "NAME"` is short for a section with only the name.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub name: String,
+    pub prompt: Option<String>,
+    pub suggestion: Option<String>,
+    pub completion: Option<String>,
+    pub error: Option<String>,
+    pub root: Option<String>,
+    pub status_bar: Option<String>,
+    pub command: Option<String>,
+    pub string: Option<String>,
+    pub variable: Option<String>,
+    pub operator: Option<String>,
+    pub option: Option<String>,
+}
+
+impl ThemeConfig {
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Spec {
+            Name(String),
+            Table(Box<ThemeConfig>),
+        }
+        Ok(match Spec::deserialize(deserializer)? {
+            Spec::Name(name) => ThemeConfig {
+                name,
+                ..ThemeConfig::default()
+            },
+            Spec::Table(config) => *config,
+        })
+    }
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig {
+            name: "default".to_string(),
+            prompt: None,
+            suggestion: None,
+            completion: None,
+            error: None,
+            root: None,
+            status_bar: None,
+            command: None,
+            string: None,
+            variable: None,
+            operator: None,
+            option: None,
+        }
+    }
+}
+
+/// Resolved escape sequences for every colored part of the shell.
+pub struct Theme {
+    /// Color of `{prompt_color}` in the prompt template.
+    pub prompt: String,
+    /// Ghost text of the inline suggestion.
+    pub suggestion: String,
+    /// Directories in the completion listing.
+    pub completion: String,
+    /// Error messages.
+    pub error: String,
+    /// The prompt symbol of root shells.
+    pub root: String,
+    /// The bottom status bar.
+    pub status_bar: String,
+    /// A command word that can be run, when highlighting is on. Unknown
+    /// commands use the error color.
+    pub command: String,
+    /// Quoted strings in the input, when highlighting is on.
+    pub string: String,
+    /// `$NAME` in the input.
+    pub variable: String,
+    /// Pipes, `&&`, `||`, `&`, and redirections in the input.
+    pub operator: String,
+    /// Words starting with `-` in the input.
+    pub option: String,
+    /// Disables the `{red}`-style color placeholders in prompt templates.
+    pub plain: bool,
+}
+
+impl Theme {
+    pub fn from_config(config: &ThemeConfig) -> Self {
+        let mut theme = Theme::bundled(&config.name).unwrap_or_else(|| {
+            eprintln!(
+                "ash: unknown theme `{}` (expected one of {}), using default",
+                config.name,
+                THEMES.join(", ")
+            );
+            Theme::bundled("default").unwrap()
+        });
+        let overrides = [
+            (&config.prompt, &mut theme.prompt),
+            (&config.suggestion, &mut theme.suggestion),
+            (&config.completion, &mut theme.completion),
+            (&config.error, &mut theme.error),
+            (&config.root, &mut theme.root),
+            (&config.status_bar, &mut theme.status_bar),
+            (&config.command, &mut theme.command),
+            (&config.string, &mut theme.string),
+            (&config.variable, &mut theme.variable),
+            (&config.operator, &mut theme.operator),
+            (&config.option, &mut theme.option),
+        ];
+        for (spec, target) in overrides {
+            if let Some(spec) = spec {
+                *target = style(spec);
+            }
+        }
+        theme
+    }
+
+    pub fn bundled(name: &str) -> Option<Self> {
+        // prompt, suggestion, completion, error, root, status bar, command,
+        // string, variable, operator, option
+        let specs = match name {
+            "default" => [
+                "blue",
+                "dim",
+                "bold blue",
+                "red",
+                "bold red",
+                "reverse",
+                "green",
+                "yellow",
+                "cyan",
+                "magenta",
+                "",
+            ],
+            "minimal" => [
+                "bold", "dim", "bold", "red", "bold", "reverse", "bold", "", "", "", "",
+            ],
+            "solarized" => [
+                "#268bd2",
+                "#586e75",
+                "bold #268bd2",
+                "#dc322f",
+                "bold #dc322f",
+                "reverse #93a1a1",
+                "#859900",
+                "#2aa198",
+                "#b58900",
+                "#d33682",
+                "#6c71c4",
+            ],
+            // Dimming and reverse video aren't colors, and without them the
+            // ghost text and status bar would blend into everything else.
+            "nocolor" => ["", "dim", "", "", "", "reverse", "", "", "", "", ""],
+            _ => return None,
+        };
+        let mut specs = specs.into_iter().map(style);
+        let mut next = || specs.next().unwrap();
+        Some(Theme {
+            prompt: next(),
+            suggestion: next(),
+            completion: next(),
+            error: next(),
+            root: next(),
+            status_bar: next(),
+            command: next(),
+            string: next(),
+            variable: next(),
+            operator: next(),
+            option: next(),
+            plain: name == "nocolor",
+        })
+    }
+
+    /// Styled spans of `input` other than the command word: quoted
+    /// strings, variables, operators, and options.
+    pub fn highlight(&self, input: &str) -> Vec<(Range<usize>, String)> {
+        let bytes = input.as_bytes();
+        let mut spans = vec![];
+        let mut word_start = true;
+        let mut i = 0;
+        while i < bytes.len() {
+            let rest = &input[i..];
+            let (length, style) = match bytes[i] {
+                quote @ (b'\'' | b'"') => (
+                    rest[1..].find(quote as char).map_or(rest.len(), |f| f + 2),
+                    &self.string,
+                ),
+                b'$' => (
+                    rest[1..]
+                        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '?'))
+                        .map_or(rest.len(), |f| f + 1),
+                    &self.variable,
+                ),
+                b'|' | b'&' | b'>' | b'<' => (
+                    rest.find(|c| !matches!(c, '|' | '&' | '>' | '<'))
+                        .unwrap_or(rest.len()),
+                    &self.operator,
+                ),
+                b'-' if word_start => (
+                    rest.find(char::is_whitespace).unwrap_or(rest.len()),
+                    &self.option,
+                ),
+                _ => {
+                    // Step over whole characters, so `rest` never starts
+                    // inside one.
+                    let c = rest.chars().next().unwrap_or(' ');
+                    word_start = c.is_whitespace();
+                    i += c.len_utf8();
+                    continue;
+                }
+            };
+            if !style.is_empty() {
+                spans.push((i..i + length, style.clone()));
+            }
+            word_start = false;
+            i += length;
+        }
+        spans
+    }
+
+    pub fn paint(&self, color: &str, text: &str) -> String {
+        if color.is_empty() {
+            return text.to_string();
+        }
+        format!("{}{}\x1b[0m", color, text)
+    }
+}
+
+/// Turns a style spec of space-separated words (`bold`, `dim`, `italic`,
+/// `underline`, `reverse`, color names, `bright-<color>`, `0`-`255`, `#rrggbb`) into an
+/// SGR escape sequence. Unknown words are ignored.
+pub fn style(spec: &str) -> String {
+    let mut codes: Vec<String> = vec![];
+    for word in spec.split_whitespace() {
+        let code = match word {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            "reverse" => "7".to_string(),
+            word => {
+                if let Some(index) = color_index(word) {
+                    (30 + index).to_string()
+                } else if let Some(index) = word.strip_prefix("bright-").and_then(color_index) {
+                    (90 + index).to_string()
+                } else if let Ok(index) = word.parse::<u8>() {
+                    format!("38;5;{}", index)
+                } else if let Some((r, g, b)) = parse_hex(word) {
+                    format!("38;2;{};{};{}", r, g, b)
+                } else {
+                    continue;
+                }
+            }
+        };
+        codes.push(code);
+    }
+    if codes.is_empty() {
+        return String::new();
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+fn color_index(name: &str) -> Option<u8> {
+    let index = match name {
+        "black" => 0,
+        "red" => 1,
+        "green" => 2,
+        "yellow" => 3,
+        "blue" => 4,
+        "magenta" => 5,
+        "cyan" => 6,
+        "white" => 7,
+        _ => return None,
+    };
+    Some(index)
+}
+
+fn parse_hex(word: &str) -> Option<(u8, u8, u8)> {
+    let hex = word.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+/// How long an interactive shell may take to get to its first prompt.
+const STARTUP_BUDGET: Duration = Duration::from_millis(5);
+
+/// Times the steps of starting the shell, for the report printed before
+/// the first prompt when `ASH_STARTUP_TIMING` is set.
+pub struct StartupTimer {
+    start: Instant,
+    last: Instant,
+    steps: Vec<(&'static str, Duration)>,
+}
+
+impl StartupTimer {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        StartupTimer {
+            start: now,
+            last: now,
+            steps: vec![],
+        }
+    }
+
+    /// Records the time since the previous step as `name`'s.
+    pub fn step(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.steps.push((name, now - self.last));
+        self.last = now;
+    }
+
+    pub fn total(&self) -> Duration {
+        self.last - self.start
+    }
+
+    /// The report, or `None` when it wasn't asked for.
+    pub fn report(&self) -> Option<String> {
+        env::var_os("ASH_STARTUP_TIMING")?;
+        let total = self.total();
+        let mut report = format!("ash: startup took {}", millis(total));
+        if total > STARTUP_BUDGET {
+            report.push_str(&format!(", over the {} budget", millis(STARTUP_BUDGET)));
+        }
+        for (name, duration) in &self.steps {
+            report.push_str(&format!("\n  {:<10} {:>9}", name, millis(*duration)));
+        }
+        Some(report)
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
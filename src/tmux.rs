@@ -0,0 +1,61 @@
+use std::env;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::autocomplete::run_with_timeout;
+use crate::worker::{self, Message};
+
+/// Whether ash is running in a tmux pane.
+pub fn inside() -> bool {
+    env::var_os("TMUX").is_some_and(|f| !f.is_empty())
+}
+
+/// The name of the tmux session the pane belongs to, for the prompt. Like
+/// the git status it's asked for in the background once per command, as
+/// the session can be renamed; until tmux answers, the prompt shows the
+/// name it had last.
+#[derive(Default)]
+pub struct SessionName {
+    name: Option<String>,
+    current: bool,
+}
+
+impl SessionName {
+    pub fn get(&mut self) -> Option<String> {
+        if !self.current && inside() {
+            self.current = true;
+            worker::spawn(|| worker::send(Message::TmuxSession(session_name())));
+        }
+        self.name.clone()
+    }
+
+    /// Stores what tmux said, returning whether the prompt would show
+    /// something else now.
+    pub fn update(&mut self, name: Option<String>) -> bool {
+        let changed = self.name != name;
+        self.name = name;
+        changed
+    }
+
+    /// Asks again for the next prompt; called after each command.
+    pub fn clear(&mut self) {
+        self.current = false;
+    }
+}
+
+fn session_name() -> Option<String> {
+    if !inside() {
+        return None;
+    }
+    let mut command = Command::new("tmux");
+    command.args(["display-message", "-p", "#S"]);
+    let name = run_with_timeout(command, Duration::from_millis(200)).ok()?;
+    Some(name.trim_end().to_string()).filter(|f| !f.is_empty())
+}
+
+/// Wraps an escape sequence in tmux's passthrough DCS, so tmux hands it to
+/// the terminal outside rather than acting on it or dropping it. tmux 3.3
+/// and later only pass it on with `allow-passthrough` set.
+pub fn passthrough(sequence: &str) -> String {
+    format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::jobs;
+
+/// One bit per signal that arrived since the shell last ran its traps.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn record_signal(signal: libc::c_int) {
+    PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Commands registered with `trap`, keyed by signal name or `EXIT`. The
+/// signal handler only records the signal; the commands run from the main
+/// loop, between commands or while waiting for a key.
+#[derive(Default)]
+pub struct Traps {
+    commands: BTreeMap<String, String>,
+}
+
+impl Traps {
+    /// Registers `command` for a signal or `EXIT`. An empty command ignores
+    /// the signal and `None` restores its default behavior.
+    pub fn set(&mut self, condition: &str, command: Option<&str>) -> Result<(), String> {
+        let name = normalize(condition)?;
+        if name != "EXIT" {
+            let signal = jobs::parse_signal(&name).unwrap();
+            if signal == libc::SIGKILL || signal == libc::SIGSTOP {
+                return Err(format!("trap: {}: cannot be trapped", condition));
+            }
+            let handler = match command {
+                None => default_handler(signal),
+                Some("") => libc::SIG_IGN,
+                Some(_) => record_signal as *const () as libc::sighandler_t,
+            };
+            unsafe { libc::signal(signal, handler) };
+        }
+        match command {
+            Some(command) => self.commands.insert(name, command.to_string()),
+            None => self.commands.remove(&name),
+        };
+        Ok(())
+    }
+
+    pub fn get(&self, condition: &str) -> Option<&String> {
+        self.commands.get(condition).filter(|f| !f.is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.commands.iter()
+    }
+}
+
+/// Lets the shell notice its terminal closing, so it can hang up its jobs
+/// before leaving instead of just dying.
+pub fn catch_hangup() {
+    unsafe { libc::signal(libc::SIGHUP, default_handler(libc::SIGHUP)) };
+}
+
+fn default_handler(signal: libc::c_int) -> libc::sighandler_t {
+    match signal {
+        libc::SIGHUP => record_signal as *const () as libc::sighandler_t,
+        signal => jobs::default_handler(signal),
+    }
+}
+
+/// Takes the names of the signals that arrived since the last call.
+pub fn take_pending() -> Vec<&'static str> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    jobs::SIGNALS
+        .iter()
+        .filter(|f| pending & (1 << f.1) != 0)
+        .map(|f| f.0)
+        .collect()
+}
+
+pub fn has_pending() -> bool {
+    PENDING.load(Ordering::SeqCst) != 0
+}
+
+/// Accepts `EXIT`, `0`, and anything `kill` does, returning the canonical
+/// name.
+fn normalize(condition: &str) -> Result<String, String> {
+    if condition.eq_ignore_ascii_case("EXIT") || condition == "0" {
+        return Ok("EXIT".to_string());
+    }
+    jobs::parse_signal(condition)
+        .and_then(jobs::signal_name)
+        .map(|f| f.to_string())
+        .ok_or_else(|| format!("trap: {}: invalid signal specification", condition))
+}
@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Condvar, Mutex, Once, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::control::{Reply, Request};
+use crate::git::GitStatus;
+use crate::plugin::Plugins;
+use crate::suggestion::DiskIndex;
+
+/// How long a worker thread waits for another task before it exits.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// What background work hands back to the main loop, which takes it in
+/// between keys.
+pub enum Message {
+    /// The index of the history file, opened for suggestions.
+    HistoryIndexed(DiskIndex),
+    /// History lines the disk index has for a prefix, for suggestions.
+    HistorySearched(String, Vec<String>),
+    /// The plugins, once they have described themselves.
+    PluginsLoaded(Plugins),
+    /// The git status of a directory, for the prompt.
+    GitStatus(PathBuf, Option<GitStatus>),
+    /// The name of the tmux session, for the prompt.
+    TmuxSession(Option<String>),
+    /// A timer set with `set_timer` went off.
+    Timer(Timer),
+    /// A request from the control socket, to be answered on the sender.
+    Control(Request, Reply),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    /// The prompt has waited long enough for the `on_idle` hook.
+    Idle,
+}
+
+#[derive(Default)]
+struct Queue {
+    tasks: VecDeque<Task>,
+    /// Workers waiting for a task.
+    idle: usize,
+}
+
+/// The threads everything in the background shares: workers started as
+/// tasks need them and kept for a while after, one thread for timers, and
+/// the channel their messages come back on. A byte on `wake` tells the
+/// main loop a message is waiting.
+struct Runtime {
+    queue: Mutex<Queue>,
+    queued: Condvar,
+    timers: Mutex<Vec<(Timer, Instant)>>,
+    timers_changed: Condvar,
+    timer_thread: Once,
+    sender: Sender<Message>,
+    receiver: Mutex<Receiver<Message>>,
+    wake: (PipeReader, PipeWriter),
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        let wake = io::pipe().expect("can't create the worker pipe");
+        // Neither end may block: a full pipe already wakes the main loop.
+        for fd in [wake.0.as_raw_fd(), wake.1.as_raw_fd()] {
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            }
+        }
+        Runtime {
+            queue: Mutex::default(),
+            queued: Condvar::new(),
+            timers: Mutex::default(),
+            timers_changed: Condvar::new(),
+            timer_thread: Once::new(),
+            sender,
+            receiver: Mutex::new(receiver),
+            wake,
+        }
+    })
+}
+
+/// Runs `task` on a worker thread, starting one when all are busy so a
+/// slow task never holds up another.
+pub fn spawn(task: impl FnOnce() + Send + 'static) {
+    let runtime = runtime();
+    let mut queue = runtime.queue.lock().unwrap();
+    queue.tasks.push_back(Box::new(task));
+    if queue.tasks.len() > queue.idle {
+        thread::spawn(|| work(runtime));
+    } else {
+        runtime.queued.notify_one();
+    }
+}
+
+fn work(runtime: &Runtime) {
+    let mut queue = runtime.queue.lock().unwrap();
+    loop {
+        if let Some(task) = queue.tasks.pop_front() {
+            drop(queue);
+            task();
+            queue = runtime.queue.lock().unwrap();
+            continue;
+        }
+        queue.idle += 1;
+        let (woken, timeout) = runtime.queued.wait_timeout(queue, KEEP_ALIVE).unwrap();
+        queue = woken;
+        queue.idle -= 1;
+        if timeout.timed_out() && queue.tasks.is_empty() {
+            return;
+        }
+    }
+}
+
+/// Hands `message` to the main loop.
+pub fn send(message: Message) {
+    let runtime = runtime();
+    let _ = runtime.sender.send(message);
+    let _ = (&runtime.wake.1).write(&[0]);
+}
+
+/// The messages sent since the last call.
+pub fn messages() -> Vec<Message> {
+    let runtime = runtime();
+    let mut buffer = [0; 64];
+    while matches!((&runtime.wake.0).read(&mut buffer), Ok(1..)) {}
+    runtime.receiver.lock().unwrap().try_iter().collect()
+}
+
+/// Becomes readable when a message is waiting, for the main loop to wait
+/// on along with the terminal.
+pub fn wake_fd() -> RawFd {
+    runtime().wake.0.as_raw_fd()
+}
+
+/// Sends `Message::Timer(timer)` after `delay`, replacing the time the
+/// timer was set to go off before.
+pub fn set_timer(timer: Timer, delay: Duration) {
+    let runtime = runtime();
+    runtime
+        .timer_thread
+        .call_once(|| drop(thread::spawn(|| run_timers(runtime))));
+    let mut timers = runtime.timers.lock().unwrap();
+    timers.retain(|f| f.0 != timer);
+    timers.push((timer, Instant::now() + delay));
+    runtime.timers_changed.notify_one();
+}
+
+pub fn cancel_timer(timer: Timer) {
+    let runtime = runtime();
+    runtime.timers.lock().unwrap().retain(|f| f.0 != timer);
+    runtime.timers_changed.notify_one();
+}
+
+fn run_timers(runtime: &Runtime) {
+    let mut timers = runtime.timers.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        timers.retain(|(timer, at)| {
+            if *at > now {
+                return true;
+            }
+            send(Message::Timer(*timer));
+            false
+        });
+        timers = match timers.iter().map(|f| f.1 - now).min() {
+            Some(wait) => runtime.timers_changed.wait_timeout(timers, wait).unwrap().0,
+            None => runtime.timers_changed.wait(timers).unwrap(),
+        };
+    }
+}
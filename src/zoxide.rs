@@ -0,0 +1,45 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::autocomplete::run_with_timeout;
+use crate::worker;
+
+/// How long a query may take before `j` gives up on it.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Tells zoxide the cwd changed to `dir`, in the background so `cd` never
+/// waits on it.
+pub fn add(dir: &Path) {
+    let mut command = Command::new("zoxide");
+    command
+        .arg("add")
+        .arg("--")
+        .arg(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    worker::spawn(move || {
+        let _ = command.status();
+    });
+}
+
+/// The directories in zoxide's database matching `words`, best first,
+/// with their scores.
+pub fn matches(words: &[String]) -> Vec<(f64, PathBuf)> {
+    let mut command = Command::new("zoxide");
+    command
+        .args(["query", "--list", "--score", "--"])
+        .args(words);
+    let Ok(output) = run_with_timeout(command, QUERY_TIMEOUT) else {
+        return vec![];
+    };
+    // Lines look like `  12.5 /home/me/code`.
+    output
+        .lines()
+        .filter_map(|line| {
+            let (score, dir) = line.trim_start().split_once(' ')?;
+            Some((score.parse().ok()?, PathBuf::from(dir)))
+        })
+        .collect()
+}
@@ -0,0 +1,62 @@
+//! Editing by grapheme: the cursor never lands inside a character made of
+//! several code points, and columns count how wide text is on screen.
+use a_shell::buffer::EditBuffer;
+
+#[test]
+fn moves_and_deletes_whole_graphemes() {
+    // An `e` with a combining acute accent, then a flag of two code points.
+    let mut buffer = EditBuffer::new("ae\u{301}🇳🇵");
+    assert!(buffer.move_left());
+    assert_eq!(buffer.cursor(), "ae\u{301}".len());
+    assert!(buffer.delete_backward());
+    assert_eq!(buffer.as_str(), "a🇳🇵");
+    assert!(buffer.delete_forward());
+    assert_eq!(buffer.as_str(), "a");
+    assert!(!buffer.delete_forward());
+}
+
+#[test]
+fn a_combining_mark_joins_the_character_before_it() {
+    let mut buffer = EditBuffer::new("e");
+    buffer.insert('\u{301}');
+    assert_eq!(buffer.cursor(), "e\u{301}".len());
+    assert!(buffer.move_left());
+    assert_eq!(buffer.cursor(), 0);
+}
+
+#[test]
+fn counts_columns_rather_than_characters() {
+    let mut buffer = EditBuffer::new("ls 日本");
+    assert_eq!(buffer.width(), 7);
+    buffer.move_left();
+    assert_eq!(buffer.cursor_column(), 5);
+    assert_eq!(EditBuffer::new("e\u{301}").width(), 1);
+}
+
+#[test]
+fn moves_by_shell_words() {
+    let mut buffer = EditBuffer::new("git commit  -m");
+    assert!(buffer.move_word_left());
+    assert_eq!(buffer.cursor(), "git commit  ".len());
+    buffer.move_word_left();
+    assert_eq!(buffer.cursor(), "git ".len());
+    buffer.move_word_right();
+    assert_eq!(buffer.cursor(), "git commit".len());
+    assert!(buffer.delete_word_backward());
+    assert_eq!(buffer.as_str(), "git   -m");
+    buffer.move_to_start();
+    assert!(!buffer.move_word_left());
+}
+
+#[test]
+fn inserts_at_the_cursor() {
+    let mut buffer = EditBuffer::new("echo ");
+    buffer.move_to_start();
+    buffer.insert_str("time ");
+    assert_eq!(buffer.as_str(), "time echo ");
+    assert_eq!(buffer.cursor(), 5);
+    assert!(buffer.move_to_end());
+    assert!(buffer.at_end());
+    assert_eq!(buffer.take(), "time echo ");
+    assert!(buffer.is_empty());
+}
@@ -0,0 +1,399 @@
+//! Runs the shell on a pseudo-terminal and keeps a model of the screen it
+//! draws, so tests can type keys and look at what a user would see.
+#![allow(dead_code)]
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const ROWS: usize = 24;
+const COLUMNS: usize = 80;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The prompt every session uses, so screens look the same for any user.
+pub const PROMPT: &str = "ash> ";
+
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A home directory of its own, removed when dropped.
+pub struct Home {
+    pub path: PathBuf,
+}
+
+impl Home {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "ash-test-{}-{}",
+            std::process::id(),
+            SESSIONS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(path.join(".config/ash")).unwrap();
+        let home = Home { path };
+        home.write_config("");
+        home
+    }
+
+    /// Writes the config file: the fixed prompt followed by `config`.
+    pub fn write_config(&self, config: &str) {
+        let config = format!("[prompt]\nformat = \"{}\"\n{}", PROMPT, config);
+        fs::write(self.path.join(".config/ash/config.toml"), config).unwrap();
+    }
+}
+
+impl Drop for Home {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The shell running interactively on a pseudo-terminal.
+pub struct Session {
+    pub home: Home,
+    master: File,
+    child: Child,
+    screen: Screen,
+    /// Everything the shell wrote, escapes included.
+    output: Vec<u8>,
+}
+
+impl Session {
+    pub fn start() -> Self {
+        Session::start_in(Home::new())
+    }
+
+    /// Starts the shell in `home`, which is also its cwd, and waits for its
+    /// first prompt.
+    pub fn start_in(home: Home) -> Self {
+        Session::start_with(home, "xterm-256color")
+    }
+
+    /// Starts the shell on a terminal that says it is a `term`.
+    pub fn start_with(home: Home, term: &str) -> Self {
+        let (mut master, mut slave) = (0, 0);
+        let size = libc::winsize {
+            ws_row: ROWS as u16,
+            ws_col: COLUMNS as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let opened = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        assert_eq!(opened, 0, "openpty failed");
+        let master = unsafe { File::from_raw_fd(master) };
+        let slave = unsafe { File::from_raw_fd(slave) };
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_a-shell"));
+        command
+            .current_dir(&home.path)
+            .env_clear()
+            .env("HOME", &home.path)
+            .env("USER", "tester")
+            .env("TERM", term)
+            .env("PATH", std::env::var("PATH").unwrap_or_default())
+            .stdin(Stdio::from(slave.try_clone().unwrap()))
+            .stdout(Stdio::from(slave.try_clone().unwrap()))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            command.pre_exec(|| {
+                // A session of its own with the pty as its controlling
+                // terminal, as a terminal emulator would set it up.
+                libc::setsid();
+                libc::ioctl(0, libc::TIOCSCTTY, 0);
+                Ok(())
+            });
+        }
+        let child = command.spawn().expect("failed to start the shell");
+        let mut session = Session {
+            home,
+            master,
+            child,
+            screen: Screen::new(),
+            output: vec![],
+        };
+        session.wait_for(PROMPT.trim_end());
+        session
+    }
+
+    /// Types `keys`, which may contain escape sequences like `\x1b[D`.
+    pub fn send(&mut self, keys: &str) {
+        self.master.write_all(keys.as_bytes()).unwrap();
+        // Give the editor a moment so keys aren't read as one paste.
+        self.pump(Duration::from_millis(50));
+    }
+
+    /// Types `line` and presses Enter.
+    pub fn run(&mut self, line: &str) {
+        self.send(line);
+        self.send("\r");
+    }
+
+    /// Reads output until the screen shows `text`, failing the test with
+    /// the screen when it doesn't in time.
+    pub fn wait_for(&mut self, text: &str) {
+        let start = Instant::now();
+        while !self.screen.text().contains(text) {
+            if start.elapsed() > TIMEOUT {
+                panic!(
+                    "timed out waiting for {:?}, screen:\n{}",
+                    text,
+                    self.screen.text()
+                );
+            }
+            self.pump(Duration::from_millis(50));
+        }
+    }
+
+    /// The line the cursor is on, without trailing blanks.
+    pub fn current_line(&mut self) -> String {
+        self.pump(Duration::from_millis(200));
+        self.screen.line(self.screen.row)
+    }
+
+    pub fn screen(&mut self) -> String {
+        self.pump(Duration::from_millis(200));
+        self.screen.text()
+    }
+
+    pub fn output(&mut self) -> String {
+        self.pump(Duration::from_millis(200));
+        String::from_utf8_lossy(&self.output).to_string()
+    }
+
+    /// Sends SIGHUP, as a terminal closing would.
+    pub fn hang_up(&mut self) {
+        unsafe { libc::kill(self.child.id() as libc::pid_t, libc::SIGHUP) };
+    }
+
+    /// Waits for the shell to exit and returns its status.
+    pub fn exit_status(&mut self) -> i32 {
+        let start = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status.code().unwrap_or(-1);
+            }
+            if start.elapsed() > TIMEOUT {
+                panic!("shell didn't exit, screen:\n{}", self.screen.text());
+            }
+            self.pump(Duration::from_millis(50));
+        }
+    }
+
+    /// Feeds whatever the shell writes within `wait` into the screen,
+    /// answering cursor position queries.
+    fn pump(&mut self, wait: Duration) {
+        let deadline = Instant::now() + wait;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut poll = libc::pollfd {
+                fd: self.master.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut poll, 1, left.as_millis() as i32) };
+            if ready <= 0 {
+                return;
+            }
+            let mut buffer = [0; 4096];
+            let Ok(read) = self.master.read(&mut buffer) else {
+                return;
+            };
+            if read == 0 {
+                return;
+            }
+            self.output.extend_from_slice(&buffer[..read]);
+            for reply in self.screen.feed(&buffer[..read]) {
+                self.master.write_all(reply.as_bytes()).unwrap();
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Runs `ash -c script` in `cwd` without a terminal.
+pub fn run_script(script: &str, cwd: &Path) -> (i32, String, String) {
+    let home = Home::new();
+    let output = Command::new(env!("CARGO_BIN_EXE_a-shell"))
+        .arg("-c")
+        .arg(script)
+        .current_dir(cwd)
+        .env("HOME", &home.path)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_STATE_HOME")
+        .env_remove("XDG_CACHE_HOME")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+/// Just enough of a VT100 to follow the shell's output: text, cursor
+/// movement, erasing, and the sequences it ignores.
+struct Screen {
+    cells: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+    saved: (usize, usize),
+    pending: Vec<u8>,
+}
+
+impl Screen {
+    fn new() -> Self {
+        Screen {
+            cells: vec![vec![' '; COLUMNS]; ROWS],
+            row: 0,
+            column: 0,
+            saved: (0, 0),
+            pending: vec![],
+        }
+    }
+
+    fn text(&self) -> String {
+        let lines = (0..ROWS).map(|f| self.line(f)).collect::<Vec<_>>();
+        lines.join("\n").trim_end().to_string()
+    }
+
+    fn line(&self, row: usize) -> String {
+        self.cells[row]
+            .iter()
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    /// Applies `bytes`, returning the replies the terminal would send.
+    fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        let text = String::from_utf8(self.pending.drain(..valid).collect()).unwrap();
+        let mut replies = vec![];
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => match chars.next() {
+                    Some('[') => {
+                        let mut params = String::new();
+                        let mut last = None;
+                        for c in chars.by_ref() {
+                            if ('\x40'..='\x7e').contains(&c) {
+                                last = Some(c);
+                                break;
+                            }
+                            params.push(c);
+                        }
+                        if let Some(reply) = self.csi(&params, last.unwrap_or(' ')) {
+                            replies.push(reply);
+                        }
+                    }
+                    Some(']') => {
+                        // Titles and marks end with BEL or ST.
+                        while let Some(c) = chars.next() {
+                            if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                                break;
+                            }
+                        }
+                    }
+                    Some('7') => self.saved = (self.row, self.column),
+                    Some('8') => (self.row, self.column) = self.saved,
+                    _ => {}
+                },
+                '\r' => self.column = 0,
+                '\n' => self.line_feed(),
+                '\x08' => self.column = self.column.saturating_sub(1),
+                '\x07' => {}
+                c => {
+                    if self.column >= COLUMNS {
+                        self.column = 0;
+                        self.line_feed();
+                    }
+                    self.cells[self.row][self.column] = c;
+                    self.column += 1;
+                }
+            }
+        }
+        replies
+    }
+
+    fn csi(&mut self, params: &str, last: char) -> Option<String> {
+        let numbers = params
+            .trim_start_matches('?')
+            .split(';')
+            .map(|f| f.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        let first = numbers.first().copied().flatten();
+        let count = first.unwrap_or(1).max(1);
+        match last {
+            'A' => self.row = self.row.saturating_sub(count),
+            'B' => self.row = (self.row + count).min(ROWS - 1),
+            'C' => self.column = (self.column + count).min(COLUMNS - 1),
+            'D' => self.column = self.column.saturating_sub(count),
+            'G' => self.column = (count - 1).min(COLUMNS - 1),
+            'H' | 'f' => {
+                self.row = (count - 1).min(ROWS - 1);
+                let column = numbers.get(1).copied().flatten().unwrap_or(1).max(1);
+                self.column = (column - 1).min(COLUMNS - 1);
+            }
+            'K' => {
+                let columns = match first.unwrap_or(0) {
+                    0 => self.column..COLUMNS,
+                    1 => 0..self.column + 1,
+                    _ => 0..COLUMNS,
+                };
+                for column in columns.filter(|f| *f < COLUMNS) {
+                    self.cells[self.row][column] = ' ';
+                }
+            }
+            'J' => match first.unwrap_or(0) {
+                0 => {
+                    self.cells[self.row][self.column.min(COLUMNS)..].fill(' ');
+                    for row in &mut self.cells[self.row + 1..] {
+                        row.fill(' ');
+                    }
+                }
+                _ => {
+                    for row in &mut self.cells {
+                        row.fill(' ');
+                    }
+                }
+            },
+            'n' if first == Some(6) => {
+                return Some(format!("\x1b[{};{}R", self.row + 1, self.column + 1));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 < ROWS {
+            self.row += 1;
+        } else {
+            self.cells.remove(0);
+            self.cells.push(vec![' '; COLUMNS]);
+        }
+    }
+}
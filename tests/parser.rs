@@ -0,0 +1,93 @@
+//! Properties of the parser over arbitrary input, since whatever a user
+//! types or a script contains reaches it unchecked.
+use a_shell::parser::{quote, CommandParser};
+use proptest::prelude::*;
+
+/// Lines made of the characters the parser treats specially, which hit
+/// its edge cases far more often than arbitrary text.
+fn shell_line() -> impl Strategy<Value = String> {
+    "[a-z~$'\"\\\\ |&\n]{0,40}"
+}
+
+fn any_line() -> impl Strategy<Value = String> {
+    prop_oneof![any::<String>(), shell_line()]
+}
+
+/// The words of `command` quoted back into a line that parses to them.
+fn requote(command: &str, args: &[String]) -> String {
+    if command.is_empty() && args.is_empty() {
+        return String::new();
+    }
+    std::iter::once(command)
+        .chain(args.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+proptest! {
+    #[test]
+    fn never_panics(line in any_line(), lines in prop::collection::vec(any_line(), 0..4)) {
+        let parser = CommandParser::new();
+        parser.parse(&line);
+        parser.syntax(&line);
+        parser.is_incomplete(&line);
+        parser.join_lines(&lines);
+    }
+
+    #[test]
+    fn quoted_words_round_trip(words in prop::collection::vec(any_line(), 0..6)) {
+        let parser = CommandParser::new();
+        let line = requote("printf", &words);
+        prop_assert!(!parser.is_incomplete(&line), "{:?} reads as incomplete", line);
+        let parsed = parser.parse(&line);
+        prop_assert_eq!(parsed.command, "printf");
+        prop_assert_eq!(parsed.args, words);
+    }
+
+    #[test]
+    fn parsing_is_stable(line in any_line()) {
+        let parser = CommandParser::new();
+        let parsed = parser.parse(&line);
+        prop_assert_eq!(&parser.parse(&line), &parsed);
+        // What was parsed reads the same once quoted, so expanded words
+        // aren't expanded again.
+        let requoted = requote(&parsed.command, &parsed.args);
+        prop_assert_eq!(&parser.parse(&requoted), &parsed, "requoted as {:?}", requoted);
+    }
+}
+
+#[test]
+fn escapes_and_quotes() {
+    let parser = CommandParser::new();
+    let parsed = parser.parse(r#"echo a\ b 'it'\''s' "say \"hi\"" '$HOME' \~ "C:\dir""#);
+    assert_eq!(
+        parsed.args,
+        ["a b", "it's", "say \"hi\"", "$HOME", "~", "C:\\dir"]
+    );
+    assert!(!parser.is_incomplete(r"echo \'"));
+    assert!(parser.is_incomplete(r#"echo "\""#));
+}
+
+#[test]
+fn describes_pipelines_and_redirections() {
+    let parser = CommandParser::new();
+    let syntax = parser.syntax("printf '%s\\n' a >> out | sort && ls &");
+    assert!(syntax.is_valid());
+    let json = serde_json::to_value(&syntax).unwrap();
+    assert_eq!(json["background"], true);
+    let first = &json["pipelines"][0];
+    assert_eq!(first[0]["args"], serde_json::json!(["%s\\n", "a"]));
+    assert_eq!(first[0]["words"][1]["quoting"], "single");
+    assert_eq!(first[0]["redirection"]["target"], "out");
+    assert_eq!(first[0]["redirection"]["append"], true);
+    assert_eq!(first[1]["command"], "sort");
+    assert_eq!(json["pipelines"][1][0]["command"], "ls");
+    assert!(!parser.syntax("ls >").is_valid());
+
+    let quoted = serde_json::to_value(parser.syntax("echo '>x' hi")).unwrap();
+    let echo = &quoted["pipelines"][0][0];
+    assert_eq!(echo["words"][1]["quoting"], "single");
+    assert_eq!(echo["args"], serde_json::json!([">x", "hi"]));
+    assert!(echo["redirection"].is_null());
+}
@@ -0,0 +1,497 @@
+mod common;
+
+use common::{run_script, Home, Session, PROMPT};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+
+const LEFT: &str = "\x1b[D";
+const UP: &str = "\x1b[A";
+const DOWN: &str = "\x1b[B";
+const BACKSPACE: &str = "\x7f";
+
+#[test]
+fn runs_a_command() {
+    let mut session = Session::start();
+    session.run("echo hello");
+    session.wait_for("\nhello\n");
+}
+
+#[test]
+fn edits_in_the_middle_of_the_line() {
+    let mut session = Session::start();
+    session.send("echo héllo");
+    session.send(&LEFT.repeat(3));
+    session.send("X");
+    assert_eq!(session.current_line(), format!("{}echo héXllo", PROMPT));
+    session.send(BACKSPACE);
+    session.send(BACKSPACE);
+    session.send("Y");
+    assert_eq!(session.current_line(), format!("{}echo hYllo", PROMPT));
+    session.send("\r");
+    session.wait_for("\nhYllo\n");
+}
+
+#[test]
+fn recalls_history() {
+    let mut session = Session::start();
+    session.run("echo first");
+    session.wait_for("\nfirst\n");
+    session.send(UP);
+    assert_eq!(session.current_line(), format!("{}echo first", PROMPT));
+}
+
+#[test]
+fn comes_back_to_the_typed_line_after_browsing_history() {
+    let mut session = Session::start();
+    session.run("echo one");
+    session.run("echo two");
+    session.wait_for("\ntwo\n");
+    session.send("zzq");
+    session.send(UP);
+    session.send(UP);
+    assert_eq!(session.current_line(), format!("{}echo one", PROMPT));
+    session.send(DOWN);
+    assert_eq!(session.current_line(), format!("{}echo two", PROMPT));
+    session.send(DOWN);
+    assert_eq!(session.current_line(), format!("{}zzq", PROMPT));
+}
+
+#[test]
+fn completes_a_unique_file() {
+    let mut session = Session::start();
+    fs::write(session.home.path.join("unique-name.txt"), "").unwrap();
+    session.send("cat uniq\t");
+    assert_eq!(
+        session.current_line(),
+        format!("{}cat unique-name.txt", PROMPT)
+    );
+}
+
+#[test]
+fn quotes_the_files_a_glob_expands_to() {
+    let mut session = Session::start();
+    fs::write(session.home.path.join("it's $x"), "").unwrap();
+    fs::write(session.home.path.join("ix"), "").unwrap();
+    session.send("cat i*\t");
+    assert_eq!(
+        session.current_line(),
+        format!("{}cat 'it'\\''s $x' 'ix'", PROMPT)
+    );
+}
+
+#[test]
+fn lists_ambiguous_completions() {
+    let mut session = Session::start();
+    fs::write(session.home.path.join("alpha-one"), "").unwrap();
+    fs::write(session.home.path.join("alpha-two"), "").unwrap();
+    session.send("cat alpha-\t");
+    session.wait_for("alpha-one  alpha-two");
+}
+
+#[test]
+fn completes_branch_names_containing_a_slash() {
+    let home = Home::new();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=a", "-c", "user.email=a@a"])
+            .args(args)
+            .current_dir(&home.path)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "start"]);
+    git(&["branch", "feature/login"]);
+    let mut session = Session::start_in(home);
+    session.send("git checkout feature/\t");
+    assert_eq!(
+        session.current_line(),
+        format!("{}git checkout feature/login", PROMPT)
+    );
+}
+
+#[test]
+fn runs_pipelines() {
+    let mut session = Session::start();
+    session.run("echo piped | tr a-z A-Z");
+    session.wait_for("\nPIPED\n");
+}
+
+#[test]
+fn exits_with_the_given_status() {
+    let mut session = Session::start();
+    session.run("exit 3");
+    assert_eq!(session.exit_status(), 3);
+}
+
+#[test]
+fn ends_a_line_at_a_trailing_semicolon() {
+    let (status, stdout, _) = run_script("echo a\\;\nexit 3;", &std::env::temp_dir());
+    assert_eq!(status, 3);
+    assert_eq!(stdout, "a;\n");
+}
+
+/// Sets up an fzf that picks the first candidate containing the query.
+fn fake_fzf(home: &Home) {
+    let fzf = home.path.join("fzf");
+    let script = "#!/bin/sh\n\
+        while [ $# -gt 1 ]; do [ \"$1\" = --query ] && query=$2; shift; done\n\
+        grep -F -m1 -- \"$query\"\n";
+    fs::write(&fzf, script).unwrap();
+    fs::set_permissions(&fzf, fs::Permissions::from_mode(0o755)).unwrap();
+    home.write_config(&format!("[fzf]\ncommand = {:?}\n", fzf));
+}
+
+#[test]
+fn picks_history_and_files_with_fzf() {
+    let home = Home::new();
+    fake_fzf(&home);
+    fs::create_dir(home.path.join("notes")).unwrap();
+    fs::write(home.path.join("notes/to do.txt"), "").unwrap();
+    let mut session = Session::start_in(home);
+    session.run("echo alpha");
+    session.run("echo beta");
+    session.wait_for("\nbeta\n");
+    session.send("alp\x12");
+    assert_eq!(session.current_line(), format!("{}echo alpha", PROMPT));
+    session.send("\x03");
+    session.send("cat do\x14");
+    assert_eq!(
+        session.current_line(),
+        format!("{}cat 'notes/to do.txt'", PROMPT)
+    );
+}
+
+#[test]
+fn keeps_zoxide_up_to_date_and_jumps_with_it() {
+    let home = Home::new();
+    let bin = home.path.join("bin");
+    fs::create_dir_all(home.path.join("code/project")).unwrap();
+    fs::create_dir(&bin).unwrap();
+    let script = format!(
+        "#!/bin/sh\n\
+        echo \"$@\" >> {0}/zoxide.log\n\
+        [ \"$1\" = query ] && echo '  9.0 {0}/code/project'\n\
+        exit 0\n",
+        home.path.display()
+    );
+    fs::write(bin.join("zoxide"), script).unwrap();
+    fs::set_permissions(bin.join("zoxide"), fs::Permissions::from_mode(0o755)).unwrap();
+    let mut session = Session::start_in(home);
+    session.run(&format!("export PATH={}:$PATH", bin.display()));
+    session.run("cd code");
+    session.run("z proj");
+    session.run("pwd");
+    session.wait_for("code/project\n");
+    let log = fs::read_to_string(session.home.path.join("zoxide.log")).unwrap();
+    assert!(log.contains("add -- "), "{}", log);
+    assert!(log.contains("query --list --score -- proj"), "{}", log);
+}
+
+#[test]
+fn confirms_commands_matching_a_pattern_however_their_options_are_grouped() {
+    let home = Home::new();
+    home.write_config("[confirm]\nenabled = true\npatterns = [\"ls -ad ~\"]\n");
+    let mut session = Session::start_in(home);
+    session.run("ls -d -a ~/");
+    session.wait_for("`ls -d -a ");
+    session.send("n");
+    session.run("ls -da / ~");
+    session.wait_for("`ls -da / ");
+    session.send("n");
+    session.run("ls -d ~");
+    session.run("ls -ad /");
+    session.run("echo done");
+    session.wait_for("\ndone\n");
+    assert_eq!(
+        session.screen().matches("looks destructive").count(),
+        2,
+        "{}",
+        session.screen()
+    );
+}
+
+#[test]
+fn matches_confirm_patterns_with_many_stars_in_linear_time() {
+    let stars = "*a".repeat(12);
+    let home = Home::new();
+    home.write_config(&format!(
+        "[confirm]\nenabled = true\npatterns = [\"ls {0}b\", \"env {0}\"]\n",
+        stars
+    ));
+    let mut session = Session::start_in(home);
+    let long = "a".repeat(50);
+    session.run(&format!("ls {}", long));
+    session.run("echo done");
+    session.wait_for("\ndone\n");
+    assert!(!session.screen().contains("looks destructive"));
+    session.run(&format!("env {}", long));
+    session.wait_for("looks destructive");
+}
+
+#[test]
+fn asks_direnv_before_every_prompt() {
+    let home = Home::new();
+    let bin = home.path.join("bin");
+    fs::create_dir(&bin).unwrap();
+    let direnv = bin.join("direnv");
+    let script = format!(
+        "#!/bin/sh\n\
+        echo x >> {0}/direnv.log\n\
+        printf '{{\"DIRENV_CALLS\": \"%s\"}}' $(wc -l < {0}/direnv.log)\n",
+        home.path.display()
+    );
+    fs::write(&direnv, script).unwrap();
+    fs::set_permissions(&direnv, fs::Permissions::from_mode(0o755)).unwrap();
+    home.write_config(&format!("[env]\nPATH = {{ prepend = [{:?}] }}\n", bin));
+    let mut session = Session::start_in(home);
+    session.run("printenv DIRENV_CALLS");
+    session.wait_for("\n1\n");
+    session.run("printenv DIRENV_CALLS");
+    session.wait_for("\n2\n");
+}
+
+#[test]
+fn suggests_from_the_history_index_and_adds_new_lines_to_it() {
+    let home = Home::new();
+    let state = home.path.join(".local/state/ash");
+    let cache = home.path.join(".cache/ash");
+    fs::create_dir_all(&state).unwrap();
+    fs::create_dir_all(&cache).unwrap();
+    // An index of the oldest line, and a line run since in front of it.
+    let history = fs::canonicalize(&state).unwrap().join("history");
+    let index = cache.join(format!(
+        "{}.index",
+        history.display().to_string().replace('/', "%2F")
+    ));
+    fs::write(&history, "echo added\necho ancient\n").unwrap();
+    fs::write(&index, "# 1 13\necho ancient\t0\n").unwrap();
+    home.write_config("[history]\npreload = 0\n");
+    let mut session = Session::start_in(home);
+    session.send("echo ad");
+    session.wait_for("echo added");
+    session.send("\x03");
+    session.send("echo anc");
+    session.wait_for("echo ancient");
+    assert_eq!(
+        fs::read_to_string(&index).unwrap(),
+        "# 2 24\necho added\t1\necho ancient\t0\n"
+    );
+}
+
+#[test]
+fn help_lists_the_keys_as_bound() {
+    let home = Home::new();
+    home.write_config("[keys]\n\"ctrl-y\" = \"copy-line\"\n\"ctrl-r\" = \"clear-screen\"\n");
+    let mut session = Session::start_in(home);
+    session.run("help");
+    session.wait_for("to bind in [keys]: accept-suggestion, fuzzy-history\n");
+    let screen = session.screen();
+    assert!(screen.contains("ctrl-y    Copy the line"), "{}", screen);
+    assert!(screen.contains("ctrl-r    Clear the screen"), "{}", screen);
+    assert!(screen.contains("ctrl-t    Pick files"), "{}", screen);
+}
+
+#[test]
+fn copies_the_line_to_the_clipboard() {
+    let home = Home::new();
+    home.write_config("[keys]\n\"ctrl-y\" = \"copy-line\"\n");
+    let mut session = Session::start_in(home);
+    session.send("echo hi\x19");
+    let output = session.output();
+    assert!(
+        output.contains("\x1b]52;c;ZWNobyBoaQ==\x07"),
+        "{:?}",
+        output
+    );
+}
+
+#[test]
+fn copies_piped_input_or_the_last_command_line() {
+    let mut session = Session::start();
+    session.run("echo piped | copy");
+    session.run("copy");
+    let output = session.output();
+    assert!(output.contains("\x1b]52;c;cGlwZWQK\x07"), "{:?}", output);
+    let last = "\x1b]52;c;ZWNobyBwaXBlZCB8IGNvcHk=\x07";
+    assert!(output.contains(last), "{:?}", output);
+}
+
+/// Sends `request` on the control socket and returns the reply.
+fn request(socket: &mut BufReader<UnixStream>, request: &str) -> String {
+    writeln!(socket.get_mut(), "{}", request).unwrap();
+    let mut reply = String::new();
+    socket.read_line(&mut reply).unwrap();
+    reply.trim_end().to_string()
+}
+
+#[test]
+fn takes_requests_on_the_control_socket() {
+    let home = Home::new();
+    home.write_config("[options]\ncontrol_socket = true\n");
+    fs::create_dir(home.path.join("sub")).unwrap();
+    let mut session = Session::start_in(home);
+    let dir = session.home.path.join(".local/state/ash");
+    let path = fs::read_dir(&dir)
+        .unwrap()
+        .map(|f| f.unwrap().path())
+        .find(|f| f.extension().is_some_and(|f| f == "sock"))
+        .expect("no socket");
+    let mut socket = BufReader::new(UnixStream::connect(&path).unwrap());
+    let home = session.home.path.display().to_string();
+    assert_eq!(request(&mut socket, "cwd"), format!("ok {}", home));
+    assert_eq!(request(&mut socket, "cd sub"), "ok");
+    assert_eq!(request(&mut socket, "cwd"), format!("ok {}/sub", home));
+    assert!(request(&mut socket, "cd nowhere").starts_with("error "));
+    assert_eq!(request(&mut socket, "run echo from-socket"), "ok");
+    session.wait_for("\nfrom-socket\n");
+    // An unfinished line the user is typing stays theirs.
+    session.run("echo 'abc");
+    assert_eq!(request(&mut socket, "run echo injected"), "ok");
+    session.wait_for("\ninjected\n");
+    let screen = session.screen();
+    let restored = format!("\ninjected\n{}echo 'abc", PROMPT);
+    assert!(screen.ends_with(&restored), "{}", screen);
+    session.send("\x03");
+    assert_eq!(request(&mut socket, "run false"), "ok");
+    assert_eq!(request(&mut socket, "status"), "ok 1");
+    assert!(request(&mut socket, "dance").starts_with("error "));
+    session.run("exit");
+    session.exit_status();
+    assert!(!path.exists());
+}
+
+#[test]
+fn highlights_lines_with_non_ascii_text() {
+    let home = Home::new();
+    home.write_config("[options]\nhighlighting = true\n");
+    let mut session = Session::start_in(home);
+    session.run("echo café 'naïve' -ü");
+    session.wait_for("\ncafé naïve -ü\n");
+}
+
+#[test]
+fn reads_plain_lines_on_a_dumb_terminal() {
+    let mut session = Session::start_with(Home::new(), "dumb");
+    session.run("echo 'two");
+    session.run("lines'");
+    session.wait_for("\ntwo\nlines\n");
+    let output = session.output();
+    assert!(!output.contains('\x1b'), "{:?}", output);
+}
+
+#[test]
+fn restores_the_line_being_edited_after_a_hang_up() {
+    let mut session = Session::start();
+    session.send("echo unsaved");
+    session.hang_up();
+    assert_eq!(session.exit_status(), 129);
+    let recovery = ".local/state/ash/recovery.json";
+    let saved = fs::read_to_string(session.home.path.join(recovery)).unwrap();
+
+    let home = Home::new();
+    fs::create_dir_all(home.path.join(".local/state/ash")).unwrap();
+    fs::write(home.path.join(recovery), saved).unwrap();
+    let mut session = Session::start_in(home);
+    assert_eq!(session.current_line(), format!("{}echo unsaved", PROMPT));
+    assert!(!session.home.path.join(recovery).exists());
+}
+
+#[test]
+fn reports_unknown_commands_with_127() {
+    let dir = std::env::temp_dir();
+    let (status, _, stderr) = run_script("no-such-command-here", &dir);
+    assert_eq!(status, 127);
+    assert!(stderr.contains("command not found"), "{}", stderr);
+}
+
+#[test]
+fn reports_syntax_errors_with_2() {
+    let dir = std::env::temp_dir();
+    let (status, _, _) = run_script("echo >", &dir);
+    assert_eq!(status, 2);
+}
+
+#[test]
+fn redirects_only_on_an_unquoted_operator() {
+    let home = Home::new();
+    let (status, stdout, _) = run_script("echo '>x' \\>y \">z\" hello >out", &home.path);
+    assert_eq!(status, 0);
+    assert_eq!(
+        fs::read_to_string(home.path.join("out")).unwrap(),
+        ">x >y >z hello\n"
+    );
+    assert!(stdout.is_empty(), "{}", stdout);
+    for name in ["x", "y", "z"] {
+        assert!(!home.path.join(name).exists(), "{} was written", name);
+    }
+}
+
+#[test]
+fn only_the_user_can_read_the_log() {
+    let home = Home::new();
+    let status = Command::new(env!("CARGO_BIN_EXE_a-shell"))
+        .args(["-c", "true"])
+        .env("HOME", &home.path)
+        .env("ASH_LOG", "debug")
+        .env_remove("XDG_STATE_HOME")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let log = fs::metadata(home.path.join(".local/state/ash/ash.log")).unwrap();
+    assert_eq!(log.permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn timeout_takes_its_duration_after_an_equals_sign_or_a_space() {
+    let script = "--timeout=1 sleep 5\necho $?\n--timeout 1 sleep 5\necho $?\n--timeoutx 1 true";
+    let (status, stdout, stderr) = run_script(script, &std::env::temp_dir());
+    assert_eq!(stdout, "124\n124\n");
+    assert_ne!(status, 0);
+    assert!(stderr.contains("--timeoutx"), "{}", stderr);
+}
+
+#[test]
+fn removing_a_trap_in_a_script_restores_the_default() {
+    let script = "trap 'echo caught' INT\ntrap - INT\nsh -c 'kill -INT $PPID'\necho survived";
+    let (status, stdout, _) = run_script(script, &std::env::temp_dir());
+    assert_ne!(status, 0);
+    assert!(!stdout.contains("survived"), "{}", stdout);
+}
+
+#[test]
+fn printf_escapes_write_raw_bytes() {
+    let home = Home::new();
+    let (status, _, _) = run_script("printf '\\0351\\xff\\0777%b' '\\0101' >out", &home.path);
+    assert_eq!(status, 0);
+    assert_eq!(fs::read(home.path.join("out")).unwrap(), b"\xe9\xff\xffA");
+}
+
+#[test]
+fn needs_the_closing_bracket_only_for_brackets() {
+    let dir = std::env::temp_dir();
+    let (status, _, stderr) = run_script("[ -d / ", &dir);
+    assert_eq!(status, 2);
+    assert!(stderr.contains("missing `]`"), "{}", stderr);
+    assert_eq!(run_script("[ -d / ]", &dir).0, 0);
+    assert_eq!(run_script("test a = ]", &dir).0, 1);
+    assert_eq!(run_script("test ] = ]", &dir).0, 0);
+}
+
+#[test]
+fn keeps_options_set_at_runtime_across_a_reload() {
+    let dir = std::env::temp_dir();
+    let script = "set -o pipefail\nreload\nshopt pipefail";
+    let (status, stdout, stderr) = run_script(script, &dir);
+    assert_eq!(status, 0, "{}{}", stdout, stderr);
+    assert!(
+        stdout.contains("pipefail") && stdout.contains("on"),
+        "{}",
+        stdout
+    );
+}